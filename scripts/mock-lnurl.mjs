@@ -0,0 +1,53 @@
+// Minimal LNURL-pay service used by the zap tests.
+//
+//   node scripts/mock-lnurl.mjs
+//
+// Serves http://127.0.0.1:3001/.well-known/lnurlp/<name> and a callback that
+// returns a fake bolt11 invoice carrying the requested amount.
+import http from "node:http";
+
+const PORT = 3001;
+const NOSTR_PUBKEY = "9630f464cca6a5147aa8a35f0bcdd3ce485324e732fd39e09233b1d848238f31";
+
+const send = (res, status, body) => {
+  res.writeHead(status, {
+    "Content-Type": "application/json",
+    "Access-Control-Allow-Origin": "*",
+  });
+  res.end(JSON.stringify(body));
+};
+
+http
+  .createServer((req, res) => {
+    const url = new URL(req.url, `http://127.0.0.1:${PORT}`);
+
+    if (url.pathname.startsWith("/.well-known/lnurlp/")) {
+      return send(res, 200, {
+        tag: "payRequest",
+        callback: `http://127.0.0.1:${PORT}/callback`,
+        minSendable: 1000,
+        maxSendable: 100000000,
+        metadata: '[["text/plain","mock"]]',
+        allowsNostr: true,
+        nostrPubkey: NOSTR_PUBKEY,
+      });
+    }
+
+    if (url.pathname === "/callback") {
+      const amount = Number(url.searchParams.get("amount"));
+      if (!amount || amount % 100 !== 0) {
+        return send(res, 200, { status: "ERROR", reason: "invalid amount" });
+      }
+      try {
+        const zapRequest = JSON.parse(url.searchParams.get("nostr"));
+        if (zapRequest.kind !== 9734) throw new Error("not a zap request");
+      } catch (e) {
+        return send(res, 200, { status: "ERROR", reason: String(e) });
+      }
+      // amount in nano-btc, 1n = 100 msats
+      return send(res, 200, { pr: `lnbc${amount / 100}n1pmockinvoice`, routes: [] });
+    }
+
+    send(res, 404, { status: "ERROR", reason: "not found" });
+  })
+  .listen(PORT, "127.0.0.1", () => console.log(`mock lnurl on :${PORT}`));
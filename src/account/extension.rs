@@ -0,0 +1,137 @@
+use nostr_sdk::nips::nip07::{self, Nip07Signer};
+use nostr_sdk::{Event, NostrSigner, PublicKey, UnsignedEvent};
+use thiserror::Error;
+use wasm_bindgen::JsValue;
+
+use crate::store::user::ExtensionAccount;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No NIP-07 extension found")]
+    NotFound,
+    #[error("The extension rejected the request: {0}")]
+    Rejected(String),
+    #[error("The extension uses another key than this account")]
+    KeyMismatch,
+    #[error(transparent)]
+    Nip07(nip07::Error),
+}
+
+impl From<nip07::Error> for Error {
+    fn from(e: nip07::Error) -> Self {
+        match e {
+            // a rejected promise, either denied by the user or failed in the extension
+            nip07::Error::Wasm(msg) => Error::Rejected(msg),
+            e => Error::Nip07(e),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Whether `window.nostr` is injected
+pub fn is_available() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("nostr"))
+        .map(|nostr| !nostr.is_undefined() && !nostr.is_null())
+        .unwrap_or(false)
+}
+
+/// A NIP-07 signer (Alby, nos2x, ...) that fails with [`Error::NotFound`]
+/// instead of a JS exception once the extension is gone
+#[derive(Debug, Clone)]
+pub struct Extension {
+    inner: Nip07Signer,
+}
+
+impl Extension {
+    pub fn new() -> Result<Self> {
+        if !is_available() {
+            return Err(Error::NotFound);
+        }
+        Ok(Self {
+            inner: Nip07Signer::new()?,
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        if is_available() {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    pub async fn get_public_key(&self) -> Result<PublicKey> {
+        self.check()?;
+        Ok(self.inner.get_public_key().await?)
+    }
+
+    pub async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<Event> {
+        self.check()?;
+        Ok(self.inner.sign_event(unsigned).await?)
+    }
+
+    pub async fn nip04_encrypt(&self, public_key: PublicKey, plaintext: &str) -> Result<String> {
+        self.check()?;
+        Ok(self.inner.nip04_encrypt(public_key, plaintext).await?)
+    }
+
+    pub async fn nip04_decrypt(&self, public_key: PublicKey, ciphertext: &str) -> Result<String> {
+        self.check()?;
+        Ok(self.inner.nip04_decrypt(public_key, ciphertext).await?)
+    }
+
+    pub async fn nip44_encrypt(&self, public_key: PublicKey, plaintext: &str) -> Result<String> {
+        self.check()?;
+        Ok(self.inner.nip44_encrypt(public_key, plaintext).await?)
+    }
+
+    pub async fn nip44_decrypt(&self, public_key: PublicKey, payload: &str) -> Result<String> {
+        self.check()?;
+        Ok(self.inner.nip44_decrypt(public_key, payload).await?)
+    }
+
+    /// The signer taken by the publish functions
+    pub fn signer(self) -> NostrSigner {
+        NostrSigner::nip07(self.inner)
+    }
+}
+
+/// Asks the extension for its key to create an account
+pub async fn login_extension() -> Result<ExtensionAccount> {
+    let pk = Extension::new()?.get_public_key().await?;
+    Ok(ExtensionAccount::new(pk))
+}
+
+/// Signer for a stored extension account, the extension must still use the same key
+pub async fn extension_signer(account: &ExtensionAccount) -> Result<NostrSigner> {
+    let extension = Extension::new()?;
+    if extension.get_public_key().await? != account.pk {
+        return Err(Error::KeyMismatch);
+    }
+    Ok(extension.signer())
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // the test browser runs without extensions
+    #[wasm_bindgen_test]
+    async fn test_missing_extension() {
+        assert!(!is_available());
+        assert!(matches!(Extension::new(), Err(Error::NotFound)));
+        assert!(matches!(login_extension().await, Err(Error::NotFound)));
+
+        let account = ExtensionAccount::new(Keys::generate().public_key());
+        assert!(matches!(
+            extension_signer(&account).await,
+            Err(Error::NotFound)
+        ));
+    }
+}
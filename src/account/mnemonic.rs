@@ -0,0 +1,115 @@
+use aes_gcm::aead::OsRng;
+use nostr_sdk::bip39::Mnemonic;
+use nostr_sdk::nips::nip06::FromMnemonic;
+use nostr_sdk::secp256k1::rand::Rng;
+use nostr_sdk::Keys;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Mnemonic(#[from] nostr_sdk::bip39::Error),
+    #[error(transparent)]
+    Nip06(#[from] nostr_sdk::nips::nip06::Error),
+    #[error("Mnemonic must have 12 or 24 words")]
+    WordCount,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Generates a new BIP-39 English mnemonic of 12 or 24 words
+pub fn generate_mnemonic(words: usize) -> Result<String> {
+    let mnemonic = match words {
+        12 => Mnemonic::from_entropy(&OsRng.gen::<[u8; 16]>())?,
+        24 => Mnemonic::from_entropy(&OsRng.gen::<[u8; 32]>())?,
+        _ => return Err(Error::WordCount),
+    };
+    Ok(mnemonic.to_string())
+}
+
+/// Derives the key at `m/44'/1237'/<account>'/0/0` as NIP-06 specifies
+pub fn derive_keys(mnemonic: &str, passphrase: Option<&str>, account: u32) -> Result<Keys> {
+    // normalizes spacing and validates the checksum
+    let mnemonic = Mnemonic::parse(mnemonic.trim())?.to_string();
+    Ok(Keys::from_mnemonic_with_account(
+        mnemonic,
+        passphrase.filter(|p| !p.is_empty()).map(str::to_string),
+        Some(account),
+    )?)
+}
+
+/// Positions of `count` distinct words the user has to repeat to confirm the backup
+pub fn backup_challenge(mnemonic: &str, count: usize) -> Vec<usize> {
+    let len = mnemonic.split_whitespace().count();
+    let mut positions: Vec<usize> = Vec::new();
+    while positions.len() < count.min(len) {
+        let pos = OsRng.gen_range(0..len);
+        if !positions.contains(&pos) {
+            positions.push(pos);
+        }
+    }
+    positions.sort_unstable();
+    positions
+}
+
+pub fn verify_backup(mnemonic: &str, answers: &[(usize, String)]) -> bool {
+    let words: Vec<&str> = mnemonic.split_whitespace().collect();
+    answers.iter().all(|(pos, answer)| {
+        words
+            .get(*pos)
+            .map_or(false, |word| word.eq_ignore_ascii_case(answer.trim()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // test vector from NIP-06
+    const MNEMONIC: &str =
+        "leader monkey parrot ring guide accident before fence cannon height naive bean";
+
+    #[wasm_bindgen_test]
+    fn test_derive_keys() {
+        let keys = derive_keys(MNEMONIC, None, 0).unwrap();
+        assert_eq!(
+            keys.secret_key().unwrap().display_secret().to_string(),
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a"
+        );
+        let other = derive_keys(MNEMONIC, None, 1).unwrap();
+        assert_ne!(keys.public_key(), other.public_key());
+        let with_passphrase = derive_keys(MNEMONIC, Some("secret"), 0).unwrap();
+        assert_ne!(keys.public_key(), with_passphrase.public_key());
+        assert!(derive_keys("leader monkey parrot", None, 0).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_generate_and_confirm() {
+        let mnemonic = generate_mnemonic(12).unwrap();
+        assert_eq!(mnemonic.split_whitespace().count(), 12);
+        assert!(derive_keys(&mnemonic, None, 0).is_ok());
+        assert_eq!(
+            generate_mnemonic(24).unwrap().split_whitespace().count(),
+            24
+        );
+        assert!(generate_mnemonic(13).is_err());
+
+        let words: Vec<&str> = mnemonic.split_whitespace().collect();
+        let positions = backup_challenge(&mnemonic, 3);
+        assert_eq!(positions.len(), 3);
+        let answers: Vec<(usize, String)> = positions
+            .iter()
+            .map(|pos| (*pos, words[*pos].to_uppercase()))
+            .collect();
+        assert!(verify_backup(&mnemonic, &answers));
+        let wrong: Vec<(usize, String)> = positions
+            .iter()
+            .map(|pos| (*pos, "wrong".to_string()))
+            .collect();
+        assert!(!verify_backup(&mnemonic, &wrong));
+    }
+}
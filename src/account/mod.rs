@@ -1,3 +1,9 @@
+pub mod extension;
+pub mod mnemonic;
+pub mod remote;
+pub mod session;
+
+use std::collections::HashSet;
 use std::str::FromStr;
 
 use aes_gcm::aead::{generic_array::GenericArray, Aead, KeyInit, OsRng};
@@ -5,13 +11,95 @@ use aes_gcm::{Aes256Gcm, Nonce};
 use nostr_sdk::bitcoin::hashes::sha256::Hash as Sha256Hash;
 use nostr_sdk::hashes::Hash;
 use nostr_sdk::key::SecretKey;
+use nostr_sdk::nips::nip49::{EncryptedSecretKey, KeySecurity};
 use nostr_sdk::secp256k1::rand::Rng;
+use nostr_sdk::{FromBech32, ToBech32};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tracing::error;
 use tracing::info;
-use wasm_bindgen_test::console_log;
+
+use crate::store::user::NcryptsecPrivkey;
+use crate::store::AccountType;
+use crate::utils::format::parse_pin;
+
+// scrypt cost recommended by NIP-49
+const NCRYPTSEC_LOG_N: u8 = 16;
+// scrypt only slows guessing down, the passphrase itself has to be hard to guess
+pub const MIN_PASSPHRASE_LEN: usize = 12;
+const MIN_PASSPHRASE_DISTINCT: usize = 5;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Nip49(#[from] nostr_sdk::nips::nip49::Error),
+    #[error(transparent)]
+    Nip19(#[from] nostr_sdk::nips::nip19::Error),
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("This account has no local secret key")]
+    NoSecretKey,
+    #[error("The passphrase needs at least {MIN_PASSPHRASE_LEN} characters, not all the same few")]
+    WeakPassphrase,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Encrypts `sk` as a NIP-49 ncryptsec string
+pub fn encrypt_ncryptsec(sk: &SecretKey, passphrase: &str) -> Result<String> {
+    let encrypted = EncryptedSecretKey::new(sk, passphrase, NCRYPTSEC_LOG_N, KeySecurity::Medium)?;
+    Ok(encrypted.to_bech32()?)
+}
+
+pub fn decrypt_ncryptsec(ncryptsec: &str, passphrase: &str) -> Result<SecretKey> {
+    let encrypted = EncryptedSecretKey::from_bech32(ncryptsec)?;
+    encrypted
+        .to_secret_key(passphrase)
+        .map_err(|_| Error::WrongPassphrase)
+}
+
+/// Refuses a passphrase too weak to protect a key at rest
+pub fn check_passphrase(passphrase: &str) -> Result<()> {
+    let distinct: HashSet<char> = passphrase.chars().collect();
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN || distinct.len() < MIN_PASSPHRASE_DISTINCT {
+        return Err(Error::WeakPassphrase);
+    }
+    Ok(())
+}
+
+/// Decrypts the secret key of `account`, legacy PIN protected records
+/// with the PIN as passphrase
+pub fn unlock(account: &AccountType, passphrase: &str) -> Result<SecretKey> {
+    match account {
+        AccountType::Ncryptsec(key) => decrypt_ncryptsec(&key.ncryptsec, passphrase),
+        AccountType::SecretKey(key) => {
+            let pin = parse_pin(passphrase).ok_or(Error::WrongPassphrase)?;
+            key.encrypted_sk.decrypt(pin).ok_or(Error::WrongPassphrase)
+        }
+        _ => Err(Error::NoSecretKey),
+    }
+}
+
+/// Moves a legacy PIN protected key, unlocked with `pin`, to an ncryptsec
+/// under `passphrase`.
+///
+/// Re-encrypting under the PIN would keep it guessable in 10^4 tries, so
+/// the new passphrase has to pass [`check_passphrase`].
+pub fn upgrade_legacy(account: &AccountType, pin: &str, passphrase: &str) -> Result<AccountType> {
+    check_passphrase(passphrase)?;
+    match account {
+        AccountType::SecretKey(_) => {
+            let sk = unlock(account, pin)?;
+            Ok(AccountType::Ncryptsec(NcryptsecPrivkey::new(
+                encrypt_ncryptsec(&sk, passphrase)?,
+            )))
+        }
+        _ => Err(Error::NoSecretKey),
+    }
+}
 
 // Define a struct to hold the encrypted secret key and the hash of the pin
+// Legacy: new keys are stored as ncryptsec and these records are moved by `upgrade_legacy`
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct EncryptedSK {
     encrypted_sk: Vec<u8>,
@@ -48,20 +136,15 @@ impl EncryptedSK {
         // Verify the pin
         let key = Sha256Hash::hash(&pin);
         let cipher = Aes256Gcm::new(GenericArray::from_slice(key.as_byte_array()));
-        console_log!("Derived Key: {:?}", key.as_byte_array());
-
-        console_log!("Nonce: {:?}", self.nonce);
-        console_log!("Encrypted SK: {:?}", self.encrypted_sk);
 
         match cipher.decrypt(Nonce::from_slice(&self.nonce), &*self.encrypted_sk) {
             Ok(decrypted) => {
-                console_log!("Decrypted Bytes: {:?}", decrypted);
                 // Convert decrypted bytes back to string and then to SecretKey
                 let decrypted_str = String::from_utf8(decrypted).ok()?;
                 SecretKey::from_str(&decrypted_str).ok()
             }
-            Err(err) => {
-                console_log!("Decryption Error: {:?}", err);
+            Err(_) => {
+                error!("Decryption failure");
                 None
             }
         }
@@ -81,6 +164,39 @@ impl EncryptedSK {
     }
 }
 
+// Same scheme as EncryptedSK, used by legacy wallet connections only: new
+// ones keep their secret as ncryptsec, see `crate::nostr::Wallet::seal`
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct EncryptedData {
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+}
+
+impl EncryptedData {
+    #[cfg(test)]
+    pub fn new(plaintext: &str, pin: [u8; 4]) -> Self {
+        let key = Sha256Hash::hash(&pin);
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key.as_byte_array()));
+        let nonce_array = OsRng.gen::<[u8; 12]>();
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce_array), plaintext.as_bytes())
+            .expect("encrypt legacy test data");
+        Self {
+            ciphertext,
+            nonce: nonce_array.to_vec(),
+        }
+    }
+
+    pub fn decrypt(&self, pin: [u8; 4]) -> Option<String> {
+        let key = Sha256Hash::hash(&pin);
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key.as_byte_array()));
+        let decrypted = cipher
+            .decrypt(Nonce::from_slice(&self.nonce), &*self.ciphertext)
+            .ok()?;
+        String::from_utf8(decrypted).ok()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,4 +244,66 @@ mod tests {
 
         assert!(decrypted_sk.is_none());
     }
+
+    #[wasm_bindgen_test]
+    fn test_ncryptsec() {
+        let keys = Keys::generate();
+        let secret_key = keys.secret_key().unwrap();
+
+        let ncryptsec = encrypt_ncryptsec(secret_key, "correct horse battery staple").unwrap();
+        assert!(ncryptsec.starts_with("ncryptsec1"));
+        let decrypted = decrypt_ncryptsec(&ncryptsec, "correct horse battery staple").unwrap();
+        assert_eq!(&decrypted, secret_key);
+        assert!(matches!(
+            decrypt_ncryptsec(&ncryptsec, "wrong"),
+            Err(Error::WrongPassphrase)
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_upgrade_legacy() {
+        let keys = Keys::generate();
+        let secret_key = keys.secret_key().unwrap();
+        let legacy = AccountType::SecretKey(crate::store::user::PinProtectedPrivkey::new(
+            EncryptedSK::new(secret_key, [1, 2, 3, 4]),
+        ));
+
+        assert!(matches!(
+            unlock(&legacy, "4321"),
+            Err(Error::WrongPassphrase)
+        ));
+        assert_eq!(&unlock(&legacy, "1234").unwrap(), secret_key);
+
+        // the PIN itself is no passphrase
+        assert!(matches!(
+            upgrade_legacy(&legacy, "1234", "1234"),
+            Err(Error::WeakPassphrase)
+        ));
+        assert!(matches!(
+            upgrade_legacy(&legacy, "4321", "correct horse battery staple"),
+            Err(Error::WrongPassphrase)
+        ));
+        let upgraded = upgrade_legacy(&legacy, "1234", "correct horse battery staple").unwrap();
+        assert!(matches!(upgraded, AccountType::Ncryptsec(_)));
+        assert!(unlock(&upgraded, "1234").is_err());
+        let sk = unlock(&upgraded, "correct horse battery staple").unwrap();
+        assert_eq!(&sk, secret_key);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_check_passphrase() {
+        assert!(check_passphrase("correct horse battery staple").is_ok());
+        assert!(check_passphrase("short").is_err());
+        assert!(check_passphrase("121212121212121212").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_encrypted_data() {
+        let pin = [1, 2, 3, 4];
+        let secret = "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c";
+
+        let encrypted = EncryptedData::new(secret, pin);
+        assert_eq!(encrypted.decrypt(pin).as_deref(), Some(secret));
+        assert!(encrypted.decrypt([4, 3, 2, 1]).is_none());
+    }
 }
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use nostr_sdk::nips::nip46::NostrConnectURI;
+use nostr_sdk::signer::Nip46Signer;
+use nostr_sdk::{Keys, NostrSigner, Url};
+use thiserror::Error;
+
+use super::{check_passphrase, decrypt_ncryptsec, encrypt_ncryptsec};
+use crate::store::user::RemoteSignerSession;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Uri(#[from] nostr_sdk::nips::nip46::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::nip46::Error),
+    #[error(transparent)]
+    Key(#[from] nostr_sdk::key::Error),
+    #[error(transparent)]
+    Account(#[from] super::Error),
+    #[error("Expected a bunker:// URI")]
+    NotBunker,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// Long enough for the user to approve the request in the bunker
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Starts a session from a bunker:// URI pasted by the user
+pub async fn connect_bunker(
+    uri: &str,
+    passphrase: &str,
+) -> Result<(RemoteSignerSession, NostrSigner)> {
+    let uri = NostrConnectURI::parse(uri)?;
+    if !matches!(uri, NostrConnectURI::Bunker { .. }) {
+        return Err(Error::NotBunker);
+    }
+    connect(uri, Keys::generate(), passphrase).await
+}
+
+/// The nostrconnect:// URI to show as a QR code; the bunker answers through `relay`
+pub fn nostrconnect_uri(app_keys: &Keys, relay: Url, app_name: &str) -> NostrConnectURI {
+    NostrConnectURI::client(app_keys.public_key(), [relay], app_name)
+}
+
+/// Waits for the bunker to accept `uri` and returns the session to store,
+/// the client key encrypted under `passphrase`
+pub async fn connect(
+    uri: NostrConnectURI,
+    app_keys: Keys,
+    passphrase: &str,
+) -> Result<(RemoteSignerSession, NostrSigner)> {
+    // checked before the user goes through the bunker approval
+    check_passphrase(passphrase)?;
+    let app_ncryptsec = encrypt_ncryptsec(app_keys.secret_key()?, passphrase)?;
+    let signer = Nip46Signer::new(uri, app_keys, TIMEOUT, None).await?;
+    let pk = signer.signer_public_key().await?;
+    // a nostrconnect session is resumed later through the bunker URI
+    let bunker_uri = signer.bunker_uri().await?;
+    let session = RemoteSignerSession::new(pk, bunker_uri.to_string(), app_ncryptsec);
+    Ok((session, NostrSigner::nip46(signer)))
+}
+
+/// Resumes a stored session with the client keys the bunker already authorized
+pub async fn remote_signer(session: &RemoteSignerSession, passphrase: &str) -> Result<NostrSigner> {
+    let uri = NostrConnectURI::parse(&session.bunker_uri)?;
+    let app_keys = Keys::new(decrypt_ncryptsec(&session.app_ncryptsec, passphrase)?);
+    let signer = Nip46Signer::new(uri, app_keys, TIMEOUT, None).await?;
+    Ok(NostrSigner::nip46(signer))
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_connect_bunker_rejects_client_uri() {
+        let app_keys = Keys::generate();
+        let uri = nostrconnect_uri(
+            &app_keys,
+            Url::parse("wss://relay.nsec.app").unwrap(),
+            "capybastr",
+        );
+        let result = connect_bunker(&uri.to_string(), "correct horse battery staple").await;
+        assert!(matches!(result, Err(Error::NotBunker)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_nostrconnect_uri() {
+        let app_keys = Keys::generate();
+        let uri = nostrconnect_uri(
+            &app_keys,
+            Url::parse("wss://relay.nsec.app").unwrap(),
+            "capybastr",
+        );
+        let text = uri.to_string();
+        assert!(text.starts_with("nostrconnect://"));
+        assert_eq!(NostrConnectURI::parse(&text).unwrap(), uri);
+    }
+}
@@ -0,0 +1,281 @@
+use std::str::FromStr;
+
+use nostr_sdk::{Filter, Keys, Kind, NostrSigner, PublicKey, Timestamp};
+use thiserror::Error;
+
+use super::extension::extension_signer;
+use super::remote::remote_signer;
+use super::unlock;
+use crate::init::FOLLOWING_SUB_KEY;
+use crate::nostr::{get_following, HashedClient};
+use crate::store::subscription::{Account, CustomAccounts, CustomSub, FilterTemp};
+use crate::store::user::NoLogin;
+use crate::store::{AccountType, CBWebDatabase, CBwebDatabaseError, User};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No signer: this session is watch-only or logged out")]
+    NoSigner,
+    #[error("This account is locked, enter its passphrase")]
+    Locked,
+    #[error(transparent)]
+    Account(#[from] super::Error),
+    #[error(transparent)]
+    Remote(#[from] super::remote::Error),
+    #[error(transparent)]
+    Extension(#[from] super::extension::Error),
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error("Fetch error: {0}")]
+    Fetch(String),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// What changes with the active account besides the signer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountState {
+    pub contacts: Vec<PublicKey>,
+    pub mutes: Vec<PublicKey>,
+    /// Timestamp of the newest notification already seen
+    pub notifications_since: Option<Timestamp>,
+}
+
+impl AccountState {
+    pub fn is_muted(&self, public_key: &PublicKey) -> bool {
+        self.mutes.contains(public_key)
+    }
+
+    /// The Following subscription over the contacts that are not muted
+    pub fn following_sub(&self, relay_set: &str) -> CustomSub {
+        let accounts = self
+            .contacts
+            .iter()
+            .filter(|pk| !self.is_muted(pk))
+            .map(|pk| {
+                let hex = pk.to_hex();
+                Account {
+                    alt_name: hex[0..5].to_string(),
+                    npub: hex,
+                }
+            })
+            .collect();
+        CustomSub {
+            name: FOLLOWING_SUB_KEY.to_string(),
+            relay_set: relay_set.to_string(),
+            live: false,
+            since: 0,
+            until: 0,
+            filters: vec![FilterTemp::Accounts(CustomAccounts {
+                r#type: String::from("accounts"),
+                kinds: vec![1],
+                accounts,
+            })],
+            keep_alive: true,
+        }
+    }
+}
+
+/// The active account: who we are, how we sign and the state loaded for it.
+///
+/// A watch-only (`Pub`) or logged out session has no signer, publishing with
+/// it fails with [`Error::NoSigner`].
+#[derive(Clone)]
+pub struct Session {
+    user: User,
+    public_key: Option<PublicKey>,
+    signer: Option<NostrSigner>,
+    state: AccountState,
+}
+
+impl Session {
+    pub fn logged_out() -> Self {
+        Self {
+            user: User {
+                name: crate::init::NOT_LOGGED_IN_USER_NAME.to_string(),
+                inner: AccountType::NotLoggedIn(NoLogin::empty()),
+            },
+            public_key: None,
+            signer: None,
+            state: AccountState::default(),
+        }
+    }
+
+    /// Builds the signer of `user`, local keys and remote signer sessions
+    /// need `passphrase`
+    pub async fn open(user: User, passphrase: Option<&str>) -> Result<Self> {
+        let (public_key, signer) = match &user.inner {
+            AccountType::NotLoggedIn(_) => return Ok(Self::logged_out()),
+            AccountType::Pub(account) => (account.pk, None),
+            AccountType::SecretKey(_) | AccountType::Ncryptsec(_) => {
+                let passphrase = passphrase.ok_or(Error::Locked)?;
+                let keys = Keys::new(unlock(&user.inner, passphrase)?);
+                (keys.public_key(), Some(keys.into()))
+            }
+            AccountType::RemoteSigner(session) => {
+                let passphrase = passphrase.ok_or(Error::Locked)?;
+                (session.pk, Some(remote_signer(session, passphrase).await?))
+            }
+            AccountType::Extension(account) => (account.pk, Some(extension_signer(account).await?)),
+        };
+        Ok(Self {
+            user,
+            public_key: Some(public_key),
+            signer,
+            state: AccountState::default(),
+        })
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn public_key(&self) -> Option<PublicKey> {
+        self.public_key
+    }
+
+    pub fn state(&self) -> &AccountState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut AccountState {
+        &mut self.state
+    }
+
+    pub fn is_watch_only(&self) -> bool {
+        self.public_key.is_some() && self.signer.is_none()
+    }
+
+    /// The signer to publish with
+    pub fn signer(&self) -> Result<&NostrSigner> {
+        self.signer.as_ref().ok_or(Error::NoSigner)
+    }
+
+    /// Loads contacts and mutes from the relays and the notifications cursor
+    /// from the local database
+    pub async fn load_state(&mut self, relay_set: &HashedClient, db: &CBWebDatabase) -> Result<()> {
+        let Some(public_key) = self.public_key else {
+            self.state = AccountState::default();
+            return Ok(());
+        };
+        let contacts = get_following(relay_set, &public_key, None)
+            .await
+            .map_err(|e| Error::Fetch(e.to_string()))?
+            .iter()
+            .filter_map(|hex| PublicKey::from_str(hex).ok())
+            .collect();
+
+        let filter = Filter::new().kind(Kind::MuteList).author(public_key);
+        let events = relay_set
+            .get_events_of(vec![filter], None)
+            .await
+            .map_err(|e| Error::Fetch(e.to_string()))?;
+        let mutes = events
+            .iter()
+            .max_by_key(|event| event.created_at())
+            .map(|event| event.public_keys().copied().collect())
+            .unwrap_or_default();
+
+        let notifications_since = db
+            .get_notifications_cursor(&public_key.to_hex())
+            .await?
+            .map(Timestamp::from);
+
+        self.state = AccountState {
+            contacts,
+            mutes,
+            notifications_since,
+        };
+        Ok(())
+    }
+
+    /// Moves the notifications cursor forward and persists it
+    pub async fn mark_notifications_seen(
+        &mut self,
+        db: &CBWebDatabase,
+        until: Timestamp,
+    ) -> Result<()> {
+        let Some(public_key) = self.public_key else {
+            return Ok(());
+        };
+        if self
+            .state
+            .notifications_since
+            .map_or(false, |since| since >= until)
+        {
+            return Ok(());
+        }
+        self.state.notifications_since = Some(until);
+        db.save_notifications_cursor(&public_key.to_hex(), until.as_u64())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::account::encrypt_ncryptsec;
+    use crate::store::user::{NcryptsecPrivkey, OnlyPubkey};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_watch_only_has_no_signer() {
+        let pk = Keys::generate().public_key();
+        let user = User {
+            name: "watch".to_string(),
+            inner: AccountType::Pub(OnlyPubkey::new(pk)),
+        };
+        let session = Session::open(user, None).await.unwrap();
+        assert!(session.is_watch_only());
+        assert_eq!(session.public_key(), Some(pk));
+        assert!(matches!(session.signer(), Err(Error::NoSigner)));
+
+        let logged_out = Session::logged_out();
+        assert!(logged_out.public_key().is_none());
+        assert!(matches!(logged_out.signer(), Err(Error::NoSigner)));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_open_local_key() {
+        let keys = Keys::generate();
+        let ncryptsec = encrypt_ncryptsec(keys.secret_key().unwrap(), "passphrase").unwrap();
+        let user = User {
+            name: "local".to_string(),
+            inner: AccountType::Ncryptsec(NcryptsecPrivkey::new(ncryptsec)),
+        };
+        assert!(matches!(
+            Session::open(user.clone(), None).await,
+            Err(Error::Locked)
+        ));
+        let session = Session::open(user, Some("passphrase")).await.unwrap();
+        assert_eq!(session.public_key(), Some(keys.public_key()));
+        let signer = session.signer().unwrap();
+        assert_eq!(signer.public_key().await.unwrap(), keys.public_key());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_following_sub_skips_mutes() {
+        let followed = Keys::generate().public_key();
+        let muted = Keys::generate().public_key();
+        let state = AccountState {
+            contacts: vec![followed, muted],
+            mutes: vec![muted],
+            notifications_since: None,
+        };
+        let sub = state.following_sub("default");
+        assert_eq!(sub.name, FOLLOWING_SUB_KEY);
+        match &sub.filters[0] {
+            FilterTemp::Accounts(accounts) => {
+                assert_eq!(accounts.accounts.len(), 1);
+                assert_eq!(accounts.accounts[0].npub, followed.to_hex());
+            }
+            _ => panic!("expected an accounts filter"),
+        }
+    }
+}
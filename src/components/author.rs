@@ -1,6 +1,7 @@
 use dioxus::prelude::*;
 
 use crate::components::icons::*;
+use crate::components::ZapModal;
 use crate::init::MODAL_MANAGER;
 use crate::nostr::{get_metadata, get_newest_event, MultiClient};
 
@@ -44,7 +45,6 @@ pub fn Author(props: AuthorProps) -> Element {
             spawn(async move {
                 let multiclient = multiclient();
                 if let Some(client) = multiclient.get_client(&relay_name).await {
-                    let client = client.client();
                     {
                         let filter = Filter::new().author(pubkey).kind(Kind::RelayList);
                         let event_result1 = client.get_events_of(vec![filter], None).await.unwrap();
@@ -147,6 +147,22 @@ pub fn Author(props: AuthorProps) -> Element {
         );
         MODAL_MANAGER.write().open_modal("qrcode");
     };
+    let zap_relay_name = relay_name.clone();
+    let handle_zap = move || {
+        let modal_id = format!("zap-{}", pubkey.to_hex());
+        MODAL_MANAGER.write().add_modal(
+            rsx! {
+                ZapModal {
+                    id: modal_id.clone(),
+                    recipient: pubkey,
+                    event_id: None,
+                    relay_name: zap_relay_name.clone(),
+                }
+            },
+            modal_id.clone(),
+        );
+        MODAL_MANAGER.write().open_modal(&modal_id);
+    };
     rsx! {
         // Custom Sub component
         div {
@@ -195,6 +211,13 @@ pub fn Author(props: AuthorProps) -> Element {
                   },
                   dangerous_inner_html: "{QRCODE}",
                 }
+                span{
+                  class:"zap-position",
+                  onclick: move |_| {
+                    handle_zap();
+                  },
+                  dangerous_inner_html: "{ZAP}",
+                }
 
               }
             }
@@ -8,9 +8,12 @@ mod message;
 mod modal_manager;
 mod notification;
 mod outside;
+mod publish_status;
 mod quote;
+mod relay_info;
 mod switch;
 mod relays_manage;
+mod zap_modal;
 
 pub mod icons;
 
@@ -24,7 +27,10 @@ pub use message::Message;
 pub use modal_manager::{ModalManager, ModalManagerProvider};
 pub use notification::Notification;
 pub use outside::ClickOutside;
+pub use publish_status::PublishStatus;
 pub use quote::Quote;
+pub use relay_info::RelayInfo;
 pub use switch::Switch;
 pub use relays_manage::RelaysManage;
+pub use zap_modal::ZapModal;
 
@@ -7,7 +7,9 @@ use crate::components::icons::LOADING;
 // use crate::components::MODAL_MANAGER;
 use std::time::Duration;
 use nostr_sdk::{PublicKey,Event};
+use crate::account::session::Session;
 use crate::nostr::MultiClient;
+use crate::store::CBWebDatabase;
 use crate::nostr::{NotificationPaginator,NotificationMsg};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
@@ -22,12 +24,16 @@ pub struct NotifContenteProps {
 #[component]
 pub fn Notification(props: NotifContenteProps) -> Element {
     let multiclient = use_context::<Signal<MultiClient>>();
+    let mut session = use_context::<Signal<Session>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
     let public_key = props.public_key.clone();
     let relay_name = props.relay_name.clone();
     let mut notif_events: Signal<Vec<NotificationMsg>> = use_signal(|| Vec::new());
     let mut paginator = use_signal(|| None);
     let mut is_loading = use_signal(|| false);
     let mut before_present_time_str = use_signal(|| "".to_string());
+    // notifications newer than the account's cursor when the list was opened
+    let mut unread = use_signal(|| 0usize);
 
     //parse as event 
     let get_event =  move | notif_msg: &NotificationMsg| -> Event{
@@ -55,6 +61,36 @@ pub fn Notification(props: NotifContenteProps) -> Element {
                   let notifi_msgs: Option<Vec<NotificationMsg>> = paginator.next_page().await;
                   match notifi_msgs {
                       Some(notifi_msgs) => {
+                        let (notifi_msgs, since) = {
+                          let session = session.read();
+                          let state = session.state();
+                          let msgs: Vec<NotificationMsg> = notifi_msgs
+                            .into_iter()
+                            .filter(|msg| !state.is_muted(&get_event(msg).author()))
+                            .collect();
+                          (msgs, state.notifications_since)
+                        };
+                        let first_page = notif_events.read().is_empty();
+                        if first_page && session.read().public_key() == Some(public_key) {
+                          let newest = notifi_msgs.iter().map(|msg| get_event(msg).created_at).max();
+                          unread.set(
+                            notifi_msgs
+                              .iter()
+                              .filter(|msg| since.map_or(true, |since| get_event(msg).created_at > since))
+                              .count(),
+                          );
+                          if let Some(newest) = newest {
+                            let db = cb_database_db.read().clone();
+                            let mut current = session.read().clone();
+                            match current.mark_notifications_seen(&db, newest).await {
+                              Ok(_) => {
+                                session.write().state_mut().notifications_since =
+                                  current.state().notifications_since;
+                              }
+                              Err(e) => tracing::error!("save notifications cursor error: {:?}", e),
+                            }
+                          }
+                        }
                         notif_events.extend(notifi_msgs);
                         
                         //
@@ -87,10 +123,9 @@ pub fn Notification(props: NotifContenteProps) -> Element {
               let client_result = clients.get_or_create(&relay_name.clone()).await;
               match client_result {
                   Ok(hc) => {
-                    let client = hc.client();
                     let timeout: Option<Duration> = Some(std::time::Duration::from_secs(5));
                     let paginator_result: NotificationPaginator =
-                        NotificationPaginator::new(client, public_key.clone(), timeout, 20, false);
+                        NotificationPaginator::new(hc, public_key.clone(), timeout, 20, false);
                     paginator.set(Some(paginator_result));
                     handle_fetch();
                   }
@@ -116,6 +151,12 @@ pub fn Notification(props: NotifContenteProps) -> Element {
                     class: "title custom-sub-title font-raleway-800 font-size-20",
                     "Notification"
                 }
+                if unread() > 0 {
+                    span {
+                        class: "font-size-14 ml-8",
+                        "{unread} new"
+                    }
+                }
                 button {
                     class: "icon",
                     dangerous_inner_html: "{MORE}"
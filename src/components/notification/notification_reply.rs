@@ -31,7 +31,6 @@ pub fn NotifReply(props: NotifReplyProps) -> Element {
         spawn(async move {
             let clients = multiclient();
             if let Some(client) = clients.get_client(&relay_name).await {
-                let client = client.client();
                 //get to db
                 match client.database().event_by_id(_event_id).await {
                     Ok(db_events) => {
@@ -0,0 +1,72 @@
+use dioxus::prelude::*;
+use nostr_sdk::Event;
+
+use crate::nostr::{queue_failed, MultiClient, PublishResult, RelayOutcome};
+use crate::store::{CBWebDatabase, DEFAULT_RELAY_SET_KEY};
+
+/// "sent to 3/5 relays" for a published event, with what each relay said
+/// and a button to send it to the relay set again
+#[component]
+pub fn PublishStatus(
+    event: Event,
+    result: PublishResult,
+    on_result: EventHandler<PublishResult>,
+) -> Element {
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let mut expanded = use_signal(|| false);
+    let mut sending = use_signal(|| false);
+
+    let handle_rebroadcast = {
+        let event = event.clone();
+        let result = result.clone();
+        move |_| {
+            let event = event.clone();
+            let mut result = result.clone();
+            sending.set(true);
+            spawn(async move {
+                match multiclient().get_or_create(DEFAULT_RELAY_SET_KEY).await {
+                    Ok(hc) => {
+                        result.merge(hc.send_event(event.clone()).await);
+                        let db = cb_database_db.read().clone();
+                        if let Err(e) = queue_failed(&db, &event, &result, 0).await {
+                            tracing::error!("queue failed relays error: {:?}", e);
+                        }
+                        on_result.call(result);
+                    }
+                    Err(e) => tracing::error!("rebroadcast client error: {:?}", e),
+                }
+                sending.set(false);
+            });
+        }
+    };
+
+    let complete = result.accepted() == result.total();
+    rsx! {
+        div {
+            class: "publish-status publish-status--{complete}",
+            div {
+                class: "flex items-center justify-between",
+                span {
+                    class: "cursor-pointer",
+                    onclick: move |_| expanded.set(!expanded()),
+                    "{result}"
+                }
+                button {
+                    class: "capy-btn",
+                    disabled: sending(),
+                    onclick: handle_rebroadcast,
+                    if sending() { "Sending..." } else { "Rebroadcast" }
+                }
+            }
+            if expanded() {
+                for (url, outcome) in result.relays.iter() {
+                    div {
+                        class: format!("publish-status-relay text-overflow {}", if *outcome == RelayOutcome::Accepted { "" } else { "publish-status-relay--failed" }),
+                        "{url} {outcome}"
+                    }
+                }
+            }
+        }
+    }
+}
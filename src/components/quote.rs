@@ -39,7 +39,7 @@ pub fn Quote(props: QouteProps) -> Element {
                 };
 
                 // Fetch the event by ID
-                match get_event_by_id(&client.client(), &event_id, None).await {
+                match get_event_by_id(&client, &event_id, None).await {
                     Ok(Some(event)) => {
                         let pk = event.author();
                         let content = event.content.to_string();
@@ -0,0 +1,133 @@
+use dioxus::prelude::*;
+use nostr_sdk::nips::nip11::RelayInformationDocument;
+use nostr_sdk::Filter;
+
+use crate::nostr::{check_filters, get_relay_info};
+use crate::store::CBWebDatabase;
+
+fn limits(document: &RelayInformationDocument) -> Vec<String> {
+    let mut limits = Vec::new();
+    if let Some(limitation) = &document.limitation {
+        if let Some(max) = limitation.max_limit {
+            limits.push(format!("max limit {}", max));
+        }
+        if let Some(max) = limitation.max_filters {
+            limits.push(format!("max filters {}", max));
+        }
+        if let Some(max) = limitation.max_subscriptions {
+            limits.push(format!("max subscriptions {}", max));
+        }
+        if limitation.auth_required == Some(true) {
+            limits.push("auth required".to_string());
+        }
+        if limitation.payment_required == Some(true) {
+            limits.push("payment required".to_string());
+        }
+    }
+    limits
+}
+
+fn fees(document: &RelayInformationDocument) -> Option<String> {
+    let fees = document.fees.as_ref()?;
+    let admission = fees
+        .admission
+        .iter()
+        .map(|fee| format!("{} {}", fee.amount, fee.unit))
+        .collect::<Vec<String>>();
+    if admission.is_empty() {
+        return None;
+    }
+    Some(format!("admission {}", admission.join(", ")))
+}
+
+fn retention(document: &RelayInformationDocument) -> Vec<String> {
+    document
+        .retention
+        .iter()
+        .filter_map(|rule| match (rule.time, rule.count) {
+            (Some(time), _) => Some(format!("{} days", time / 86400)),
+            (None, Some(count)) => Some(format!("{} events", count)),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// The NIP-11 document of a relay and what `subs` use that it won't serve
+#[component]
+pub fn RelayInfo(url: String, #[props(default)] subs: Vec<(String, Vec<Filter>)>) -> Element {
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let mut expanded = use_signal(|| false);
+    let info = use_resource(use_reactive((&url,), move |(url,)| async move {
+        let db = cb_database_db.read().clone();
+        match get_relay_info(&db, &url).await {
+            Ok(document) => Some(document),
+            Err(e) => {
+                tracing::warn!("relay info of {} error: {:?}", url, e);
+                None
+            }
+        }
+    }));
+
+    let Some(Some(document)) = info.read().clone() else {
+        return rsx! {};
+    };
+    let warnings: Vec<String> = subs
+        .iter()
+        .flat_map(|(name, filters)| {
+            check_filters(&document, filters)
+                .into_iter()
+                .map(move |unsupported| format!("{}: {}", name, unsupported))
+        })
+        .collect();
+    let title = document.name.clone().unwrap_or(url.clone());
+    let nips = document
+        .supported_nips
+        .as_ref()
+        .map(|nips| {
+            nips.iter()
+                .map(|nip| nip.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    let limits = limits(&document).join(" · ");
+    let fees = fees(&document);
+    let retention = retention(&document).join(", ");
+
+    rsx! {
+        div {
+            class: "relay-info",
+            div {
+                class: "relay-info-title cursor-pointer text-overflow",
+                onclick: move |_| expanded.set(!expanded()),
+                "{title}"
+            }
+            for warning in warnings.iter() {
+                div {
+                    class: "relay-info-warning",
+                    "{warning}"
+                }
+            }
+            if expanded() {
+                if let Some(description) = &document.description {
+                    div { "{description}" }
+                }
+                if let Some(pubkey) = &document.pubkey {
+                    div { class: "text-overflow", "operator {pubkey}" }
+                }
+                if !nips.is_empty() {
+                    div { "NIPs {nips}" }
+                }
+                if !limits.is_empty() {
+                    div { "{limits}" }
+                }
+                if let Some(fees) = fees {
+                    div { "{fees}" }
+                }
+                if !retention.is_empty() {
+                    div { "retention {retention}" }
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::components::icons::{ADD, BOTTOMRIGHT, FALSE, LOADING, UPPERRIGHT};
+use crate::components::RelayInfo;
+use crate::nostr::{apply_relay_set_edit, EventCache, MultiClient, Register};
 use crate::store::subscription::CustomSub;
 use crate::store::subscription::RelaySet;
 use crate::store::{CBWebDatabase, DEFAULT_RELAY_SET_KEY};
@@ -13,6 +15,9 @@ pub fn RelaysManage(sub_name: String) -> Element {
     // tracing::info!("system_subs: {:?}", sub_name);
     let mut cb_database_db = use_context::<Signal<CBWebDatabase>>();
     let mut subs_map = use_context::<Signal<HashMap<String, CustomSub>>>();
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let register = use_context::<Signal<Register>>();
+    let event_cache = use_context::<Signal<EventCache>>();
     let mut relay_sets = use_signal(|| Vec::<RelaySet>::new());
     let mut relay_current_index = use_signal(|| 0);
     let current_relay_set = use_memo(move || match relay_sets.read().get(relay_current_index()) {
@@ -21,6 +26,15 @@ pub fn RelaysManage(sub_name: String) -> Element {
     });
 
     let sub_name_current = use_signal(|| sub_name);
+    // the subscriptions reading from the selected relay set
+    let relay_subs = use_memo(move || {
+        subs_map
+            .read()
+            .values()
+            .filter(|sub| sub.relay_set == current_relay_set().name)
+            .map(|sub| (sub.name.clone(), sub.get_filters()))
+            .collect::<Vec<_>>()
+    });
     let mut new_relay = use_signal(String::new);
     let mut is_save_loaded = use_signal(|| false);
     let mut old_relay_sets: Signal<Vec<RelaySet>> = use_signal(Vec::new);
@@ -133,18 +147,13 @@ pub fn RelaysManage(sub_name: String) -> Element {
             is_save_loaded.set(true);
             let cb_database_db_write = cb_database_db.write();
 
-            //check action type
-            let mut is_add = true;
-            let mut is_edit = false;
-            for (i, realy) in _old_relay_sets.iter().enumerate() {
-                if realy.name == realy_name {
-                    if i == _relay_index() {
-                        is_edit = true;
-                        is_add = false;
-                        break;
-                    }
-                }
-            }
+            //check action type, the set at the current index is the one being edited
+            let old_name = _old_relay_sets
+                .get(_relay_index())
+                .map(|realy| realy.name.clone())
+                .unwrap_or_else(|| realy_name.clone());
+            let is_edit = _old_relay_sets.get(_relay_index()).is_some();
+            let is_add = !is_edit;
 
             //save relay  to db
             if is_add {
@@ -157,9 +166,28 @@ pub fn RelaysManage(sub_name: String) -> Element {
             //edit relay  to db
             if is_edit {
                 cb_database_db_write
-                    .relay_set_change(realy_name.clone(), _relay_current.clone())
+                    .relay_set_change(old_name.clone(), _relay_current.clone())
                     .await
                     .unwrap();
+                if let Err(e) = apply_relay_set_edit(
+                    &multiclient(),
+                    &register(),
+                    &event_cache(),
+                    &old_name,
+                    &_relay_current,
+                )
+                .await
+                {
+                    tracing::error!("relays_manage apply relay set edit error:{:?}", e);
+                }
+                // the db moved every sub on the set, the map has to follow
+                if old_name != _relay_current.name {
+                    for sub in subs_map.write().values_mut() {
+                        if sub.relay_set == old_name {
+                            sub.relay_set.clone_from(&_relay_current.name);
+                        }
+                    }
+                }
             }
 
             //update sub map and db
@@ -219,6 +247,9 @@ pub fn RelaysManage(sub_name: String) -> Element {
                 .remove_relay_set(_relay_current.name.clone())
                 .await
                 .unwrap();
+            if let Err(e) = multiclient().remove(&_relay_current.name).await {
+                tracing::error!("relays_manage remove relay set client error:{:?}", e);
+            }
 
             // update sub relay-set to default
             let mut new_map = HashMap::new();
@@ -347,6 +378,10 @@ pub fn RelaysManage(sub_name: String) -> Element {
                       }
                     }
                 }
+                RelayInfo {
+                    url: relay_url.clone(),
+                    subs: relay_subs()
+                }
             }
             div {
                 class:"relay-url-item flex items-center",
@@ -0,0 +1,189 @@
+use dioxus::prelude::*;
+use futures::StreamExt;
+use nostr_sdk::{EventId, PublicKey};
+
+use crate::account::session::Session;
+use crate::components::icons::{FALSE, LOADING};
+use crate::init::MODAL_MANAGER;
+use crate::nostr::{send_zap, MultiClient, PaymentBackend, Wallet, ZapOptions, ZapState};
+use crate::store::wallet::WalletConnection;
+use crate::store::CBWebDatabase;
+
+const WEBLN: &str = "webln";
+
+fn describe(state: &ZapState) -> String {
+    match state {
+        ZapState::ResolvingLnurl => "Looking up the lightning address...".to_string(),
+        ZapState::RequestingInvoice => "Requesting an invoice...".to_string(),
+        ZapState::Paying { .. } => "Paying the invoice...".to_string(),
+        ZapState::WaitingReceipt => "Paid, waiting for the zap receipt...".to_string(),
+        ZapState::ReceiptSeen(zap) => format!("Zapped {} sats", zap.amount_msats / 1000),
+        ZapState::Failed(e) => format!("Zap failed: {}", e),
+    }
+}
+
+/// Amount and comment form that zaps a profile, or one of its notes when
+/// `event_id` is set, paying through WebLN or one of the account's NWC wallets
+#[component]
+pub fn ZapModal(
+    id: String,
+    recipient: PublicKey,
+    event_id: Option<EventId>,
+    relay_name: String,
+) -> Element {
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let session = use_context::<Signal<Session>>();
+
+    let mut amount = use_signal(|| "21".to_string());
+    let mut comment = use_signal(String::new);
+    let mut backend = use_signal(|| WEBLN.to_string());
+    let mut passphrase = use_signal(String::new);
+    let mut wallets = use_signal(Vec::<WalletConnection>::new);
+    let mut state = use_signal(|| None::<ZapState>);
+    let mut sending = use_signal(|| false);
+
+    use_effect(move || {
+        let user = session.read().user().name.clone();
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            match db.get_wallets(user).await {
+                Ok(list) => wallets.set(list),
+                Err(e) => tracing::error!("get wallets error: {:?}", e),
+            }
+        });
+    });
+
+    let mut fail = move |reason: String| {
+        state.set(Some(ZapState::Failed(reason)));
+        sending.set(false);
+    };
+
+    let handle_send = move |_| {
+        let relay_name = relay_name.clone();
+        let sats = match amount().trim().parse::<u64>() {
+            Ok(sats) if sats > 0 => sats,
+            _ => {
+                fail("Invalid amount".to_string());
+                return;
+            }
+        };
+        let signer = match session.read().signer() {
+            Ok(signer) => signer.clone(),
+            Err(e) => {
+                fail(e.to_string());
+                return;
+            }
+        };
+        let payment = if backend() == WEBLN {
+            PaymentBackend::WebLN
+        } else {
+            let connection = wallets.read().iter().find(|w| w.id == backend()).cloned();
+            let Some(connection) = connection else {
+                fail("Choose a wallet".to_string());
+                return;
+            };
+            match Wallet::unlock(&connection, &passphrase()) {
+                Ok(wallet) => PaymentBackend::Nwc(wallet),
+                Err(e) => {
+                    fail(format!("{}: {}", connection.name, e));
+                    return;
+                }
+            }
+        };
+        passphrase.set(String::new());
+        sending.set(true);
+        state.set(None);
+        spawn(async move {
+            let hc = match multiclient().get_or_create(&relay_name).await {
+                Ok(hc) => hc,
+                Err(e) => {
+                    fail(e.to_string());
+                    return;
+                }
+            };
+            let mut opts = ZapOptions::new(recipient, sats * 1000).comment(comment());
+            if let Some(event_id) = event_id {
+                opts = opts.event(event_id);
+            }
+            let mut progress = Box::pin(send_zap(hc, signer, payment, opts, None));
+            while let Some(next) = progress.next().await {
+                state.set(Some(next));
+            }
+            sending.set(false);
+        });
+    };
+
+    let on_close = move |_| {
+        MODAL_MANAGER.write().close_modal(&id);
+    };
+
+    rsx! {
+        div {
+            class: "zap-modal",
+            div {
+                class: "zap-modal-content",
+                div {
+                    class: "zap-modal-close btn-circle btn-circle-false",
+                    onclick: on_close,
+                    dangerous_inner_html: "{FALSE}",
+                }
+                h1 {
+                    class: "font-size-16 mb-15",
+                    if event_id.is_some() { "Zap this note" } else { "Zap this profile" }
+                }
+                input {
+                    class: "relay-ipt mb-10",
+                    r#type: "number",
+                    min: "1",
+                    placeholder: "Amount in sats",
+                    value: "{amount}",
+                    oninput: move |event| amount.set(event.value()),
+                }
+                input {
+                    class: "relay-ipt mb-10",
+                    r#type: "text",
+                    placeholder: "Comment",
+                    value: "{comment}",
+                    oninput: move |event| comment.set(event.value()),
+                }
+                select {
+                    class: "relay-ipt mb-10",
+                    value: "{backend}",
+                    oninput: move |event| backend.set(event.value()),
+                    option { value: WEBLN, "Browser extension (WebLN)" }
+                    for wallet in wallets() {
+                        option { value: "{wallet.id}", "{wallet.name}" }
+                    }
+                }
+                if backend() != WEBLN {
+                    input {
+                        class: "relay-ipt mb-10",
+                        r#type: "password",
+                        placeholder: "Wallet passphrase",
+                        value: "{passphrase}",
+                        oninput: move |event| passphrase.set(event.value()),
+                    }
+                }
+                button {
+                    class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                    disabled: sending(),
+                    onclick: handle_send,
+                    if sending() { "Zapping..." } else { "Zap" }
+                }
+                if sending() {
+                    div {
+                        class: "laoding-box",
+                        dangerous_inner_html: "{LOADING}"
+                    }
+                }
+                if let Some(current) = state() {
+                    div {
+                        class: format!("zap-modal-state font-size-14 {}", if matches!(current, ZapState::Failed(_)) { "zap-modal-state--failed" } else { "" }),
+                        {describe(&current)}
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,12 +2,14 @@ use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use dioxus::prelude::*;
-use nostr_indexeddb::WebDatabase;
-use nostr_sdk::{ClientBuilder, Event, SubscriptionId};
+use nostr_sdk::{Event, SubscriptionId};
+use wasm_bindgen_futures::spawn_local;
 
+use crate::account::session::{self, Session};
+use crate::account::upgrade_legacy;
 use crate::components::{ModalManager, ModalManagerProvider};
 use crate::nostr::Register;
-use crate::nostr::{EventCache, HashedClient, MultiClient};
+use crate::nostr::{sync_account, sync_subs, EventCache, MultiClient};
 use crate::store::subscription::{CustomHashTag, CustomSub, FilterTemp, RelaySet};
 use crate::store::user::NoLogin;
 use crate::store::{
@@ -55,6 +57,13 @@ impl Counter {
         (count).0 += 1;
         (count).1.push(event);
     }
+    /// Counts a whole batch with a single write, one re-render per batch
+    pub fn inc_batch(&self, id: &SubscriptionId, events: Vec<Event>) {
+        let mut counts = self.counts.write().unwrap();
+        let count = counts.entry(id.clone()).or_insert((0, vec![]));
+        (count).0 += events.len();
+        (count).1.extend(events);
+    }
     pub fn clear_size(&self, id: &SubscriptionId) {
         let mut counts = self.counts.write().unwrap();
         let count = counts.entry(id.clone()).or_insert((0, vec![]));
@@ -76,6 +85,73 @@ pub static SUB_COUNTERS: GlobalSignal<Counter> = Signal::global(Counter::new);
 
 pub static MODAL_MANAGER: GlobalSignal<ModalManager> = Signal::global(ModalManager::new);
 
+/// Moves the legacy record of `user` to an ncryptsec under the new
+/// `passphrase` and saves it, returns the upgraded user
+pub async fn upgrade_account(
+    cb_database_db: Signal<CBWebDatabase>,
+    mut all_users: Signal<Vec<User>>,
+    user: User,
+    pin: &str,
+    passphrase: &str,
+) -> Result<User, session::Error> {
+    let upgraded = User {
+        name: user.name.clone(),
+        inner: upgrade_legacy(&user.inner, pin, passphrase)?,
+    };
+    let db = cb_database_db.read().clone();
+    db.save_user(upgraded.clone()).await?;
+    for u in all_users.write().iter_mut() {
+        if u.name == upgraded.name {
+            *u = upgraded.clone();
+        }
+    }
+    Ok(upgraded)
+}
+
+/// Makes `user` the active account without reloading the page.
+///
+/// The signer is built and the contact list, mute list and notifications
+/// cursor are loaded before the session is swapped, so views keyed on the
+/// session's public key (the Following feed, notifications, inbox) reload once.
+pub async fn switch_account(
+    mut session: Signal<Session>,
+    multiclient: Signal<MultiClient>,
+    cb_database_db: Signal<CBWebDatabase>,
+    user: User,
+    passphrase: Option<String>,
+) -> Result<(), session::Error> {
+    let mut new_session = Session::open(user.clone(), passphrase.as_deref()).await?;
+    let db = cb_database_db.read().clone();
+    match multiclient().get_or_create(DEFAULT_RELAY_SET_KEY).await {
+        Ok(hc) => {
+            if let Err(e) = new_session.load_state(&hc, &db).await {
+                tracing::error!("load account state error: {:?}", e);
+            }
+            // own events, contact list and DMs, only what the db lacks
+            if let Some(public_key) = new_session.public_key() {
+                spawn_local(async move {
+                    match sync_account(&hc, public_key).await {
+                        Ok(report) => tracing::info!("account sync: {}", report),
+                        Err(e) => tracing::error!("account sync error: {:?}", e),
+                    }
+                });
+            }
+        }
+        Err(e) => tracing::error!("account client Error: {:?}", e),
+    }
+    db.save_misc(LAST_LOGINED_KEY.to_string(), user.name)
+        .await?;
+    // NIP-42 challenges are answered as the active account
+    match multiclient().pool().await {
+        Ok(pool) => pool
+            .authenticator()
+            .change_signer(&pool, new_session.signer().ok().cloned()),
+        Err(e) => tracing::error!("authenticator Error: {:?}", e),
+    }
+    session.set(new_session);
+    Ok(())
+}
+
 #[allow(non_snake_case)]
 pub fn App() -> Element {
     tracing::info!("Welcome to Capybastr!!");
@@ -85,16 +161,18 @@ pub fn App() -> Element {
     let mut subs_map: Signal<HashMap<String, CustomSub>> =
         use_context_provider(|| Signal::new(HashMap::<String, CustomSub>::new()));
     let mut all_users: Signal<Vec<User>> = use_context_provider(|| Signal::new(Vec::<User>::new()));
+    let session: Signal<Session> = use_context_provider(|| Signal::new(Session::logged_out()));
 
     // theme class name
     let theme = use_context_provider(|| Signal::new(String::from("light")));
 
     let mut router = use_signal(|| rsx! {div{}});
 
-    use_context_provider(|| Signal::new(EventCache::new(300, 300)));
+    let event_cache = use_context_provider(|| Signal::new(EventCache::new(300, 300)));
 
     // use_context_provider(|| Signal::new(Counter::new(true)));
-    use_context_provider(|| Signal::new(Register::new()));
+    // live events keep the cached queries fresh
+    use_context_provider(|| Signal::new(Register::with_cache(event_cache.read().clone())));
 
     // hook: on mounted
     let on_mounted = move |_| {
@@ -114,27 +192,22 @@ pub fn App() -> Element {
                 db.save_relay_set(RelaySet {
                     name: DEFAULT_RELAY_SET_KEY.to_string(),
                     relays: vec!["wss://nos.lol".to_string(), "wss://nostr.wine".to_string()],
+                    auth: HashMap::new(),
                 })
                 .await
                 .unwrap();
             }
 
-            //init nostr db
-            let nostr_db = WebDatabase::open(NOSTR_DB_NAME).await.unwrap();
-
-            //init multiclient
+            //init multiclient, every relay set shares the connections of one pool
             let relay_sets: Vec<RelaySet> = db.get_all_relay_sets().await.unwrap();
             if !relay_sets.is_empty() {
-                let mut _multiclient = multiclient.write(); // Await the write lock
+                let _multiclient = multiclient.read().clone();
                 for rs in relay_sets {
                     let client = _multiclient.get_client(&rs.name).await;
                     if client.is_none() {
-                        let client_builder = ClientBuilder::new().database(nostr_db.clone());
-                        let c: nostr_sdk::Client = client_builder.build();
-                        c.add_relays(rs.relays).await.unwrap();
-                        c.connect().await;
-                        let hc = HashedClient::new(c).await;
-                        _multiclient.register(rs.name, hc).await;
+                        if let Err(e) = _multiclient.create(&rs).await {
+                            tracing::error!("relay set {} error: {:?}", rs.name, e);
+                        }
                     }
                 }
             }
@@ -202,6 +275,41 @@ pub fn App() -> Element {
             }
 
             router.set(rsx! {Router::<Route> {}});
+
+            // catch the kept alive subscriptions up before their feeds open
+            spawn_local({
+                let multiclient = multiclient.read().clone();
+                let subs = all_sub.read().clone();
+                async move {
+                    match sync_subs(&multiclient, &subs).await {
+                        Ok(report) => tracing::info!("subscription sync: {}", report),
+                        Err(e) => tracing::error!("subscription sync error: {:?}", e),
+                    }
+                }
+            });
+
+            // resume the last account, encrypted keys stay locked until the user enters the passphrase
+            let last_logined = db
+                .get_misc(LAST_LOGINED_KEY.to_string())
+                .await
+                .ok()
+                .flatten();
+            let last_user = all_users
+                .read()
+                .iter()
+                .find(|u| Some(&u.name) == last_logined.as_ref())
+                .cloned();
+            if let Some(user) = last_user {
+                if !user.inner.is_encrypted() {
+                    spawn(async move {
+                        if let Err(e) =
+                            switch_account(session, multiclient, cb_database_db, user, None).await
+                        {
+                            tracing::error!("resume account error: {:?}", e);
+                        }
+                    });
+                }
+            }
         });
     };
 
@@ -0,0 +1,374 @@
+use std::sync::{Arc, RwLock};
+
+use dashmap::DashMap;
+use nostr_sdk::{
+    ClientMessage, EventBuilder, EventId, NostrSigner, RelayMessage, RelayPoolNotification,
+    SubscriptionId, Url,
+};
+use thiserror::Error;
+use wasm_bindgen_futures::spawn_local;
+
+use super::multiclient::SharedPool;
+use crate::store::subscription::{AuthPolicy, RelaySet};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No signer to authenticate with")]
+    NoSigner,
+    #[error("{0} has not sent an AUTH challenge")]
+    NoChallenge(Url),
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Prefix of the CLOSED and OK messages of a relay that wants AUTH first
+pub const AUTH_REQUIRED: &str = "auth-required:";
+
+pub fn is_auth_required(message: &str) -> bool {
+    message.starts_with(AUTH_REQUIRED)
+}
+
+/// Where a relay stands with NIP-42
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthState {
+    /// Challenged, waiting for the user to accept
+    Challenged,
+    Authenticating,
+    Authenticated,
+    Failed(String),
+    /// Challenged but the session can't sign
+    NoSigner,
+    /// Challenged and the policy says never
+    Declined,
+}
+
+impl std::fmt::Display for AuthState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Challenged => write!(f, "authentication requested"),
+            Self::Authenticating => write!(f, "authenticating"),
+            Self::Authenticated => write!(f, "authenticated"),
+            Self::Failed(reason) => write!(f, "authentication failed: {}", reason),
+            Self::NoSigner => write!(f, "authentication needs an account that can sign"),
+            Self::Declined => write!(f, "authentication declined"),
+        }
+    }
+}
+
+/// Answers NIP-42 AUTH challenges of the relays of the [`SharedPool`] with
+/// the signer of the active session, following the policy of each relay.
+///
+/// Subscriptions a relay closed as auth-required are sent again once it
+/// accepted the AUTH event.
+#[derive(Debug, Clone, Default)]
+pub struct Authenticator {
+    signer: Arc<RwLock<Option<NostrSigner>>>,
+    policies: Arc<DashMap<Url, AuthPolicy>>,
+    challenges: Arc<DashMap<Url, String>>,
+    states: Arc<DashMap<Url, AuthState>>,
+    // AUTH events waiting for their OK
+    pending: Arc<DashMap<EventId, Url>>,
+    closed: Arc<DashMap<Url, Vec<SubscriptionId>>>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called on account switch, `None` when logged out or watch-only.
+    ///
+    /// The connections stay up, authenticated as the previous identity, so
+    /// every stored challenge is answered again: returns the relays to
+    /// authenticate with right away, the others wait on the user.
+    pub fn set_signer(&self, signer: Option<NostrSigner>) -> Vec<Url> {
+        *self.signer.write().unwrap() = signer;
+        self.states.clear();
+        // an OK for the previous identity must not count for the new one
+        self.pending.clear();
+        let mut now = Vec::new();
+        for entry in self.challenges.iter() {
+            match self.on_challenge(entry.key()) {
+                Some(state) => {
+                    self.states.insert(entry.key().clone(), state);
+                }
+                None => now.push(entry.key().clone()),
+            }
+        }
+        now
+    }
+
+    /// Switches the identity and authenticates as it where the policy says
+    /// always, in the background like [`Self::on_notification`]
+    pub fn change_signer(&self, pool: &SharedPool, signer: Option<NostrSigner>) {
+        for url in self.set_signer(signer) {
+            let authenticator = self.clone();
+            let pool = pool.clone();
+            spawn_local(async move {
+                if let Err(e) = authenticator.authenticate(&pool, &url).await {
+                    tracing::warn!("auth to {} failed: {:?}", url, e);
+                }
+            });
+        }
+    }
+
+    fn signer(&self) -> Option<NostrSigner> {
+        self.signer.read().unwrap().clone()
+    }
+
+    pub fn policy(&self, url: &Url) -> AuthPolicy {
+        self.policies
+            .get(url)
+            .map(|policy| *policy)
+            .unwrap_or_default()
+    }
+
+    /// One connection serves every set using a relay, the set applied
+    /// last decides its policy
+    pub fn set_policies(&self, relay_set: &RelaySet) {
+        for relay in relay_set.relays.iter() {
+            if let Ok(url) = Url::parse(relay) {
+                self.policies.insert(url, relay_set.auth_policy(relay));
+            }
+        }
+    }
+
+    pub fn state(&self, url: &Url) -> Option<AuthState> {
+        self.states.get(url).map(|state| state.clone())
+    }
+
+    /// Relays waiting on the user: challenged under `ask` or without signer
+    pub fn waiting(&self) -> Vec<(Url, AuthState)> {
+        self.states
+            .iter()
+            .filter(|entry| matches!(entry.value(), AuthState::Challenged | AuthState::NoSigner))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn forget(&self, url: &Url) {
+        self.challenges.remove(url);
+        self.states.remove(url);
+        self.closed.remove(url);
+    }
+
+    /// What to do about a challenge of `url` without asking anyone
+    fn on_challenge(&self, url: &Url) -> Option<AuthState> {
+        match self.policy(url) {
+            AuthPolicy::Never => Some(AuthState::Declined),
+            _ if self.signer().is_none() => Some(AuthState::NoSigner),
+            AuthPolicy::Ask => Some(AuthState::Challenged),
+            AuthPolicy::Always => None,
+        }
+    }
+
+    /// Signs the pending challenge of `url` and sends the AUTH event
+    pub async fn authenticate(&self, pool: &SharedPool, url: &Url) -> Result<()> {
+        let signer = self.signer().ok_or(Error::NoSigner)?;
+        let challenge = self
+            .challenges
+            .get(url)
+            .map(|challenge| challenge.clone())
+            .ok_or_else(|| Error::NoChallenge(url.clone()))?;
+        self.states.insert(url.clone(), AuthState::Authenticating);
+        let builder = EventBuilder::auth(challenge, url.clone());
+        let event = match signer.sign_event_builder(builder).await {
+            Ok(event) => event,
+            Err(e) => {
+                self.states
+                    .insert(url.clone(), AuthState::Failed(e.to_string()));
+                return Err(e.into());
+            }
+        };
+        self.pending.insert(event.id, url.clone());
+        pool.client()
+            .send_msg_to([url.clone()], ClientMessage::auth(event))
+            .await?;
+        Ok(())
+    }
+
+    /// Sends the subscriptions `url` closed for lack of AUTH again
+    async fn resubscribe(&self, pool: &SharedPool, url: &Url) -> Result<()> {
+        let Some((_, sub_ids)) = self.closed.remove(url) else {
+            return Ok(());
+        };
+        let relay = pool.client().relay(url.clone()).await?;
+        for sub_id in sub_ids {
+            if let Some(filters) = relay.subscription(&sub_id).await {
+                pool.client()
+                    .subscribe_with_id_to([url.clone()], sub_id, filters, None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the state from a pool notification, returns the relay to
+    /// authenticate with right away
+    pub fn handle(&self, notification: &RelayPoolNotification) -> Option<Url> {
+        let RelayPoolNotification::Message { relay_url, message } = notification else {
+            return None;
+        };
+        match message {
+            RelayMessage::Auth { challenge } => {
+                self.challenges.insert(relay_url.clone(), challenge.clone());
+                match self.on_challenge(relay_url) {
+                    Some(state) => {
+                        self.states.insert(relay_url.clone(), state);
+                        None
+                    }
+                    None => Some(relay_url.clone()),
+                }
+            }
+            RelayMessage::Ok {
+                event_id,
+                status,
+                message,
+            } => {
+                let (_, url) = self.pending.remove(event_id)?;
+                let state = if *status {
+                    AuthState::Authenticated
+                } else {
+                    AuthState::Failed(message.clone())
+                };
+                self.states.insert(url, state);
+                None
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } if is_auth_required(message) => {
+                self.closed
+                    .entry(relay_url.clone())
+                    .or_default()
+                    .push(subscription_id.clone());
+                // the challenge may come before or after the CLOSED
+                if self.state(relay_url).is_none() && self.challenges.contains_key(relay_url) {
+                    if let Some(state) = self.on_challenge(relay_url) {
+                        self.states.insert(relay_url.clone(), state);
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Answers a challenge among the notifications of `pool`, signing in
+    /// the background since a remote signer may take a while
+    pub fn on_notification(&self, pool: &SharedPool, notification: &RelayPoolNotification) {
+        let authenticator = self.clone();
+        let pool = pool.clone();
+        if let Some(url) = self.handle(notification) {
+            spawn_local(async move {
+                if let Err(e) = authenticator.authenticate(&pool, &url).await {
+                    tracing::warn!("auth to {} failed: {:?}", url, e);
+                }
+            });
+            return;
+        }
+        // an accepted AUTH reopens what the relay closed
+        if let RelayPoolNotification::Message {
+            relay_url,
+            message: RelayMessage::Ok { status: true, .. },
+        } = notification
+        {
+            if self.state(relay_url) == Some(AuthState::Authenticated) {
+                let relay_url = relay_url.clone();
+                spawn_local(async move {
+                    if let Err(e) = authenticator.resubscribe(&pool, &relay_url).await {
+                        tracing::warn!("resubscribe to {} failed: {:?}", relay_url, e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn challenge(url: &Url) -> RelayPoolNotification {
+        RelayPoolNotification::Message {
+            relay_url: url.clone(),
+            message: RelayMessage::Auth {
+                challenge: "challenge".to_string(),
+            },
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_challenge_follows_policy() {
+        let authenticator = Authenticator::new();
+        let url = Url::parse("wss://relay.damus.io").unwrap();
+        let mut relay_set = RelaySet::new(&0);
+        relay_set.relays.push(url.to_string());
+
+        // no signer, nothing to answer with
+        assert_eq!(authenticator.handle(&challenge(&url)), None);
+        assert_eq!(authenticator.state(&url), Some(AuthState::NoSigner));
+        assert_eq!(authenticator.waiting().len(), 1);
+
+        assert!(authenticator
+            .set_signer(Some(Keys::generate().into()))
+            .is_empty());
+        assert_eq!(authenticator.handle(&challenge(&url)), None);
+        assert_eq!(authenticator.state(&url), Some(AuthState::Challenged));
+
+        relay_set.auth.insert(url.to_string(), AuthPolicy::Always);
+        authenticator.set_policies(&relay_set);
+        assert_eq!(authenticator.handle(&challenge(&url)), Some(url.clone()));
+
+        relay_set.auth.insert(url.to_string(), AuthPolicy::Never);
+        authenticator.set_policies(&relay_set);
+        assert_eq!(authenticator.handle(&challenge(&url)), None);
+        assert_eq!(authenticator.state(&url), Some(AuthState::Declined));
+        assert!(authenticator.waiting().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signer_switch_answers_again() {
+        let authenticator = Authenticator::new();
+        let always = Url::parse("wss://relay.damus.io").unwrap();
+        let ask = Url::parse("wss://nos.lol").unwrap();
+        let mut relay_set = RelaySet::new(&0);
+        relay_set.relays.push(always.to_string());
+        relay_set.relays.push(ask.to_string());
+        relay_set
+            .auth
+            .insert(always.to_string(), AuthPolicy::Always);
+        authenticator.set_policies(&relay_set);
+        authenticator.set_signer(Some(Keys::generate().into()));
+        assert_eq!(
+            authenticator.handle(&challenge(&always)),
+            Some(always.clone())
+        );
+        assert_eq!(authenticator.handle(&challenge(&ask)), None);
+        authenticator
+            .states
+            .insert(always.clone(), AuthState::Authenticated);
+        authenticator
+            .states
+            .insert(ask.clone(), AuthState::Authenticated);
+
+        // the connections were authenticated as the previous account
+        let now = authenticator.set_signer(Some(Keys::generate().into()));
+        assert_eq!(now, vec![always.clone()]);
+        assert_eq!(authenticator.state(&always), None);
+        assert_eq!(authenticator.state(&ask), Some(AuthState::Challenged));
+
+        assert!(authenticator.set_signer(None).is_empty());
+        assert_eq!(authenticator.state(&always), Some(AuthState::NoSigner));
+        assert_eq!(authenticator.waiting().len(), 2);
+    }
+}
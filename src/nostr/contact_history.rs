@@ -0,0 +1,183 @@
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+use nostr_indexeddb::database::Order;
+use nostr_sdk::{Event, EventId, Filter, Kind, NostrSigner, PublicKey};
+use thiserror::Error;
+
+use super::contacts::{
+    contacts_of, local_versions, newest_contact_list, publish_contact_list, save_version,
+};
+use super::multiclient::HashedClient;
+use super::publish::PublishResult;
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    ContactList(#[from] super::contacts::Error),
+    #[error(transparent)]
+    Publish(#[from] super::publish::Error),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Who a contact list follows that the one before it did not, and the
+/// other way round
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ContactListDiff {
+    pub added: Vec<PublicKey>,
+    pub removed: Vec<PublicKey>,
+}
+
+impl ContactListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// One published version of a contact list
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactListRevision {
+    pub event: Event,
+    pub contacts: usize,
+    /// Against the version before it, everything added for the oldest
+    pub diff: ContactListDiff,
+}
+
+fn followed(event: &Event) -> BTreeSet<PublicKey> {
+    contacts_of(event)
+        .into_iter()
+        .map(|contact| contact.public_key)
+        .collect()
+}
+
+/// What changed from `older` to `newer`
+pub fn diff_versions(older: Option<&Event>, newer: &Event) -> ContactListDiff {
+    let before = older.map(followed).unwrap_or_default();
+    let after = followed(newer);
+    ContactListDiff {
+        added: after.difference(&before).copied().collect(),
+        removed: before.difference(&after).copied().collect(),
+    }
+}
+
+/// Versions from `events`, newest first, each diffed with the one before
+pub fn build_history(events: Vec<Event>) -> Vec<ContactListRevision> {
+    let mut seen: HashSet<EventId> = HashSet::new();
+    let mut events: Vec<Event> = events
+        .into_iter()
+        .filter(|event| event.kind() == Kind::ContactList && seen.insert(event.id))
+        .collect();
+    events.sort_by(|a, b| a.created_at().cmp(&b.created_at()));
+    let mut history: Vec<ContactListRevision> = Vec::with_capacity(events.len());
+    for (i, event) in events.iter().enumerate() {
+        let older = i.checked_sub(1).map(|i| &events[i]);
+        history.push(ContactListRevision {
+            event: event.clone(),
+            contacts: followed(event).len(),
+            diff: diff_versions(older, event),
+        });
+    }
+    history.reverse();
+    history
+}
+
+/// Every contact list of `public_key` the event database, the local
+/// history and the relays of `relay_set` still have. Those found on relays
+/// are kept locally, a relay may drop them once a newer one arrives.
+pub async fn contact_list_history(
+    relay_set: &HashedClient,
+    db: &CBWebDatabase,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Vec<ContactListRevision>> {
+    let filter = Filter::new().author(*public_key).kind(Kind::ContactList);
+    let mut events: Vec<Event> = relay_set
+        .database()
+        .query(vec![filter.clone()], Order::Desc)
+        .await
+        .unwrap_or_default();
+    events.extend(local_versions(db, public_key).await?);
+    let known: HashSet<EventId> = events.iter().map(|event| event.id).collect();
+    for event in relay_set.get_events_of(vec![filter], timeout).await? {
+        if !known.contains(&event.id) {
+            save_version(db, &event).await?;
+            events.push(event);
+        }
+    }
+    events.retain(|event| event.author() == *public_key);
+    Ok(build_history(events))
+}
+
+/// Publishes `version` again as the current contact list, with its content
+/// and all its tags as they were. Choosing a version is the confirmation,
+/// it may follow fewer accounts. The list it replaces is kept in the local
+/// history first, so a restore can be undone.
+pub async fn restore_contact_list(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    db: &CBWebDatabase,
+    version: &Event,
+    timeout: Option<Duration>,
+) -> Result<PublishResult> {
+    if let Some(current) = newest_contact_list(relay_set, db, &version.author(), timeout).await? {
+        save_version(db, &current).await?;
+    }
+    let tags = version.tags().to_vec();
+    let (_, result) =
+        publish_contact_list(relay_set, signer, db, Some(version), tags, 0, true).await?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys, Tag, Timestamp};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn contact_list(keys: &Keys, follows: &[PublicKey], created_at: u64) -> Event {
+        EventBuilder::new(
+            Kind::ContactList,
+            "",
+            follows.iter().map(|pk| Tag::public_key(*pk)),
+        )
+        .custom_created_at(Timestamp::from(created_at))
+        .to_event(keys)
+        .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_build_history() {
+        let me = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let first = contact_list(&me, &[alice], 1);
+        let second = contact_list(&me, &[alice, bob], 2);
+        let wiped = contact_list(&me, &[], 3);
+
+        let history = build_history(vec![
+            wiped.clone(),
+            first.clone(),
+            second.clone(),
+            first.clone(),
+        ]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].event, wiped);
+        assert_eq!(history[0].contacts, 0);
+        assert!(history[0].diff.added.is_empty());
+        assert_eq!(history[0].diff.removed.len(), 2);
+        assert_eq!(history[1].diff.added, vec![bob]);
+        assert!(history[1].diff.removed.is_empty());
+        assert_eq!(history[2].diff.added, vec![alice]);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use nostr_indexeddb::database::Order;
+use nostr_sdk::{
+    Contact, Event, EventBuilder, Filter, JsonUtil, Kind, NostrSigner, PublicKey, Tag, TagStandard,
+};
+use thiserror::Error;
+
+use super::multiclient::HashedClient;
+use super::publish::{send_and_queue, PublishResult};
+use crate::store::contact::ContactListVersion;
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Event(#[from] nostr_sdk::event::Error),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+    #[error("No contact list found, publishing would replace yours with a new one")]
+    NotFound,
+    #[error("The contact list would shrink from {before} to {after} contacts")]
+    Shrink { before: usize, after: usize },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The followed public key of a `p` tag
+fn followed(tag: &Tag) -> Option<PublicKey> {
+    match tag.clone().to_standardized() {
+        Some(TagStandard::PublicKey {
+            public_key,
+            uppercase: false,
+            ..
+        }) => Some(public_key),
+        _ => None,
+    }
+}
+
+/// How many accounts a kind 3 event follows
+pub fn count_contacts(event: &Event) -> usize {
+    event
+        .tags()
+        .iter()
+        .filter_map(followed)
+        .collect::<HashSet<PublicKey>>()
+        .len()
+}
+
+/// The contacts of a kind 3 event, with their relay hints and petnames
+pub fn contacts_of(event: &Event) -> Vec<Contact> {
+    event
+        .tags()
+        .iter()
+        .filter_map(|tag| match tag.clone().to_standardized() {
+            Some(TagStandard::PublicKey {
+                public_key,
+                relay_url,
+                alias,
+                uppercase: false,
+            }) => Some(Contact::new(public_key, relay_url, alias)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The tags of `base` with `contact` followed. An existing entry is kept
+/// as it is, with its petname and relay hint.
+pub fn with_contact(base: Option<&Event>, contact: Contact) -> Vec<Tag> {
+    let mut tags: Vec<Tag> = base.map(|event| event.tags().to_vec()).unwrap_or_default();
+    if !tags
+        .iter()
+        .any(|tag| followed(tag) == Some(contact.public_key))
+    {
+        tags.push(Tag::from_standardized(TagStandard::PublicKey {
+            public_key: contact.public_key,
+            relay_url: contact.relay_url,
+            alias: contact.alias,
+            uppercase: false,
+        }));
+    }
+    tags
+}
+
+/// The tags of `base` without `public_key`, everything else untouched
+pub fn without_contact(base: Option<&Event>, public_key: &PublicKey) -> Vec<Tag> {
+    base.map(|event| {
+        event
+            .tags()
+            .iter()
+            .filter(|tag| followed(tag) != Some(*public_key))
+            .cloned()
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The tags of `base` with its contacts replaced by `contacts`
+pub fn replace_contacts(base: Option<&Event>, contacts: Vec<Contact>) -> Vec<Tag> {
+    let mut tags: Vec<Tag> = base
+        .map(|event| {
+            event
+                .tags()
+                .iter()
+                .filter(|tag| followed(tag).is_none())
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    tags.extend(contacts.into_iter().map(|contact| {
+        Tag::from_standardized(TagStandard::PublicKey {
+            public_key: contact.public_key,
+            relay_url: contact.relay_url,
+            alias: contact.alias,
+            uppercase: false,
+        })
+    }));
+    tags
+}
+
+/// Keeps `event` in the local history of contact lists
+pub async fn save_version(db: &CBWebDatabase, event: &Event) -> Result<()> {
+    db.save_contact_list(ContactListVersion {
+        id: event.id.to_hex(),
+        author: event.author().to_hex(),
+        created_at: event.created_at().as_u64(),
+        event: event.as_json(),
+    })
+    .await?;
+    Ok(())
+}
+
+/// Contact lists of `public_key` kept in the local history, newest first
+pub async fn local_versions(db: &CBWebDatabase, public_key: &PublicKey) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for version in db.get_contact_lists(&public_key.to_hex()).await? {
+        events.push(Event::from_json(&version.event)?);
+    }
+    Ok(events)
+}
+
+/// The newest contact list of `public_key` known to the event database,
+/// the local history and every relay of `relay_set`. Waiting for all
+/// relays matters: a fast relay with an old list must not win over a slow one.
+pub async fn newest_contact_list(
+    relay_set: &HashedClient,
+    db: &CBWebDatabase,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Option<Event>> {
+    let filter = Filter::new().author(*public_key).kind(Kind::ContactList);
+    let mut versions: Vec<Event> = relay_set
+        .database()
+        .query(vec![filter.clone()], Order::Desc)
+        .await
+        .unwrap_or_default();
+    versions.extend(local_versions(db, public_key).await?);
+    // better no edit at all than one on top of a list we failed to fetch
+    versions.extend(relay_set.get_events_of(vec![filter], timeout).await?);
+    Ok(versions
+        .into_iter()
+        .filter(|event| event.author() == *public_key && event.kind() == Kind::ContactList)
+        .max_by_key(|event| event.created_at()))
+}
+
+/// Refuses a list of `next` contacts replacing one of `before` when it
+/// loses more than the `removed` the user asked for
+pub fn check_shrink(before: usize, next: usize, removed: usize) -> Result<()> {
+    if next + removed < before {
+        return Err(Error::Shrink {
+            before,
+            after: next,
+        });
+    }
+    Ok(())
+}
+
+/// Publishes `tags` as the contact list replacing `base`, keeping its
+/// content (the legacy relay map). Unless `confirmed`, refuses to start a
+/// list from nothing, to drop more than `removed` contacts, or to build on
+/// a list smaller than the last one published from here, which is what a
+/// wipe by another client looks like.
+pub async fn publish_contact_list(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    db: &CBWebDatabase,
+    base: Option<&Event>,
+    tags: Vec<Tag>,
+    removed: usize,
+    confirmed: bool,
+) -> Result<(Event, PublishResult)> {
+    let public_key = signer.public_key().await?;
+    let next = tags
+        .iter()
+        .filter_map(followed)
+        .collect::<HashSet<_>>()
+        .len();
+    if !confirmed {
+        let Some(base) = base else {
+            return Err(Error::NotFound);
+        };
+        check_shrink(count_contacts(base), next, removed)?;
+        if let Some(last) = local_versions(db, &public_key).await?.first() {
+            if last.id != base.id {
+                check_shrink(count_contacts(last), next, removed + 1)?;
+            }
+        }
+    }
+    if let Some(base) = base {
+        save_version(db, base).await?;
+    }
+    let content = base.map(|event| event.content.clone()).unwrap_or_default();
+    let builder = EventBuilder::new(Kind::ContactList, content, tags);
+    let event = signer.sign_event_builder(builder).await?;
+    // kept before sending, a failed send must not lose it
+    save_version(db, &event).await?;
+    let result = send_and_queue(relay_set, &event).await;
+    Ok((event, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{Keys, UncheckedUrl};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_edit_keeps_contacts() {
+        let me = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let content = r#"{"wss://nos.lol":{"read":true,"write":true}}"#;
+        let base = EventBuilder::new(
+            Kind::ContactList,
+            content,
+            [Tag::from_standardized(TagStandard::PublicKey {
+                public_key: alice,
+                relay_url: Some(UncheckedUrl::from("wss://nos.lol")),
+                alias: Some("alice".to_string()),
+                uppercase: false,
+            })],
+        )
+        .to_event(&me)
+        .unwrap();
+
+        let tags = with_contact(Some(&base), Contact::new(bob, None, None::<String>));
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0], base.tags()[0]);
+        // following again keeps the petname
+        let tags = with_contact(Some(&base), Contact::new(alice, None, None::<String>));
+        assert_eq!(tags, base.tags().to_vec());
+
+        assert!(without_contact(Some(&base), &alice).is_empty());
+        assert_eq!(without_contact(Some(&base), &bob), base.tags().to_vec());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_check_shrink() {
+        assert!(check_shrink(300, 301, 0).is_ok());
+        assert!(check_shrink(300, 299, 1).is_ok());
+        assert!(matches!(
+            check_shrink(300, 2, 1),
+            Err(Error::Shrink {
+                before: 300,
+                after: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use gloo_timers::future::TimeoutFuture;
+use nostr_sdk::{Event, JsonUtil, RelayPoolNotification, RelayStatus, Timestamp, Url};
+use thiserror::Error;
+use wasm_bindgen_futures::spawn_local;
+
+use super::multiclient::SharedPool;
+use super::publish::{PublishResult, RelayOutcome};
+use crate::store::pending::PendingEvent;
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+    #[error(transparent)]
+    Event(#[from] nostr_sdk::event::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// a relay still failing after this many tries is given up on
+const MAX_ATTEMPTS: u32 = 12;
+const RETRY_BASE: Duration = Duration::from_secs(10);
+const RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+const RETRY_TICK_MS: u32 = 15_000;
+
+/// Wait after the `attempts`th failed try: 10s doubling up to an hour
+pub fn retry_backoff(attempts: u32) -> Duration {
+    let exp = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE * 2u32.pow(exp)).min(RETRY_MAX)
+}
+
+/// Keeps the relays of `result` that may still take `event`, replacing
+/// what was queued for it. Returns whether anything is left to send.
+pub async fn queue_failed(
+    db: &CBWebDatabase,
+    event: &Event,
+    result: &PublishResult,
+    attempts: u32,
+) -> Result<bool> {
+    let relays = result.retry_relays();
+    let id = event.id.to_hex();
+    if relays.is_empty() || attempts >= MAX_ATTEMPTS {
+        db.remove_pending_event(id).await?;
+        return Ok(false);
+    }
+    let last_error = result.relays.values().find_map(|outcome| match outcome {
+        RelayOutcome::Failed(reason) => Some(reason.clone()),
+        _ => None,
+    });
+    let pending = PendingEvent {
+        id,
+        event: event.as_json(),
+        relays: relays.iter().map(|url| url.to_string()).collect(),
+        attempts,
+        next_attempt: Timestamp::now().as_u64() + retry_backoff(attempts).as_secs(),
+        last_error,
+    };
+    db.save_pending_event(pending).await?;
+    Ok(true)
+}
+
+/// Sends the queued events whose retry is due, or those owed to `relay`
+/// right away when it just came back
+pub async fn retry_pending(
+    db: &CBWebDatabase,
+    pool: &SharedPool,
+    relay: Option<&Url>,
+) -> Result<Vec<PublishResult>> {
+    let now = Timestamp::now().as_u64();
+    let mut results = Vec::new();
+    for pending in db.get_pending_events().await? {
+        if relay.is_none() && pending.next_attempt > now {
+            continue;
+        }
+        let relays: Vec<Url> = pending
+            .relays
+            .iter()
+            .filter_map(|url| Url::parse(url).ok())
+            .filter(|url| relay.map_or(true, |relay| relay == url))
+            .collect();
+        if relays.is_empty() {
+            continue;
+        }
+        let event = Event::from_json(&pending.event)?;
+        let result = match pool
+            .client()
+            .send_event_to(relays.clone(), event.clone())
+            .await
+        {
+            Ok(output) => PublishResult::new(event.id, output.success, output.failed),
+            Err(e) => PublishResult::failed(event.id, &relays, &e.to_string()),
+        };
+        // relays not tried this time still owe it
+        let mut remaining = result.clone();
+        for url in pending.relays.iter().filter_map(|url| Url::parse(url).ok()) {
+            if !relays.contains(&url) {
+                let reason = pending.last_error.clone().unwrap_or_default();
+                remaining.relays.insert(url, RelayOutcome::Failed(reason));
+            }
+        }
+        queue_failed(db, &event, &remaining, pending.attempts + 1).await?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Retries the queued events whose retry is due for as long as the app runs
+pub fn watch(pool: SharedPool) {
+    spawn_local(async move {
+        let db = match pool.pending_db().await {
+            Ok(db) => db,
+            Err(e) => {
+                tracing::warn!("pending events unavailable: {:?}", e);
+                return;
+            }
+        };
+        loop {
+            if let Err(e) = retry_pending(&db, &pool, None).await {
+                tracing::warn!("retrying pending events failed: {:?}", e);
+            }
+            TimeoutFuture::new(RETRY_TICK_MS).await;
+        }
+    });
+}
+
+/// Sends a relay that just reconnected the events queued for it
+pub fn on_notification(pool: &SharedPool, notification: &RelayPoolNotification) {
+    let RelayPoolNotification::RelayStatus {
+        relay_url,
+        status: RelayStatus::Connected,
+    } = notification
+    else {
+        return;
+    };
+    let pool = pool.clone();
+    let relay_url = relay_url.clone();
+    spawn_local(async move {
+        let db = match pool.pending_db().await {
+            Ok(db) => db,
+            Err(e) => {
+                tracing::warn!("pending events unavailable: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = retry_pending(&db, &pool, Some(&relay_url)).await {
+            tracing::warn!("sending pending events to {} failed: {:?}", relay_url, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), Duration::from_secs(10));
+        assert_eq!(retry_backoff(2), Duration::from_secs(20));
+        assert_eq!(retry_backoff(4), Duration::from_secs(80));
+        assert_eq!(retry_backoff(MAX_ATTEMPTS), RETRY_MAX);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use nostr_sdk::{Event, Filter, Kind, PublicKey, TagStandard, Url};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::fetch::get_following;
+use super::monitor::{RelayHealth, RelayMonitor};
+use super::multiclient::{HashedClient, MultiClient};
+use super::outbox::RelayList;
+use crate::store::subscription::RelaySet;
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Fetch error: {0}")]
+    Fetch(String),
+    #[error(transparent)]
+    Outbox(#[from] super::outbox::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// more relays cost connections for little extra coverage
+pub const DEFAULT_MAX_RELAYS: usize = 8;
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a relay does for the follows
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayScore {
+    pub url: Url,
+    /// Follows writing there
+    pub coverage: usize,
+    /// What the monitor knows, `None` if we never connected
+    pub health: Option<RelayHealth>,
+}
+
+/// A relay set suggested from the social graph
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recommendation {
+    /// Every relay hinted at, best first
+    pub ranked: Vec<RelayScore>,
+    /// The few relays reaching as many follows as possible
+    pub cover: Vec<Url>,
+    pub follows: usize,
+    /// Follows none of `cover` reaches, including those without hints
+    pub uncovered: Vec<PublicKey>,
+}
+
+impl Recommendation {
+    pub fn covered(&self) -> usize {
+        self.follows - self.uncovered.len()
+    }
+}
+
+/// `wss://relay.example`, without the root slash `Url` adds
+pub fn relay_string(url: &Url) -> String {
+    url.as_str().trim_end_matches('/').to_string()
+}
+
+/// Relay hints from kind 3 events: the relay given with each p tag of
+/// `own` and the legacy relay map in the content of the follows' lists
+pub fn contact_list_hints(
+    own: Option<&Event>,
+    contact_lists: &[Event],
+) -> HashMap<PublicKey, BTreeSet<Url>> {
+    let mut hints: HashMap<PublicKey, BTreeSet<Url>> = HashMap::new();
+    if let Some(own) = own {
+        for tag in own.tags() {
+            if let Some(TagStandard::PublicKey {
+                public_key,
+                relay_url: Some(relay_url),
+                uppercase: false,
+                ..
+            }) = tag.clone().to_standardized()
+            {
+                if let Ok(url) = Url::parse(&relay_url.to_string()) {
+                    hints.entry(public_key).or_default().insert(url);
+                }
+            }
+        }
+    }
+    for event in contact_lists {
+        let Ok(Value::Object(relays)) = serde_json::from_str::<Value>(&event.content) else {
+            continue;
+        };
+        for (relay, usage) in relays {
+            // {"wss://..": {"read": true, "write": true}}
+            if usage.get("write").and_then(Value::as_bool) == Some(false) {
+                continue;
+            }
+            if let Ok(url) = Url::parse(&relay) {
+                hints.entry(event.author()).or_default().insert(url);
+            }
+        }
+    }
+    hints
+}
+
+/// NIP-65 write relays replace the kind 3 hints of an author
+pub fn merge_relay_lists(
+    hints: &mut HashMap<PublicKey, BTreeSet<Url>>,
+    relay_lists: &HashMap<PublicKey, RelayList>,
+) {
+    for (author, list) in relay_lists {
+        if !list.write.is_empty() {
+            hints.insert(*author, list.write.iter().cloned().collect());
+        }
+    }
+}
+
+/// Relays by coverage; dead relays last, faster first on a tie
+pub fn rank(
+    hints: &HashMap<PublicKey, BTreeSet<Url>>,
+    monitor: Option<&RelayMonitor>,
+) -> Vec<RelayScore> {
+    let mut coverage: HashMap<&Url, usize> = HashMap::new();
+    for urls in hints.values() {
+        for url in urls {
+            *coverage.entry(url).or_default() += 1;
+        }
+    }
+    let mut ranked: Vec<RelayScore> = coverage
+        .into_iter()
+        .map(|(url, coverage)| RelayScore {
+            url: url.clone(),
+            coverage,
+            health: monitor.and_then(|m| m.get(url)),
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        let dead = |s: &RelayScore| s.health.as_ref().map_or(false, |h| h.is_dead());
+        let latency = |s: &RelayScore| {
+            s.health
+                .as_ref()
+                .and_then(|h| h.latency)
+                .unwrap_or(Duration::MAX)
+        };
+        dead(a)
+            .cmp(&dead(b))
+            .then(b.coverage.cmp(&a.coverage))
+            .then(latency(a).cmp(&latency(b)))
+            .then(a.url.cmp(&b.url))
+    });
+    ranked
+}
+
+/// Greedy set cover over `ranked`: keeps taking the relay that reaches the
+/// most follows not reached yet, skipping dead relays
+pub fn minimal_cover(
+    hints: &HashMap<PublicKey, BTreeSet<Url>>,
+    ranked: &[RelayScore],
+    max_relays: usize,
+) -> Vec<Url> {
+    let mut uncovered: HashSet<&PublicKey> = hints.keys().collect();
+    let mut cover: Vec<Url> = Vec::new();
+    while !uncovered.is_empty() && cover.len() < max_relays {
+        let best = ranked
+            .iter()
+            .filter(|s| !s.health.as_ref().map_or(false, |h| h.is_dead()))
+            .filter(|s| !cover.contains(&s.url))
+            .map(|s| {
+                let gain = uncovered
+                    .iter()
+                    .filter(|pk| hints[**pk].contains(&s.url))
+                    .count();
+                (s, gain)
+            })
+            // first of the ranked order wins a tie
+            .fold(
+                None,
+                |best: Option<(&RelayScore, usize)>, (s, gain)| match best {
+                    Some((_, best_gain)) if best_gain >= gain => best,
+                    _ => Some((s, gain)),
+                },
+            );
+        let Some((score, gain)) = best else {
+            break;
+        };
+        if gain == 0 {
+            break;
+        }
+        uncovered.retain(|pk| !hints[*pk].contains(&score.url));
+        cover.push(score.url.clone());
+    }
+    cover
+}
+
+/// Suggests relays for the follows of `public_key`
+pub async fn discover(
+    multiclient: &MultiClient,
+    hc: &HashedClient,
+    public_key: &PublicKey,
+    max_relays: usize,
+) -> Result<Recommendation> {
+    let follows: Vec<PublicKey> = get_following(hc, public_key, Some(DISCOVERY_TIMEOUT))
+        .await
+        .map_err(|e| Error::Fetch(e.to_string()))?
+        .iter()
+        .filter_map(|hex| PublicKey::from_str(hex).ok())
+        .collect();
+
+    let mut authors = follows.clone();
+    authors.push(*public_key);
+    let filter = Filter::new().kind(Kind::ContactList).authors(authors);
+    let contact_lists = hc
+        .get_events_of(vec![filter], Some(DISCOVERY_TIMEOUT))
+        .await?;
+    let own = contact_lists
+        .iter()
+        .filter(|e| e.author() == *public_key)
+        .max_by_key(|e| e.created_at());
+    let mut hints = contact_list_hints(own, &contact_lists);
+    hints.retain(|author, _| follows.contains(author));
+
+    let relay_lists = multiclient.outbox().relay_lists(hc, &follows).await?;
+    merge_relay_lists(&mut hints, &relay_lists);
+
+    let monitor = multiclient.monitor().await.ok();
+    let ranked = rank(&hints, monitor.as_ref());
+    let cover = minimal_cover(&hints, &ranked, max_relays);
+    let uncovered = follows
+        .iter()
+        .filter(|pk| {
+            hints
+                .get(*pk)
+                .map_or(true, |urls| !cover.iter().any(|url| urls.contains(url)))
+        })
+        .copied()
+        .collect();
+    Ok(Recommendation {
+        ranked,
+        cover,
+        follows: follows.len(),
+        uncovered,
+    })
+}
+
+/// Creates the relay set `name` with `relays`, or replaces the relays of
+/// the existing one keeping its auth policies, and reconnects it
+pub async fn apply_recommendation(
+    multiclient: &MultiClient,
+    db: &CBWebDatabase,
+    name: &str,
+    relays: &[Url],
+) -> Result<RelaySet> {
+    let mut relay_set = match db.get_relay_set(name.to_string()).await {
+        Ok(relay_set) => relay_set,
+        Err(CBwebDatabaseError::NotFound) => RelaySet {
+            name: name.to_string(),
+            relays: vec![],
+            auth: HashMap::new(),
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let relays: Vec<String> = relays.iter().map(relay_string).collect();
+    relay_set.auth.retain(|relay, _| relays.contains(relay));
+    relay_set.relays = relays;
+    db.save_relay_set(relay_set.clone()).await?;
+    multiclient.remove(name).await?;
+    multiclient.create(&relay_set).await?;
+    Ok(relay_set)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys, Tag, UncheckedUrl};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_contact_list_hints() {
+        let me = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let own = EventBuilder::new(
+            Kind::ContactList,
+            "",
+            [Tag::from_standardized(TagStandard::PublicKey {
+                public_key: alice.public_key(),
+                relay_url: Some(UncheckedUrl::from("wss://nos.lol")),
+                alias: None,
+                uppercase: false,
+            })],
+        )
+        .to_event(&me)
+        .unwrap();
+        let bob_list = EventBuilder::new(
+            Kind::ContactList,
+            r#"{"wss://relay.damus.io":{"read":true,"write":true},"wss://inbox.only":{"read":true,"write":false}}"#,
+            [],
+        )
+        .to_event(&bob)
+        .unwrap();
+
+        let hints = contact_list_hints(Some(&own), &[bob_list]);
+        assert_eq!(
+            hints[&alice.public_key()],
+            BTreeSet::from([url("wss://nos.lol")])
+        );
+        assert_eq!(
+            hints[&bob.public_key()],
+            BTreeSet::from([url("wss://relay.damus.io")])
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_minimal_cover() {
+        let a = Keys::generate().public_key();
+        let b = Keys::generate().public_key();
+        let c = Keys::generate().public_key();
+        let popular = url("wss://popular.relay");
+        let niche = url("wss://niche.relay");
+        let extra = url("wss://extra.relay");
+        let hints = HashMap::from([
+            (a, BTreeSet::from([popular.clone(), extra.clone()])),
+            (b, BTreeSet::from([popular.clone()])),
+            (c, BTreeSet::from([niche.clone(), extra.clone()])),
+        ]);
+        let ranked = rank(&hints, None);
+        assert_eq!(ranked[0].url, extra);
+        assert_eq!(ranked[0].coverage, 2);
+
+        let cover = minimal_cover(&hints, &ranked, DEFAULT_MAX_RELAYS);
+        assert_eq!(cover.len(), 2);
+        for pk in [a, b, c] {
+            assert!(cover.iter().any(|url| hints[&pk].contains(url)));
+        }
+        assert_eq!(minimal_cover(&hints, &ranked, 1).len(), 1);
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use nostr_sdk::nips::nip59;
+use nostr_sdk::{
+    Event, EventBuilder, EventId, Filter, JsonUtil, Kind, NostrSigner, PublicKey, TagStandard,
+    Timestamp, UnsignedEvent,
+};
+use thiserror::Error;
+
+use super::fetch::DecryptedMsg;
+use super::multiclient::HashedClient;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    Event(#[from] nostr_sdk::event::Error),
+    #[error(transparent)]
+    Unsigned(#[from] nostr_sdk::event::unsigned::Error),
+    #[error(transparent)]
+    Builder(#[from] nostr_sdk::event::builder::Error),
+    #[error("Event is not a gift wrap")]
+    NotGiftWrap,
+    #[error("Gift wrap does not contain a seal")]
+    NotSeal,
+    #[error("Seal and rumor authors do not match")]
+    AuthorMismatch,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Seals `rumor` for `receiver` and wraps it in a kind 1059 event signed by a random key
+pub(crate) async fn gift_wrap(
+    signer: &NostrSigner,
+    receiver: &PublicKey,
+    rumor: &UnsignedEvent,
+) -> Result<Event> {
+    let content = signer.nip44_encrypt(*receiver, rumor.as_json()).await?;
+    let seal = EventBuilder::new(Kind::Seal, content, [])
+        .custom_created_at(Timestamp::tweaked(nip59::RANGE_RANDOM_TIMESTAMP_TWEAK));
+    let seal = signer.sign_event_builder(seal).await?;
+    Ok(EventBuilder::gift_wrap_from_seal(receiver, &seal, None)?)
+}
+
+/// Opens a kind 1059 gift wrap addressed to the signer and returns the inner rumor
+pub async fn unwrap_gift_wrap(signer: &NostrSigner, gift_wrap: &Event) -> Result<DecryptedMsg> {
+    if gift_wrap.kind != Kind::GiftWrap {
+        return Err(Error::NotGiftWrap);
+    }
+
+    let seal = signer
+        .nip44_decrypt(gift_wrap.author(), &gift_wrap.content)
+        .await?;
+    let seal = Event::from_json(seal)?;
+    if seal.kind != Kind::Seal {
+        return Err(Error::NotSeal);
+    }
+    seal.verify()?;
+
+    let rumor = signer.nip44_decrypt(seal.author(), &seal.content).await?;
+    let rumor = UnsignedEvent::from_json(rumor)?;
+    // the seal signature is the only proof of authorship
+    if rumor.pubkey != seal.author() {
+        return Err(Error::AuthorMismatch);
+    }
+
+    Ok(DecryptedMsg {
+        id: EventId::new(
+            &rumor.pubkey,
+            &rumor.created_at,
+            &rumor.kind,
+            &rumor.tags,
+            &rumor.content,
+        ),
+        pubkey: rumor.pubkey,
+        created_at: rumor.created_at,
+        kind: rumor.kind,
+        tags: rumor.tags,
+        content: Some(rumor.content),
+    })
+}
+
+/// A page of the gift wraps addressed to the signer with the NIP-17 direct
+/// messages they carry. Gift wraps page by their own `created_at`, tweaked
+/// into the past, not by the time of the messages inside.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GiftWrapPage {
+    pub msgs: Vec<DecryptedMsg>,
+    /// `created_at` of the oldest gift wrap, the next older page ends below it
+    pub oldest: Option<Timestamp>,
+    /// The relays returned as many gift wraps as asked, older ones may follow
+    pub full: bool,
+}
+
+/// How far back the gift wraps of the messages sent after `since` can be dated
+pub fn wrapped_since(since: Timestamp) -> Timestamp {
+    since - nip59::RANGE_RANDOM_TIMESTAMP_TWEAK.end
+}
+
+/// Fetches the newest `limit` gift wraps addressed to the signer dated
+/// within `[since, until]` and unwraps the direct messages
+pub async fn get_private_msgs(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+    limit: usize,
+    timeout: Option<Duration>,
+) -> Result<GiftWrapPage> {
+    let public_key = signer.public_key().await?;
+    let mut filter = Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(public_key)
+        .limit(limit);
+    if let Some(since) = since {
+        filter = filter.since(since);
+    }
+    if let Some(until) = until {
+        filter = filter.until(until);
+    }
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
+
+    let mut page = GiftWrapPage {
+        oldest: events.iter().map(|event| event.created_at()).min(),
+        full: events.len() >= limit,
+        ..Default::default()
+    };
+    for event in events.iter() {
+        match unwrap_gift_wrap(signer, event).await {
+            Ok(msg) if msg.kind == Kind::PrivateDirectMessage => page.msgs.push(msg),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("unwrap gift wrap {} failed: {:?}", event.id, e),
+        }
+    }
+    Ok(page)
+}
+
+/// The other participants of a conversation
+pub type ConversationKey = BTreeSet<PublicKey>;
+
+/// Stable string form of a [`ConversationKey`], used to persist read markers
+pub fn conversation_id(key: &ConversationKey) -> String {
+    key.iter()
+        .map(|pk| pk.to_hex())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversation {
+    pub key: ConversationKey,
+    /// Messages ordered from oldest to newest
+    pub messages: Vec<DecryptedMsg>,
+}
+
+impl Conversation {
+    fn new(key: ConversationKey) -> Self {
+        Self {
+            key,
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn last_message(&self) -> Option<&DecryptedMsg> {
+        self.messages.last()
+    }
+
+    fn insert(&mut self, msg: DecryptedMsg) -> bool {
+        if self.messages.iter().any(|m| m.id == msg.id) {
+            return false;
+        }
+        let pos = self
+            .messages
+            .partition_point(|m| m.created_at <= msg.created_at);
+        self.messages.insert(pos, msg);
+        true
+    }
+}
+
+/// All conversations of one account, grouped by participants
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversations {
+    me: PublicKey,
+    inner: HashMap<ConversationKey, Conversation>,
+}
+
+impl Conversations {
+    pub fn new(me: PublicKey) -> Self {
+        Self {
+            me,
+            inner: HashMap::new(),
+        }
+    }
+
+    pub fn me(&self) -> PublicKey {
+        self.me
+    }
+
+    /// Author and `p` tags without ourselves; a note to self keeps our own key
+    pub fn key_for(&self, msg: &DecryptedMsg) -> ConversationKey {
+        let mut key: ConversationKey = msg
+            .tags
+            .iter()
+            .filter_map(|tag| match tag.clone().to_standardized() {
+                Some(TagStandard::PublicKey { public_key, .. }) => Some(public_key),
+                _ => None,
+            })
+            .collect();
+        key.insert(msg.pubkey);
+        if key.len() > 1 {
+            key.remove(&self.me);
+        }
+        key
+    }
+
+    /// Returns false if the message was already known
+    pub fn insert(&mut self, msg: DecryptedMsg) -> bool {
+        let key = self.key_for(&msg);
+        self.inner
+            .entry(key.clone())
+            .or_insert_with(|| Conversation::new(key))
+            .insert(msg)
+    }
+
+    pub fn extend(&mut self, msgs: impl IntoIterator<Item = DecryptedMsg>) {
+        for msg in msgs {
+            self.insert(msg);
+        }
+    }
+
+    pub fn get(&self, key: &ConversationKey) -> Option<&Conversation> {
+        self.inner.get(key)
+    }
+
+    /// Conversations ordered by their most recent message
+    pub fn sorted(&self) -> Vec<&Conversation> {
+        let mut list: Vec<&Conversation> = self.inner.values().collect();
+        list.sort_by_key(|c| std::cmp::Reverse(c.last_message().map(|m| m.created_at)));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Keys;
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_gift_wrap_roundtrip() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let alice_signer: NostrSigner = alice.clone().into();
+        let bob_signer: NostrSigner = bob.clone().into();
+
+        let rumor = EventBuilder::private_msg_rumor(bob.public_key(), "hi bob", None)
+            .to_unsigned_event(alice.public_key());
+        let wrapped = gift_wrap(&alice_signer, &bob.public_key(), &rumor)
+            .await
+            .unwrap();
+        assert_eq!(wrapped.kind, Kind::GiftWrap);
+        assert_ne!(wrapped.author(), alice.public_key());
+
+        let msg = unwrap_gift_wrap(&bob_signer, &wrapped).await.unwrap();
+        assert_eq!(msg.pubkey, alice.public_key());
+        assert_eq!(msg.kind, Kind::PrivateDirectMessage);
+        assert_eq!(msg.content.as_deref(), Some("hi bob"));
+
+        // only the receiver can open it
+        let eve: NostrSigner = Keys::generate().into();
+        assert!(unwrap_gift_wrap(&eve, &wrapped).await.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_conversations() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let alice_signer: NostrSigner = alice.clone().into();
+        let bob_signer: NostrSigner = bob.clone().into();
+
+        let sent = EventBuilder::private_msg_rumor(bob.public_key(), "ping", None)
+            .custom_created_at(Timestamp::from(100))
+            .to_unsigned_event(alice.public_key());
+        let sent = gift_wrap(&alice_signer, &alice.public_key(), &sent)
+            .await
+            .unwrap();
+        let received = EventBuilder::private_msg_rumor(alice.public_key(), "pong", None)
+            .custom_created_at(Timestamp::from(200))
+            .to_unsigned_event(bob.public_key());
+        let received = gift_wrap(&bob_signer, &alice.public_key(), &received)
+            .await
+            .unwrap();
+
+        let mut conversations = Conversations::new(alice.public_key());
+        let pong = unwrap_gift_wrap(&alice_signer, &received).await.unwrap();
+        assert!(conversations.insert(pong.clone()));
+        assert!(!conversations.insert(pong));
+        conversations.insert(unwrap_gift_wrap(&alice_signer, &sent).await.unwrap());
+
+        let list = conversations.sorted();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].key, BTreeSet::from([bob.public_key()]));
+        let contents: Vec<_> = list[0]
+            .messages
+            .iter()
+            .filter_map(|m| m.content.as_deref())
+            .collect();
+        assert_eq!(contents, vec!["ping", "pong"]);
+    }
+}
@@ -1,14 +1,17 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
-use futures::{Future, StreamExt};
+use dashmap::DashMap;
+use futures::StreamExt;
 use gloo_timers::future::TimeoutFuture;
 use nostr_indexeddb::database::Order;
+use nostr_sdk::bitcoin::hashes::sha256::Hash as Sha256Hash;
+use nostr_sdk::hashes::Hash;
 use nostr_sdk::{
-    Client, Event, EventId, Filter, JsonUtil, Kind, Metadata, NostrSigner, PublicKey, Tag,
-    TagStandard, Timestamp,
+    Event, EventId, Filter, JsonUtil, Kind, Metadata, NostrSigner, PublicKey, Tag, TagStandard,
+    Timestamp,
 };
 use thiserror::Error;
 use tokio::sync::{mpsc, Mutex};
@@ -16,7 +19,12 @@ use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::Stream;
 use wasm_bindgen_futures::spawn_local;
 
-use super::utils::{get_newest_event, get_oldest_event};
+use super::multiclient::HashedClient;
+use super::outbox::Outbox;
+use super::utils::{
+    bolt11_amount_msats, bolt11_description_hash, get_newest_event, get_oldest_event, hash_filter,
+};
+use super::zap::zap_receipt_signer;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -31,11 +39,17 @@ pub enum Error {
     #[error(transparent)]
     Signer(#[from] nostr_sdk::signer::Error),
     #[error(transparent)]
+    Outbox(#[from] super::outbox::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
     Database(#[from] nostr_indexeddb::database::DatabaseError),
     #[error(transparent)]
     ChannelSend(#[from] tokio::sync::mpsc::error::TrySendError<String>),
     #[error("Event not found")]
     EventNotFound,
+    #[error("Invalid zap receipt: {0}")]
+    InvalidZapReceipt(String),
 }
 type Result<T> = std::result::Result<T, Error>;
 
@@ -48,13 +62,13 @@ macro_rules! create_encrypted_filters {
                 .pubkey($public_key),
             Filter::new()
                 .kind($kind)
-                .author($author)
-                .pubkey($public_key),
+                .author($public_key)
+                .pubkey($author),
         )
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DecryptedMsg {
     /// Id
     pub id: EventId,
@@ -83,133 +97,310 @@ impl From<Event> for DecryptedMsg {
     }
 }
 
+/// Time ranges the relays fully answered a query for, in seconds, both
+/// ends included
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    // sorted and disjoint
+    ranges: Vec<(u64, u64)>,
+}
+
+impl Coverage {
+    pub fn insert(&mut self, start: u64, end: u64) {
+        if start > end {
+            return;
+        }
+        let (mut start, mut end) = (start, end);
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for &(s, e) in self.ranges.iter() {
+            // adjacent seconds join, anything further apart is a gap
+            if e.saturating_add(1) < start || end.saturating_add(1) < s {
+                ranges.push((s, e));
+            } else {
+                start = start.min(s);
+                end = end.max(e);
+            }
+        }
+        ranges.push((start, end));
+        ranges.sort_unstable();
+        self.ranges = ranges;
+    }
+
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        self.ranges.iter().any(|&(s, e)| s <= start && end <= e)
+    }
+}
+
+// what the relays answered for the session, by relay set and filters
+fn coverage() -> &'static DashMap<u64, Coverage> {
+    static COVERAGE: OnceLock<DashMap<u64, Coverage>> = OnceLock::new();
+    COVERAGE.get_or_init(DashMap::new)
+}
+
+// relay pages a forward fetch may walk back to reach the newest seen
+const GAP_FILL_PAGES: usize = 5;
+
+/// Pages through the events of `filters`: older with [`Self::next_page`],
+/// newer than anything seen with [`Self::newer_page`]. A forward fetch that
+/// can't walk back to the newest seen leaves the middle open, the next
+/// [`Self::newer_page`] fills it.
+///
+/// With `from_db` each page is read from the database first and the relays
+/// fill what it lacks. They are skipped for a range they already answered
+/// in this session, so a feed opened again doesn't fetch it twice.
 #[derive(Debug, Clone)]
-#[allow(clippy::arc_with_non_send_sync)]
 pub struct EventPaginator {
-    client: Arc<Client>,
+    relay_set: HashedClient,
     filters: Vec<Filter>,
     oldest_timestamp: Option<Timestamp>,
+    newest_timestamp: Option<Timestamp>,
+    // `[since, until]` a forward fetch didn't reach yet
+    open_gap: Option<(Timestamp, Timestamp)>,
     done: bool,
     timeout: Option<Duration>,
     page_size: usize,
-    last_event_ids: HashSet<EventId>,
+    seen: HashSet<EventId>,
     from_db: bool,
+    outbox: Option<Outbox>,
 }
 
 unsafe impl Send for EventPaginator {}
 unsafe impl Sync for EventPaginator {}
 
 impl EventPaginator {
+    /// Pages of `filters` from the relays of `relay_set`
     pub fn new(
-        client: Arc<Client>,
+        relay_set: HashedClient,
         filters: Vec<Filter>,
         timeout: Option<Duration>,
         page_size: usize,
         from_db: bool,
     ) -> Self {
         Self {
-            client,
+            relay_set,
             filters,
             oldest_timestamp: None,
+            newest_timestamp: None,
+            open_gap: None,
             done: false,
             timeout,
             page_size,
-            last_event_ids: HashSet::new(),
+            seen: HashSet::new(),
             from_db,
+            outbox: None,
         }
     }
 
+    /// Reads authors from their write relays instead of only the relay set
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
     pub fn are_all_event_ids_present(&self, events: &[Event]) -> bool {
-        events
-            .iter()
-            .all(|event| self.last_event_ids.contains(&event.id))
+        events.iter().all(|event| self.seen.contains(&event.id))
     }
 
-    pub async fn next_page(&mut self) -> Option<Vec<Event>> {
-        if self.done {
-            return None;
-        }
+    fn coverage_key(&self) -> u64 {
+        hash_filter(&(self.relay_set.hash(), self.outbox.is_some(), &self.filters))
+    }
 
-        // Update filters with the oldest timestamp and limit
-        let updated_filters: Vec<Filter> = self
-            .filters
+    /// `filters` limited to a page of `[since, until]`
+    fn window(&self, since: Option<Timestamp>, until: Option<Timestamp>) -> Vec<Filter> {
+        self.filters
             .iter()
             .map(|f| {
-                let mut f = f.clone();
-                if let Some(timestamp) = self.oldest_timestamp {
-                    f = f.until(timestamp - 1);
+                let mut f = f.clone().limit(self.page_size);
+                if let Some(since) = since {
+                    f = f.since(since);
+                }
+                if let Some(until) = until {
+                    f = f.until(until);
                 }
-                f = f.limit(self.page_size);
                 f
             })
-            .collect();
+            .collect()
+    }
 
-        let events = if self.from_db {
-            // Attempt to fetch from the database first
-            match self
-                .client
-                .database()
-                .query(updated_filters.clone(), Order::Desc)
+    async fn query_relays(&self, filters: Vec<Filter>) -> Result<Vec<Event>> {
+        match &self.outbox {
+            Some(outbox) => outbox
+                .get_events_of(&self.relay_set, filters, self.timeout)
                 .await
-            {
-                Ok(events) => events,
-                // If database query fails, fall back to fetching from the relay
-                Err(err) => {
-                    tracing::error!("Database query failed: {:?}", err);
-                    self.done = true;
-                    return None;
+                .map_err(Error::from),
+            None => self
+                .relay_set
+                .get_events_of(filters, self.timeout)
+                .await
+                .map_err(Error::from),
+        }
+    }
+
+    /// Relay events of `[since, until]`, walking back from `until` for at
+    /// most `max_pages` while the pages come back full. What they answered
+    /// goes to the coverage. Also returns where the walk stopped if older
+    /// events of the range may be left.
+    async fn fetch_relays(
+        &self,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        max_pages: usize,
+    ) -> Result<(Vec<Event>, Option<Timestamp>)> {
+        let end = until.unwrap_or_else(Timestamp::now);
+        let mut events: Vec<Event> = Vec::new();
+        let mut cursor = until;
+        let mut start = None;
+        let mut open = false;
+        for _ in 0..max_pages {
+            let page = self.query_relays(self.window(since, cursor)).await?;
+            let full = page.len() >= self.page_size;
+            let oldest = get_oldest_event(&page).map(|event| event.created_at());
+            events.extend(page);
+            match oldest {
+                // a full page says nothing of what is older than it
+                Some(oldest) if full => {
+                    start = Some(oldest);
+                    cursor = Some(oldest - 1);
+                    open = true;
+                }
+                _ => {
+                    start = Some(since.unwrap_or(Timestamp::from(0)));
+                    open = false;
+                    break;
                 }
             }
-        } else {
-            // Directly fetch from the relay
+        }
+        if let Some(start) = start {
+            coverage()
+                .entry(self.coverage_key())
+                .or_default()
+                .insert(start.as_u64(), end.as_u64());
+        }
+        Ok((events, start.filter(|_| open)))
+    }
+
+    /// The events of `[since, until]` newest first: the database first, then
+    /// the relays unless they already answered for the range it spans. Also
+    /// returns the oldest time reached if older events of the range may be
+    /// left.
+    async fn fetch(
+        &self,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        max_pages: usize,
+    ) -> Result<(Vec<Event>, Option<Timestamp>)> {
+        let mut events = Vec::new();
+        if self.from_db {
             match self
-                .client
-                .get_events_of(updated_filters.clone(), self.timeout)
+                .relay_set
+                .database()
+                .query(self.window(since, until), Order::Desc)
                 .await
             {
-                Ok(events) => events,
-                Err(err) => {
-                    tracing::error!("Relay fetch failed: {:?}", err);
-                    self.done = true;
-                    return None;
-                }
+                Ok(found) => events = found,
+                Err(err) => tracing::error!("Database query failed: {:?}", err),
             }
-        };
+        }
+        // a full page from the database only spans down to its oldest event
+        let db_stop = get_oldest_event(&events)
+            .filter(|_| events.len() >= self.page_size)
+            .map(|oldest| oldest.created_at());
+        let start = db_stop.map_or_else(|| since.map_or(0, |since| since.as_u64()), |t| t.as_u64());
+        let end = until.unwrap_or_else(Timestamp::now).as_u64();
+        let covered = self.from_db
+            && coverage()
+                .get(&self.coverage_key())
+                .map_or(false, |coverage| coverage.covers(start, end));
+        let mut stop = db_stop;
+        if !covered {
+            let mut ids: HashSet<EventId> = events.iter().map(|event| event.id).collect();
+            let (found, relays_stop) = self.fetch_relays(since, until, max_pages).await?;
+            events.extend(found.into_iter().filter(|event| ids.insert(event.id)));
+            stop = relays_stop;
+        }
+        events.sort_by(|a, b| b.created_at().cmp(&a.created_at()));
+        Ok((events, stop))
+    }
 
-        if events.is_empty() || self.are_all_event_ids_present(&events) {
-            self.done = true;
+    /// The page older than anything returned so far, `None` once there is
+    /// none left
+    pub async fn next_page(&mut self) -> Option<Vec<Event>> {
+        if self.done {
             return None;
         }
+        let until = self.oldest_timestamp.map(|timestamp| timestamp - 1);
+        let events = match self.fetch(None, until, 1).await {
+            Ok((events, _)) => events,
+            Err(err) => {
+                tracing::error!("Relay fetch failed: {:?}", err);
+                self.done = true;
+                return None;
+            }
+        };
+        let mut events: Vec<Event> = events
+            .into_iter()
+            .filter(|event| !self.seen.contains(&event.id))
+            .collect();
+        events.truncate(self.page_size);
 
-        // Update the oldest timestamp
-        if let Some(oldest_event) = get_oldest_event(&events) {
-            self.oldest_timestamp = Some(oldest_event.created_at());
-        } else {
+        let Some(oldest) = get_oldest_event(&events).map(|event| event.created_at()) else {
             self.done = true;
-            return None; // No valid oldest event available
-        }
-
-        // Update the filters
-        self.filters = updated_filters;
-        self.last_event_ids = events.iter().map(|event| event.id).collect();
+            return None;
+        };
+        self.oldest_timestamp = Some(oldest);
+        let newest = get_newest_event(&events).map(|event| event.created_at());
+        self.newest_timestamp = self.newest_timestamp.max(newest);
+        self.seen.extend(events.iter().map(|event| event.id));
         Some(events)
     }
-}
 
-impl Stream for EventPaginator {
-    type Item = Option<Vec<Event>>;
-
-    fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        let fut = self.next_page();
-        futures::pin_mut!(fut);
-        match fut.poll(cx) {
-            std::task::Poll::Ready(res) => std::task::Poll::Ready(Some(res)),
-            std::task::Poll::Pending => std::task::Poll::Pending,
+    /// The events newer than anything returned so far, `None` when there
+    /// are none yet. Before any page it is the first page.
+    ///
+    /// If the previous call left a gap below what it returned, this call
+    /// fills the gap instead, newest first.
+    pub async fn newer_page(&mut self) -> Option<Vec<Event>> {
+        let Some(newest) = self.newest_timestamp else {
+            return self.next_page().await;
+        };
+        loop {
+            let (since, until) = match self.open_gap {
+                Some((since, until)) => (since, Some(until)),
+                None => (newest, None),
+            };
+            let (events, stop) = match self.fetch(Some(since), until, GAP_FILL_PAGES).await {
+                Ok(found) => found,
+                Err(err) => {
+                    tracing::error!("Relay fetch failed: {:?}", err);
+                    return None;
+                }
+            };
+            // the gap only shrinks, it closes once a walk gets down to `since`
+            self.open_gap = stop
+                .map(|stop| (since, stop - 1))
+                .filter(|(since, until)| since <= until);
+            let events: Vec<Event> = events
+                .into_iter()
+                .filter(|event| self.seen.insert(event.id))
+                .collect();
+            self.newest_timestamp = get_newest_event(&events)
+                .map(|event| event.created_at())
+                .max(self.newest_timestamp);
+            if !events.is_empty() {
+                return Some(events);
+            }
+            if self.open_gap.is_none() {
+                return None;
+            }
         }
     }
+
+    /// The older pages one after the other, ends with the last one
+    pub fn into_stream(self) -> impl Stream<Item = Vec<Event>> {
+        futures::stream::unfold(self, |mut paginator| async move {
+            paginator.next_page().await.map(|page| (page, paginator))
+        })
+    }
 }
 
 pub struct DecryptedMsgPaginator<'a> {
@@ -221,7 +412,7 @@ pub struct DecryptedMsgPaginator<'a> {
 impl<'a> DecryptedMsgPaginator<'a> {
     pub async fn new(
         signer: &'a NostrSigner,
-        client: Arc<Client>,
+        relay_set: HashedClient,
         target_pub_key: PublicKey,
         timeout: Option<Duration>,
         page_size: usize,
@@ -229,11 +420,11 @@ impl<'a> DecryptedMsgPaginator<'a> {
     ) -> Result<DecryptedMsgPaginator<'a>> {
         let public_key = signer.public_key().await?;
 
-        let (me, target) =
+        let (received, sent) =
             create_encrypted_filters!(Kind::EncryptedDirectMessage, target_pub_key, public_key);
-        let filters = vec![me, target];
+        let filters = vec![received, sent];
 
-        let paginator = EventPaginator::new(client, filters, timeout, page_size, from_db);
+        let paginator = EventPaginator::new(relay_set, filters, timeout, page_size, from_db);
         Ok(DecryptedMsgPaginator {
             signer,
             target_pub_key,
@@ -280,59 +471,355 @@ impl<'a> DecryptedMsgPaginator<'a> {
     }
 }
 
+/// Fetches and decrypts NIP-04 messages of the signer, optionally with one counterparty only
+pub async fn get_legacy_msgs(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    target: Option<PublicKey>,
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+    limit: Option<usize>,
+    timeout: Option<Duration>,
+) -> Result<Vec<DecryptedMsg>> {
+    let public_key = signer.public_key().await?;
+    let filters = match target {
+        Some(target) => {
+            let (received, sent) =
+                create_encrypted_filters!(Kind::EncryptedDirectMessage, target, public_key);
+            vec![received, sent]
+        }
+        None => vec![
+            Filter::new()
+                .kind(Kind::EncryptedDirectMessage)
+                .pubkey(public_key),
+            Filter::new()
+                .kind(Kind::EncryptedDirectMessage)
+                .author(public_key),
+        ],
+    };
+    let filters: Vec<Filter> = filters
+        .into_iter()
+        .map(|mut f| {
+            if let Some(since) = since {
+                f = f.since(since);
+            }
+            if let Some(until) = until {
+                f = f.until(until);
+            }
+            if let Some(limit) = limit {
+                f = f.limit(limit);
+            }
+            f
+        })
+        .collect();
+    let events = relay_set.get_events_of(filters, timeout).await?;
+
+    let mut msgs = Vec::new();
+    for event in events.into_iter() {
+        // the shared secret is the same in both directions
+        let counterparty = if event.author() == public_key {
+            match event.public_keys().next() {
+                Some(pk) => *pk,
+                None => continue,
+            }
+        } else {
+            event.author()
+        };
+        match signer.nip04_decrypt(counterparty, &event.content).await {
+            Ok(content) => {
+                let mut msg: DecryptedMsg = event.into();
+                msg.content = Some(content);
+                msgs.push(msg);
+            }
+            Err(e) => tracing::warn!("decrypt {} failed: {:?}", event.id, e),
+        }
+    }
+    Ok(msgs)
+}
+
 pub async fn get_event_by_id(
-    client: &Client,
+    relay_set: &HashedClient,
     event_id: &EventId,
     timeout: Option<std::time::Duration>,
 ) -> Result<Option<Event>> {
     let filter = Filter::new().id(*event_id).limit(1);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
     Ok(events.into_iter().next())
 }
 
 pub async fn get_events_by_ids(
-    client: &Client,
+    relay_set: &HashedClient,
     event_ids: &[EventId],
     timeout: Option<std::time::Duration>,
 ) -> Result<Vec<Event>> {
     let filters: Vec<Filter> = event_ids.iter().map(|id| Filter::new().id(*id)).collect();
-    let events = client.get_events_of(filters, timeout).await?;
+    let events = relay_set.get_events_of(filters, timeout).await?;
     Ok(events)
 }
 
 pub async fn get_metadata(
-    client: &Client,
+    relay_set: &HashedClient,
     public_key: &PublicKey,
     timeout: Option<Duration>,
 ) -> Result<Metadata> {
     let filter = Filter::new().author(*public_key).kind(Kind::Metadata);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
 
     if let Some(event) = get_newest_event(&events) {
         let metadata = Metadata::from_json(&event.content)?;
-        client.database().save_event(event).await?;
+        relay_set.database().save_event(event).await?;
         Ok(metadata)
     } else {
         Err(Error::EventNotFound)
     }
 }
 
-pub async fn get_zap() {
-    todo!()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZapTarget {
+    Event(EventId),
+    Profile(PublicKey),
+}
+
+impl ZapTarget {
+    fn filter(&self) -> Filter {
+        let filter = Filter::new().kind(Kind::ZapReceipt);
+        match self {
+            ZapTarget::Event(event_id) => filter.event(*event_id),
+            ZapTarget::Profile(public_key) => filter.pubkey(*public_key),
+        }
+    }
+}
+
+/// A zap receipt (kind 9735) that has been checked against its embedded zap request (kind 9734)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zap {
+    /// Id of the receipt
+    pub id: EventId,
+    /// Lightning service that signed the receipt
+    pub provider: PublicKey,
+    /// Author of the zap request
+    pub sender: PublicKey,
+    /// Receiver of the zap
+    pub recipient: PublicKey,
+    /// The zapped note, `None` for profile zaps
+    pub event_id: Option<EventId>,
+    /// Amount paid, taken from the bolt11 invoice
+    pub amount_msats: u64,
+    /// Comment of the zap request
+    pub comment: String,
+    pub created_at: Timestamp,
+}
+
+impl TryFrom<&Event> for Zap {
+    type Error = Error;
+
+    fn try_from(receipt: &Event) -> Result<Self> {
+        if receipt.kind() != Kind::ZapReceipt {
+            return Err(Error::InvalidZapReceipt("not a zap receipt".to_string()));
+        }
+
+        let mut bolt11 = None;
+        let mut description = None;
+        let mut recipient = None;
+        let mut event_id = None;
+        for tag in receipt.iter_tags() {
+            match <nostr_sdk::Tag as Clone>::clone(tag).to_standardized() {
+                Some(TagStandard::Bolt11(invoice)) => bolt11 = Some(invoice),
+                Some(TagStandard::Description(desc)) => description = Some(desc),
+                Some(TagStandard::PublicKey {
+                    public_key,
+                    uppercase: false,
+                    ..
+                }) => recipient = Some(public_key),
+                Some(TagStandard::Event { event_id: id, .. }) => event_id = Some(id),
+                _ => {}
+            }
+        }
+
+        let bolt11 =
+            bolt11.ok_or_else(|| Error::InvalidZapReceipt("missing bolt11 tag".to_string()))?;
+        let amount_msats = bolt11_amount_msats(&bolt11)
+            .ok_or_else(|| Error::InvalidZapReceipt("invoice without amount".to_string()))?;
+        let recipient =
+            recipient.ok_or_else(|| Error::InvalidZapReceipt("missing p tag".to_string()))?;
+        let description = description
+            .ok_or_else(|| Error::InvalidZapReceipt("missing description tag".to_string()))?;
+
+        // the description must be the signed zap request the invoice was created for
+        let request = Event::from_json(&description)
+            .map_err(|_| Error::InvalidZapReceipt("description is not an event".to_string()))?;
+        if request.kind() != Kind::ZapRequest || request.verify().is_err() {
+            return Err(Error::InvalidZapReceipt("invalid zap request".to_string()));
+        }
+        let description_hash = Sha256Hash::hash(description.as_bytes()).to_byte_array();
+        if bolt11_description_hash(&bolt11) != Some(description_hash) {
+            return Err(Error::InvalidZapReceipt(
+                "description hash mismatch".to_string(),
+            ));
+        }
+
+        let mut request_recipient = None;
+        let mut request_event_id = None;
+        let mut request_amount = None;
+        for tag in request.iter_tags() {
+            match <nostr_sdk::Tag as Clone>::clone(tag).to_standardized() {
+                Some(TagStandard::PublicKey {
+                    public_key,
+                    uppercase: false,
+                    ..
+                }) => request_recipient = Some(public_key),
+                Some(TagStandard::Event { event_id: id, .. }) => request_event_id = Some(id),
+                Some(TagStandard::Amount { millisats, .. }) => request_amount = Some(millisats),
+                _ => {}
+            }
+        }
+
+        if request_recipient != Some(recipient) {
+            return Err(Error::InvalidZapReceipt("recipient mismatch".to_string()));
+        }
+        if request_event_id != event_id {
+            return Err(Error::InvalidZapReceipt(
+                "zapped event mismatch".to_string(),
+            ));
+        }
+        if let Some(requested) = request_amount {
+            if requested != amount_msats {
+                return Err(Error::InvalidZapReceipt("amount mismatch".to_string()));
+            }
+        }
+
+        Ok(Self {
+            id: receipt.id,
+            provider: receipt.author(),
+            sender: request.author(),
+            recipient,
+            event_id,
+            amount_msats,
+            comment: request.content().to_string(),
+            created_at: receipt.created_at(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ZapSummary {
+    pub total_msats: u64,
+    pub count: usize,
+    /// Totals per zapped note
+    pub by_note: HashMap<EventId, u64>,
+    /// Totals per sender
+    pub by_sender: HashMap<PublicKey, u64>,
+    pub zaps: Vec<Zap>,
+}
+
+impl ZapSummary {
+    /// Sums the valid receipts of `events` that were signed by the key
+    /// `signers` has for their recipient
+    pub fn from_events(events: &[Event], signers: &HashMap<PublicKey, PublicKey>) -> Self {
+        let mut summary = Self::default();
+        let mut seen = HashSet::new();
+        for event in events {
+            if !seen.insert(event.id) {
+                continue;
+            }
+            match Zap::try_from(event) {
+                Ok(zap) if signers.get(&zap.recipient) == Some(&zap.provider) => summary.add(zap),
+                Ok(_) => tracing::warn!(
+                    "skip zap receipt {}: not signed by the recipient's lightning service",
+                    event.id
+                ),
+                Err(e) => tracing::warn!("skip zap receipt {}: {}", event.id, e),
+            }
+        }
+        summary
+    }
+
+    fn add(&mut self, zap: Zap) {
+        self.total_msats += zap.amount_msats;
+        self.count += 1;
+        if let Some(event_id) = zap.event_id {
+            *self.by_note.entry(event_id).or_insert(0) += zap.amount_msats;
+        }
+        *self.by_sender.entry(zap.sender).or_insert(0) += zap.amount_msats;
+        self.zaps.push(zap);
+    }
+
+    pub fn total_sats(&self) -> u64 {
+        self.total_msats / 1000
+    }
+
+    /// Senders ordered by the amount they zapped, biggest first
+    pub fn top_zappers(&self, n: usize) -> Vec<(PublicKey, u64)> {
+        let mut zappers: Vec<(PublicKey, u64)> = self
+            .by_sender
+            .iter()
+            .map(|(pk, msats)| (*pk, *msats))
+            .collect();
+        zappers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        zappers.truncate(n);
+        zappers
+    }
+}
+
+pub async fn get_zaps(
+    relay_set: &HashedClient,
+    target: ZapTarget,
+    timeout: Option<Duration>,
+    is_fetch: bool,
+) -> Result<ZapSummary> {
+    let mut events: Vec<Event> = Vec::new();
+    let mut zap_filter = target.filter();
+
+    // Get zap receipts from db
+    match relay_set
+        .database()
+        .query(vec![zap_filter.clone()], Order::Desc)
+        .await
+    {
+        Ok(db_events) => events.extend(db_events),
+        Err(e) => tracing::error!("zap receipts db query failed: {:?}", e),
+    }
+
+    // Get zap receipts from relay if needed
+    if is_fetch {
+        if let Some(newest) = get_newest_event(&events) {
+            zap_filter = zap_filter.since(newest.created_at() + 1);
+        }
+        let relay_events = relay_set.get_events_of(vec![zap_filter], timeout).await?;
+        events.extend(relay_events);
+    }
+
+    // anyone can publish a 9735, only the recipient's lightning service counts
+    let recipients: HashSet<PublicKey> = events
+        .iter()
+        .filter_map(|event| Zap::try_from(event).ok())
+        .map(|zap| zap.recipient)
+        .collect();
+    let mut signers = HashMap::new();
+    for recipient in recipients {
+        match zap_receipt_signer(relay_set, &recipient, timeout).await {
+            Ok(signer) => {
+                signers.insert(recipient, signer);
+            }
+            Err(e) => tracing::warn!("no zap receipt signer for {}: {}", recipient, e),
+        }
+    }
+
+    Ok(ZapSummary::from_events(&events, &signers))
 }
 
 pub async fn get_repost(
-    client: &Client,
+    relay_set: &HashedClient,
     event_id: &EventId,
     timeout: Option<std::time::Duration>,
 ) -> Result<Vec<Event>> {
     let filter = Filter::new().kind(Kind::Repost).event(*event_id);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
     Ok(events)
 }
 
 pub async fn get_reactions(
-    client: &Client,
+    relay_set: &HashedClient,
     event_id: &EventId,
     timeout: Option<Duration>,
     is_fetch: bool,
@@ -344,7 +831,11 @@ pub async fn get_reactions(
 
     // Get reactions from db
     let db_filter = reaction_filter.clone();
-    match client.database().query(vec![db_filter], Order::Desc).await {
+    match relay_set
+        .database()
+        .query(vec![db_filter], Order::Desc)
+        .await
+    {
         Ok(db_events) => {
             if !db_events.is_empty() {
                 events.extend(db_events);
@@ -366,7 +857,9 @@ pub async fn get_reactions(
             reaction_filter = reaction_filter.since(since);
         }
 
-        let relay_events = client.get_events_of(vec![reaction_filter], timeout).await?;
+        let relay_events = relay_set
+            .get_events_of(vec![reaction_filter], timeout)
+            .await?;
         events.extend(relay_events);
     }
 
@@ -380,23 +873,23 @@ pub async fn get_reactions(
 }
 
 pub async fn get_replies(
-    client: &Client,
+    relay_set: &HashedClient,
     event_id: &EventId,
     timeout: Option<std::time::Duration>,
 ) -> Result<Vec<Event>> {
     let filter = Filter::new().kind(Kind::TextNote).event(*event_id);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
     // TODO: filter out the mentions if necessary
     Ok(events)
 }
 
 pub async fn get_following(
-    client: &Client,
+    relay_set: &HashedClient,
     public_key: &PublicKey,
     timeout: Option<std::time::Duration>,
 ) -> Result<Vec<String>> {
     let filter = Filter::new().kind(Kind::ContactList).author(*public_key);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+    let events = relay_set.get_events_of(vec![filter], timeout).await?;
     let mut ret: Vec<String> = vec![];
     if let Some(latest_event) = events.iter().max_by_key(|event| event.created_at()) {
         ret.extend(latest_event.tags().iter().filter_map(|tag| {
@@ -414,7 +907,7 @@ pub async fn get_following(
 }
 
 pub async fn get_followers(
-    client: Arc<Client>,
+    relay_set: HashedClient,
     public_key: &PublicKey,
     timeout: Option<std::time::Duration>,
     from_db: bool,
@@ -425,7 +918,7 @@ pub async fn get_followers(
 
     spawn_local({
         let paginator = Arc::new(Mutex::new(EventPaginator::new(
-            client,
+            relay_set,
             vec![filter],
             timeout,
             500,
@@ -474,7 +967,7 @@ pub struct NotificationPaginator {
 
 impl NotificationPaginator {
     pub fn new(
-        client: Arc<Client>,
+        relay_set: HashedClient,
         public_key: PublicKey,
         timeout: Option<std::time::Duration>,
         page_size: usize,
@@ -483,7 +976,7 @@ impl NotificationPaginator {
         let filters = create_notification_filters(&public_key);
 
         Self {
-            paginator: EventPaginator::new(client, filters, timeout, page_size, from_db),
+            paginator: EventPaginator::new(relay_set, filters, timeout, page_size, from_db),
         }
     }
 
@@ -533,7 +1026,8 @@ mod tests {
     use nostr_indexeddb::database::Order;
     use nostr_indexeddb::WebDatabase;
     use nostr_sdk::key::SecretKey;
-    use nostr_sdk::{Client, ClientBuilder, EventBuilder, FromBech32, Keys};
+    use nostr_sdk::nips::nip57::ZapRequestData;
+    use nostr_sdk::{Client, ClientBuilder, EventBuilder, FromBech32, Keys, UncheckedUrl};
     use wasm_bindgen_futures::spawn_local;
     use wasm_bindgen_test::*;
 
@@ -554,6 +1048,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let event = get_event_by_id(&client, &event_id, timeout).await.unwrap();
         assert!(event.is_some());
     }
@@ -567,6 +1062,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let replies = get_replies(&client, &event_id, timeout).await.unwrap();
         assert_eq!(replies.len(), 4);
     }
@@ -581,6 +1077,7 @@ mod tests {
         client.add_relay("wss://nos.lol").await.unwrap();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let root = get_event_by_id(&client, &event_id, timeout)
             .await
             .unwrap()
@@ -604,6 +1101,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let reactions = get_reactions(&client, &event_id, timeout, true)
             .await
             .unwrap();
@@ -638,6 +1136,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
 
         let public_key = PublicKey::from_bech32(
             "npub1xtscya34g58tk0z605fvr788k263gsu6cy9x0mhnm87echrgufzsevkk5s",
@@ -650,8 +1149,7 @@ mod tests {
 
         let page_size = 10;
         let timeout = Some(std::time::Duration::from_secs(5));
-        let mut paginator =
-            EventPaginator::new(Arc::new(client), vec![filter], timeout, page_size, false);
+        let mut paginator = EventPaginator::new(client, vec![filter], timeout, page_size, false);
 
         let mut count = 0;
         while let Some(result) = paginator.next_page().await {
@@ -664,6 +1162,61 @@ mod tests {
         assert!(count > 100);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_next_page_and_newer_page() {
+        let db = WebDatabase::open(NOSTR_DB_NAME).await.unwrap();
+        let client = HashedClient::new(ClientBuilder::new().database(db).build()).await;
+        let keys = Keys::generate();
+        let save = |created_at: u64| {
+            let client = client.clone();
+            let event = EventBuilder::text_note("gm", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            async move {
+                client.database().save_event(&event).await.unwrap();
+                event.created_at().as_u64()
+            }
+        };
+        let times = |events: Option<Vec<Event>>| -> Vec<u64> {
+            events
+                .unwrap_or_default()
+                .iter()
+                .map(|event| event.created_at().as_u64())
+                .collect()
+        };
+
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let mut paginator = EventPaginator::new(client.clone(), vec![filter], None, 2, true);
+        // as if the relays had answered for all time, pages come from the database
+        coverage()
+            .entry(paginator.coverage_key())
+            .or_default()
+            .insert(0, u64::MAX);
+
+        save(100).await;
+        save(101).await;
+        assert_eq!(times(paginator.next_page().await), vec![101, 100]);
+        assert_eq!(paginator.next_page().await, None);
+
+        for created_at in 200..=204 {
+            save(created_at).await;
+        }
+        // one page can't reach down to 101, the middle is filled next
+        assert_eq!(times(paginator.newer_page().await), vec![204, 203]);
+        assert_eq!(
+            paginator.open_gap,
+            Some((Timestamp::from(101), Timestamp::from(202)))
+        );
+        assert_eq!(times(paginator.newer_page().await), vec![202, 201]);
+        assert_eq!(times(paginator.newer_page().await), vec![200]);
+        assert_eq!(paginator.open_gap, None);
+        assert_eq!(paginator.newer_page().await, None);
+
+        save(300).await;
+        assert_eq!(times(paginator.newer_page().await), vec![300]);
+    }
+
     #[wasm_bindgen_test]
     async fn test_encrypted_direct_message_filters_iterator() {
         let private_key = SecretKey::from_bech32(
@@ -679,18 +1232,13 @@ mod tests {
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
         let signer = client.signer().await.unwrap();
+        let client = HashedClient::new(client).await;
         let page_size = 3;
         let timeout = Some(std::time::Duration::from_secs(5));
-        let mut paginator = DecryptedMsgPaginator::new(
-            &signer,
-            Arc::new(client),
-            target_pub_key,
-            timeout,
-            page_size,
-            false,
-        )
-        .await
-        .unwrap();
+        let mut paginator =
+            DecryptedMsgPaginator::new(&signer, client, target_pub_key, timeout, page_size, false)
+                .await
+                .unwrap();
         let mut count = 0;
         while let Some(events) = paginator.next_page().await {
             console_log!("events are: {:?}", events);
@@ -704,12 +1252,12 @@ mod tests {
 
     #[wasm_bindgen_test]
     async fn test_get_followers() {
-        let client = &Client::default();
-        let arc_client = Arc::new(client.clone());
+        let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.add_relay("wss://nos.lol").await.unwrap();
 
         client.connect().await;
+        let client = HashedClient::new(client).await;
 
         let public_key = PublicKey::from_bech32(
             "npub1zfss807aer0j26mwp2la0ume0jqde3823rmu97ra6sgyyg956e0s6xw445",
@@ -719,7 +1267,7 @@ mod tests {
         let timeout = Some(std::time::Duration::from_secs(5));
         let exit_cond = Arc::new(AtomicBool::new(false));
 
-        let stream = get_followers(arc_client, &public_key, timeout, false).await;
+        let stream = get_followers(client, &public_key, timeout, false).await;
 
         spawn_local({
             let exit_cond_clone = Arc::clone(&exit_cond);
@@ -749,9 +1297,9 @@ mod tests {
     #[wasm_bindgen_test]
     async fn test_get_following() {
         let client = Client::default();
-        let client = Arc::new(client);
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
 
         let public_key = PublicKey::from_bech32(
             "npub1q0uulk2ga9dwkp8hsquzx38hc88uqggdntelgqrtkm29r3ass6fq8y9py9",
@@ -770,6 +1318,7 @@ mod tests {
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.add_relay("wss://nos.lol").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
 
         let public_key = PublicKey::from_bech32(
             "npub1zfss807aer0j26mwp2la0ume0jqde3823rmu97ra6sgyyg956e0s6xw445",
@@ -777,8 +1326,7 @@ mod tests {
         .unwrap();
 
         let timeout = Some(std::time::Duration::from_secs(5));
-        let mut paginator =
-            NotificationPaginator::new(Arc::new(client), public_key, timeout, 100, false);
+        let mut paginator = NotificationPaginator::new(client, public_key, timeout, 100, false);
         let mut count = 0;
         loop {
             let result = paginator.next_page().await;
@@ -799,11 +1347,151 @@ mod tests {
         assert!(count > 0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_get_zaps() {
+        let client = Client::default();
+        client.add_relay("wss://relay.damus.io").await.unwrap();
+        client.add_relay("wss://nos.lol").await.unwrap();
+        client.connect().await;
+        let client = HashedClient::new(client).await;
+
+        let public_key = PublicKey::from_bech32(
+            "npub1zfss807aer0j26mwp2la0ume0jqde3823rmu97ra6sgyyg956e0s6xw445",
+        )
+        .unwrap();
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let summary = get_zaps(&client, ZapTarget::Profile(public_key), timeout, true)
+            .await
+            .unwrap();
+        console_log!("zaps: {:?}", summary.top_zappers(5));
+        assert_eq!(summary.count, summary.zaps.len());
+        assert_eq!(summary.total_msats, summary.by_sender.values().sum::<u64>());
+    }
+
+    /// An unsigned 21 sats invoice committing to `description_hash`
+    fn fake_invoice(description_hash: &[u8; 32]) -> String {
+        const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+        // timestamp, then the `h` field of 52 words
+        let mut words = vec![0u8; 7];
+        words.extend([23, 52 >> 5, 52 & 31]);
+        let mut acc: u32 = 0;
+        let mut bits = 0;
+        for byte in description_hash {
+            acc = (acc << 8) | *byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                words.push(((acc >> bits) & 31) as u8);
+            }
+        }
+        words.push(((acc << (5 - bits)) & 31) as u8);
+        // signature and checksum
+        words.extend([0u8; 110]);
+        let data: String = words.iter().map(|w| CHARSET[*w as usize] as char).collect();
+        format!("lnbc210n1{}", data)
+    }
+
+    /// A zap request from a new key to `recipient` and its description hash
+    fn zap_request(recipient: PublicKey) -> (String, [u8; 32]) {
+        let data = ZapRequestData::new(recipient, vec![UncheckedUrl::from("wss://nos.lol")])
+            .amount(21_000);
+        let request = EventBuilder::public_zap_request(data)
+            .to_event(&Keys::generate())
+            .unwrap();
+        let description = request.as_json();
+        let hash = Sha256Hash::hash(description.as_bytes()).to_byte_array();
+        (description, hash)
+    }
+
+    fn zap_receipt(
+        provider: &Keys,
+        recipient: PublicKey,
+        description: &str,
+        description_hash: &[u8; 32],
+    ) -> Event {
+        EventBuilder::new(
+            Kind::ZapReceipt,
+            "",
+            [
+                Tag::public_key(recipient),
+                Tag::from_standardized(TagStandard::Bolt11(fake_invoice(description_hash))),
+                Tag::from_standardized(TagStandard::Description(description.to_string())),
+            ],
+        )
+        .to_event(provider)
+        .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_zap_receipt_description_hash() {
+        let provider = Keys::generate();
+        let recipient = Keys::generate().public_key();
+        let (description, hash) = zap_request(recipient);
+
+        let receipt = zap_receipt(&provider, recipient, &description, &hash);
+        let zap = Zap::try_from(&receipt).unwrap();
+        assert_eq!(zap.amount_msats, 21_000);
+        assert_eq!(zap.provider, provider.public_key());
+
+        // the invoice was created for another zap request
+        let mismatched = zap_receipt(&provider, recipient, &description, &[0u8; 32]);
+        assert!(matches!(
+            Zap::try_from(&mismatched),
+            Err(Error::InvalidZapReceipt(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_zap_receipt_foreign_author() {
+        let provider = Keys::generate();
+        let recipient = Keys::generate().public_key();
+        let (description, hash) = zap_request(recipient);
+        let signers = HashMap::from([(recipient, provider.public_key())]);
+
+        let receipt = zap_receipt(&provider, recipient, &description, &hash);
+        let forged = zap_receipt(&Keys::generate(), recipient, &description, &hash);
+        assert!(Zap::try_from(&forged).is_ok());
+
+        let summary = ZapSummary::from_events(&[receipt.clone(), forged], &signers);
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.zaps[0].id, receipt.id);
+        assert_eq!(summary.total_msats, 21_000);
+
+        // nothing counts for a recipient without a known lightning service
+        let summary = ZapSummary::from_events(&[receipt], &HashMap::new());
+        assert_eq!(summary.count, 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_bolt11_amount() {
+        use crate::nostr::utils::bolt11_amount_msats;
+
+        assert_eq!(
+            bolt11_amount_msats("lnbc2500u1pvjluezpp5qqqsyqcyq5rqwzqf"),
+            Some(250_000_000)
+        );
+        assert_eq!(
+            bolt11_amount_msats("lnbc20m1pvjluezpp5qqqsyqcyq5rqwzqf"),
+            Some(2_000_000_000)
+        );
+        assert_eq!(
+            bolt11_amount_msats("lnbc10n1pvjluezpp5qqqsyqcyq5rqwzqf"),
+            Some(1_000)
+        );
+        assert_eq!(
+            bolt11_amount_msats("lntb20m1pvjluezpp5qqqsyqcyq5rqwzqf"),
+            Some(2_000_000_000)
+        );
+        assert_eq!(bolt11_amount_msats("lnbc1pvjluezpp5qqqsyqcyq5rqwzqf"), None);
+        assert_eq!(bolt11_amount_msats("not an invoice"), None);
+    }
+
     #[wasm_bindgen_test]
     async fn test_get_repost() {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
 
         let event_id =
             EventId::from_bech32("note186yr06e9qgd285f9lsj3t56g2nvmqj0ddudgx57sn8k5lqcp5c4q53edv9")
@@ -812,4 +1500,22 @@ mod tests {
         console_log!("repost: {:?}", repost);
         assert!(!repost.is_empty());
     }
+
+    #[wasm_bindgen_test]
+    fn test_coverage() {
+        let mut coverage = Coverage::default();
+        coverage.insert(100, 200);
+        coverage.insert(300, 400);
+        assert!(coverage.covers(120, 180));
+        assert!(!coverage.covers(150, 350));
+        assert!(!coverage.covers(250, 260));
+
+        // filling the gap joins both ranges
+        coverage.insert(201, 299);
+        assert!(coverage.covers(100, 400));
+        coverage.insert(50, 120);
+        assert_eq!(coverage.ranges, vec![(50, 400)]);
+        coverage.insert(10, 5);
+        assert_eq!(coverage.ranges, vec![(50, 400)]);
+    }
 }
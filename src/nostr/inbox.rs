@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use nostr_sdk::{NostrSigner, PublicKey, Timestamp};
+use thiserror::Error;
+
+use super::dm::{
+    self, conversation_id, get_private_msgs, wrapped_since, ConversationKey, Conversations,
+    GiftWrapPage,
+};
+use super::fetch::{self, get_legacy_msgs, DecryptedMsg};
+use super::multiclient::HashedClient;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Fetch(#[from] fetch::Error),
+    #[error(transparent)]
+    Dm(#[from] dm::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// gift wrap pages a fetch may walk through before giving up
+const MAX_WRAP_PAGES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationSummary {
+    pub key: ConversationKey,
+    pub last: DecryptedMsg,
+    pub unread: usize,
+}
+
+/// What a fetch of the [`Inbox`] found, for [`Inbox::extend_page`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InboxPage {
+    pub msgs: Vec<DecryptedMsg>,
+    // where the gift wraps fetched so far end, if the fetch moved it
+    oldest_wrap: Option<Timestamp>,
+    wraps_done: bool,
+}
+
+/// Every NIP-04 and NIP-17 conversation of one account.
+///
+/// Fetching methods only read the inbox and return a page, so callers can
+/// await them without holding a write lock and then [`Inbox::extend_page`].
+///
+/// Gift wraps don't tell the conversation they belong to, so NIP-17 history
+/// is paged for the whole inbox, by the `created_at` of the gift wraps. A
+/// page of older messages keeps the messages of every conversation.
+#[derive(Debug, Clone)]
+pub struct Inbox {
+    relay_set: HashedClient,
+    signer: NostrSigner,
+    conversations: Conversations,
+    read_markers: HashMap<String, u64>,
+    timeout: Option<Duration>,
+    // the oldest gift wrap fetched, older pages end below it
+    oldest_wrap: Option<Timestamp>,
+    // no gift wrap is older than `oldest_wrap`
+    wraps_done: bool,
+}
+
+impl Inbox {
+    pub async fn new(
+        relay_set: HashedClient,
+        signer: NostrSigner,
+        read_markers: HashMap<String, u64>,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let me = signer.public_key().await?;
+        Ok(Self {
+            relay_set,
+            signer,
+            conversations: Conversations::new(me),
+            read_markers,
+            timeout,
+            oldest_wrap: None,
+            wraps_done: false,
+        })
+    }
+
+    pub fn me(&self) -> PublicKey {
+        self.conversations.me()
+    }
+
+    pub fn extend(&mut self, msgs: Vec<DecryptedMsg>) {
+        self.conversations.extend(msgs);
+    }
+
+    /// Adds the messages of `page` and moves the gift wrap cursor
+    pub fn extend_page(&mut self, page: InboxPage) {
+        if let Some(oldest) = page.oldest_wrap {
+            self.oldest_wrap = Some(self.oldest_wrap.map_or(oldest, |held| held.min(oldest)));
+        }
+        self.wraps_done |= page.wraps_done;
+        self.extend(page.msgs);
+    }
+
+    /// Conversations ordered by recency
+    pub fn summaries(&self) -> Vec<ConversationSummary> {
+        self.conversations
+            .sorted()
+            .into_iter()
+            .filter_map(|c| {
+                Some(ConversationSummary {
+                    key: c.key.clone(),
+                    last: c.last_message()?.clone(),
+                    unread: self.unread_count(&c.key),
+                })
+            })
+            .collect()
+    }
+
+    pub fn messages(&self, key: &ConversationKey) -> Vec<DecryptedMsg> {
+        self.conversations
+            .get(key)
+            .map(|c| c.messages.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn unread_count(&self, key: &ConversationKey) -> usize {
+        let me = self.me();
+        let marker = self
+            .read_markers
+            .get(&conversation_id(key))
+            .copied()
+            .unwrap_or(0);
+        self.conversations.get(key).map_or(0, |c| {
+            c.messages
+                .iter()
+                .filter(|m| m.pubkey != me && m.created_at.as_u64() > marker)
+                .count()
+        })
+    }
+
+    /// Marks the conversation read up to its last message, returns true if the marker moved
+    pub fn mark_read(&mut self, key: &ConversationKey) -> bool {
+        let Some(last) = self.conversations.get(key).and_then(|c| c.last_message()) else {
+            return false;
+        };
+        let last = last.created_at.as_u64();
+        let marker = self.read_markers.entry(conversation_id(key)).or_insert(0);
+        if *marker >= last {
+            return false;
+        }
+        *marker = last;
+        true
+    }
+
+    pub fn read_markers(&self) -> &HashMap<String, u64> {
+        &self.read_markers
+    }
+
+    fn newest(&self, key: Option<&ConversationKey>) -> Option<Timestamp> {
+        match key {
+            Some(key) => self.conversations.get(key).and_then(|c| c.last_message()),
+            None => self
+                .conversations
+                .sorted()
+                .first()
+                .and_then(|c| c.last_message()),
+        }
+        .map(|m| m.created_at)
+    }
+
+    /// Messages newer than the newest one we have, or the latest page on first load
+    pub async fn fetch_latest(&self, page_size: usize) -> Result<InboxPage> {
+        self.fetch_newer_of(None, page_size).await
+    }
+
+    /// Messages of one thread after its newest loaded message, along with
+    /// those of other threads that came in meanwhile
+    pub async fn fetch_newer(&self, key: &ConversationKey, page_size: usize) -> Result<InboxPage> {
+        self.fetch_newer_of(Some(key), page_size).await
+    }
+
+    /// History of one thread before its oldest loaded message. Gift wraps
+    /// are paged back from where the last page ended until the thread gets
+    /// a message, the other threads keep what the pages bring.
+    pub async fn fetch_older(&self, key: &ConversationKey, page_size: usize) -> Result<InboxPage> {
+        let until = self
+            .conversations
+            .get(key)
+            .and_then(|c| c.messages.first())
+            .map(|m| m.created_at - 1);
+        let mut msgs = self.fetch_legacy(Some(key), None, until, page_size).await?;
+        if self.wraps_done {
+            return Ok(InboxPage {
+                msgs,
+                ..Default::default()
+            });
+        }
+        let wraps = self
+            .fetch_wraps(
+                None,
+                self.oldest_wrap.map(|oldest| oldest - 1),
+                page_size,
+                |msgs| msgs.iter().any(|m| &self.conversations.key_for(m) == key),
+            )
+            .await?;
+        msgs.extend(wraps.msgs);
+        Ok(InboxPage {
+            msgs,
+            oldest_wrap: wraps.oldest,
+            wraps_done: !wraps.full,
+        })
+    }
+
+    async fn fetch_newer_of(
+        &self,
+        key: Option<&ConversationKey>,
+        page_size: usize,
+    ) -> Result<InboxPage> {
+        let since = self.newest(key).map(|newest| newest + 1);
+        let mut msgs = self.fetch_legacy(key, since, None, page_size).await?;
+        // a message newer than any we have can be wrapped up to the tweak
+        // range earlier, whichever thread it belongs to
+        let wrap_since = self.newest(None).map(|newest| wrapped_since(newest + 1));
+        let wraps = self
+            .fetch_wraps(wrap_since, None, page_size, |_| false)
+            .await?;
+        msgs.extend(wraps.msgs);
+        // older pages go on below the first gift wraps fetched, and only a
+        // page without lower bound says where the history ends
+        let first = self.oldest_wrap.is_none();
+        Ok(InboxPage {
+            msgs,
+            oldest_wrap: wraps.oldest.filter(|_| first),
+            wraps_done: first && wrap_since.is_none() && !wraps.full,
+        })
+    }
+
+    // NIP-04 can be narrowed to a counterparty on the relay and has no group messages
+    async fn fetch_legacy(
+        &self,
+        key: Option<&ConversationKey>,
+        since: Option<Timestamp>,
+        until: Option<Timestamp>,
+        page_size: usize,
+    ) -> Result<Vec<DecryptedMsg>> {
+        let target = match key {
+            Some(key) if key.len() == 1 => key.iter().next().copied(),
+            Some(_) => return Ok(Vec::new()),
+            None => None,
+        };
+        Ok(get_legacy_msgs(
+            &self.relay_set,
+            &self.signer,
+            target,
+            since,
+            until,
+            Some(page_size),
+            self.timeout,
+        )
+        .await?)
+    }
+
+    /// Gift wrap pages back from `until` down to `since` while they come
+    /// back full, stops early once `enough` holds for what was found
+    async fn fetch_wraps(
+        &self,
+        since: Option<Timestamp>,
+        mut until: Option<Timestamp>,
+        page_size: usize,
+        enough: impl Fn(&[DecryptedMsg]) -> bool,
+    ) -> Result<GiftWrapPage> {
+        let mut pages = GiftWrapPage::default();
+        // without a lower bound one page is the latest page
+        let max_pages = if since.is_some() { MAX_WRAP_PAGES } else { 1 };
+        for _ in 0..max_pages {
+            let page = get_private_msgs(
+                &self.relay_set,
+                &self.signer,
+                since,
+                until,
+                page_size,
+                self.timeout,
+            )
+            .await?;
+            pages.msgs.extend(page.msgs);
+            pages.oldest = page.oldest.or(pages.oldest);
+            pages.full = page.full;
+            match page.oldest {
+                Some(oldest) if page.full && !enough(&pages.msgs) => until = Some(oldest - 1),
+                _ => break,
+            }
+        }
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::nostr::dm::{gift_wrap, unwrap_gift_wrap};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_inbox_unread() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let alice_signer: NostrSigner = alice.clone().into();
+        let bob_signer: NostrSigner = bob.clone().into();
+
+        let mut inbox = Inbox::new(
+            HashedClient::new(nostr_sdk::Client::default()).await,
+            alice_signer.clone(),
+            HashMap::new(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let mut msgs = Vec::new();
+        for (i, text) in ["one", "two"].iter().enumerate() {
+            let rumor = EventBuilder::private_msg_rumor(alice.public_key(), *text, None)
+                .custom_created_at(Timestamp::from(100 + i as u64))
+                .to_unsigned_event(bob.public_key());
+            let wrapped = gift_wrap(&bob_signer, &alice.public_key(), &rumor)
+                .await
+                .unwrap();
+            msgs.push(unwrap_gift_wrap(&alice_signer, &wrapped).await.unwrap());
+        }
+        inbox.extend(msgs);
+
+        let key = ConversationKey::from([bob.public_key()]);
+        let summaries = inbox.summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].unread, 2);
+        assert_eq!(summaries[0].last.content.as_deref(), Some("two"));
+
+        assert!(inbox.mark_read(&key));
+        assert!(!inbox.mark_read(&key));
+        assert_eq!(inbox.unread_count(&key), 0);
+        assert_eq!(inbox.read_markers().get(&conversation_id(&key)), Some(&101));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_inbox_wrap_cursor() {
+        let alice = Keys::generate();
+        let mut inbox = Inbox::new(
+            HashedClient::new(nostr_sdk::Client::default()).await,
+            alice.into(),
+            HashMap::new(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        inbox.extend_page(InboxPage {
+            oldest_wrap: Some(Timestamp::from(500)),
+            ..Default::default()
+        });
+        // a newer page doesn't move the cursor up
+        inbox.extend_page(InboxPage {
+            oldest_wrap: Some(Timestamp::from(900)),
+            ..Default::default()
+        });
+        assert_eq!(inbox.oldest_wrap, Some(Timestamp::from(500)));
+
+        inbox.extend_page(InboxPage {
+            oldest_wrap: Some(Timestamp::from(200)),
+            wraps_done: true,
+            ..Default::default()
+        });
+        assert_eq!(inbox.oldest_wrap, Some(Timestamp::from(200)));
+        assert!(inbox.wraps_done);
+    }
+}
@@ -1,30 +1,74 @@
+mod auth;
+mod contact_history;
+mod contacts;
+mod delivery;
+mod discovery;
+mod dm;
 mod fetch;
+mod inbox;
+mod monitor;
 mod multiclient;
 mod note;
+mod outbox;
 mod publish;
 mod register;
+mod relay_info;
+mod sync;
 mod utils;
+mod wallet;
+mod zap;
+pub use auth::{is_auth_required, AuthState, Authenticator, AUTH_REQUIRED};
+pub use contact_history::{
+    build_history, contact_list_history, diff_versions, restore_contact_list, ContactListDiff,
+    ContactListRevision,
+};
+pub use contacts::{
+    check_shrink, contacts_of, count_contacts, local_versions, newest_contact_list,
+    publish_contact_list, replace_contacts, with_contact, without_contact,
+};
+pub use delivery::{queue_failed, retry_backoff, retry_pending};
+pub use discovery::{
+    apply_recommendation, discover, relay_string, Recommendation, RelayScore, DEFAULT_MAX_RELAYS,
+};
+pub use dm::{
+    conversation_id, get_private_msgs, unwrap_gift_wrap, wrapped_since, Conversation,
+    ConversationKey, Conversations, GiftWrapPage,
+};
 pub use fetch::{
     create_notification_filters, get_event_by_id, get_events_by_ids, get_followers, get_following,
-    get_metadata, get_reactions, get_replies, get_repost, process_notification_events,
-    DecryptedMsg, DecryptedMsgPaginator, EventPaginator, NotificationMsg, NotificationPaginator,
+    get_legacy_msgs, get_metadata, get_reactions, get_replies, get_repost, get_zaps,
+    process_notification_events, Coverage, DecryptedMsg, DecryptedMsgPaginator, EventPaginator,
+    NotificationMsg, NotificationPaginator, Zap, ZapSummary, ZapTarget,
 };
 pub use publish::{
-    delete_event, file_metadata, follow, new_channel, publish_text_note, reaction, repost,
-    send_channel_msg, send_private_msg, set_channel_metadata, set_contact_list, set_relay_list,
-    unfollow,
+    delete_event, file_metadata, follow, new_channel, publish_text_note, publish_text_note_routed,
+    reaction, repost, send_channel_msg, send_private_msg, set_channel_metadata, set_contact_list,
+    set_relay_list, unfollow, PublishResult, RelayOutcome,
+};
+
+pub use inbox::{ConversationSummary, Inbox, InboxPage};
+
+pub use sync::{
+    account_filters, sync_account, sync_filter, sync_subs, RelaySync, SyncMethod, SyncReport,
 };
 
+pub use outbox::{mentioned_public_keys, route_authors, Outbox, RelayList};
+
+pub use monitor::{ConnectionState, RelayHealth, RelayMonitor, SkipPolicy};
+
 pub use multiclient::EventCache;
 pub use multiclient::HashedClient;
 pub use multiclient::MultiClient;
+pub use multiclient::{apply_relay_set_edit, RelaySetDiff};
 
 pub use note::DisplayOrder;
 pub use note::ReplyTreeManager;
 pub use note::ReplyTrees;
 pub use note::TextNote;
-pub use register::NotificationHandler;
 pub use register::Register;
+pub use register::{BatchOptions, OverflowPolicy, SubEvent, SubState, SubStream};
+
+pub use relay_info::{check_filters, fetch_relay_info, get_relay_info, Unsupported};
 
 pub use utils::get_ancestors;
 pub use utils::get_children;
@@ -33,3 +77,10 @@ pub use utils::get_oldest_event;
 pub use utils::hash_filter;
 pub use utils::is_note_address;
 pub use utils::AddressType;
+
+pub use wallet::Wallet;
+
+pub use zap::{
+    fetch_pay_info, lnurl_pay_url, request_invoice, send_zap, wait_for_receipt, LnurlPayInfo,
+    PaymentBackend, ZapOptions, ZapState,
+};
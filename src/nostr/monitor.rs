@@ -0,0 +1,425 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use gloo_timers::future::TimeoutFuture;
+use nostr_sdk::{Filter, RelayMessage, RelayPoolNotification, RelayStatus, SubscriptionId, Url};
+use wasm_bindgen_futures::spawn_local;
+
+use super::multiclient::SharedPool;
+
+// first reconnect after 1s, doubling up to 5 minutes
+const BACKOFF_BASE_MS: u64 = 1_000;
+const BACKOFF_MAX_MS: u64 = 300_000;
+// weight of the newest sample in the latency averages
+const EWMA_WEIGHT: f64 = 0.3;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+fn ewma(current: Option<Duration>, sample: Duration) -> Duration {
+    match current {
+        Some(current) => Duration::from_secs_f64(
+            current.as_secs_f64() * (1.0 - EWMA_WEIGHT) + sample.as_secs_f64() * EWMA_WEIGHT,
+        ),
+        None => sample,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+impl From<RelayStatus> for ConnectionState {
+    fn from(status: RelayStatus) -> Self {
+        match status {
+            RelayStatus::Connected => Self::Connected,
+            RelayStatus::Disconnected | RelayStatus::Terminated => Self::Disconnected,
+            _ => Self::Connecting,
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connecting => write!(f, "connecting"),
+            Self::Connected => write!(f, "connected"),
+            Self::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+/// What is known about one relay connection
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RelayHealth {
+    pub state: ConnectionState,
+    /// Round-trip time of a probe request, averaged
+    pub latency: Option<Duration>,
+    /// Time from REQ to EOSE of the subscriptions, averaged
+    pub eose: Option<Duration>,
+    pub errors: u32,
+    pub notices: u32,
+    /// Failed connection attempts since the last successful one, a drop of
+    /// a live connection is not one
+    pub failures: u32,
+    /// Connected time of the past sessions
+    pub uptime: Duration,
+    /// Start of the current session, ms since the epoch
+    pub connected_since: Option<f64>,
+    /// When the next reconnect is attempted, ms since the epoch, cleared
+    /// once the attempt goes out
+    pub next_retry: Option<f64>,
+}
+
+impl RelayHealth {
+    /// Total connected time including the current session
+    pub fn uptime(&self) -> Duration {
+        let current = self
+            .connected_since
+            .map(|since| Duration::from_millis((now_ms() - since).max(0.0) as u64))
+            .unwrap_or_default();
+        self.uptime + current
+    }
+
+    /// Delay before the next reconnect, doubling with every failure
+    pub fn backoff(&self) -> Duration {
+        let exp = self.failures.min(16);
+        Duration::from_millis((BACKOFF_BASE_MS << exp).min(BACKOFF_MAX_MS))
+    }
+
+    /// Disconnected and at least one attempt to come back failed
+    pub fn is_dead(&self) -> bool {
+        self.state == ConnectionState::Disconnected && self.failures > 0
+    }
+
+    pub fn is_slow(&self, threshold: Duration) -> bool {
+        self.latency.map_or(false, |l| l > threshold) || self.eose.map_or(false, |e| e > threshold)
+    }
+
+    fn connected(&mut self) {
+        if self.state != ConnectionState::Connected {
+            self.connected_since = Some(now_ms());
+        }
+        self.state = ConnectionState::Connected;
+        self.failures = 0;
+        self.next_retry = None;
+    }
+
+    fn disconnected(&mut self) {
+        if let Some(since) = self.connected_since.take() {
+            self.uptime += Duration::from_millis((now_ms() - since).max(0.0) as u64);
+        }
+        if self.state != ConnectionState::Connected {
+            self.failures += 1;
+        }
+        self.state = ConnectionState::Disconnected;
+    }
+}
+
+/// Which relays of a set queries may leave out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkipPolicy {
+    /// Leave out relays that are down and failed to reconnect
+    pub skip_dead: bool,
+    /// Leave out relays slower than this
+    pub slow_after: Option<Duration>,
+}
+
+impl Default for SkipPolicy {
+    fn default() -> Self {
+        Self {
+            skip_dead: true,
+            slow_after: None,
+        }
+    }
+}
+
+/// Tracks the health of every relay of the [`SharedPool`] and reconnects
+/// dropped relays with exponential backoff.
+#[derive(Debug, Clone, Default)]
+pub struct RelayMonitor {
+    health: Arc<DashMap<Url, RelayHealth>>,
+    requests: Arc<DashMap<(Url, SubscriptionId), f64>>,
+    policy: Arc<RwLock<SkipPolicy>>,
+}
+
+impl RelayMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, url: &Url) -> Option<RelayHealth> {
+        self.health.get(url).map(|h| h.clone())
+    }
+
+    /// The health of `urls`, unknown relays are still connecting
+    pub fn snapshot(&self, urls: &[Url]) -> Vec<(Url, RelayHealth)> {
+        urls.iter()
+            .map(|url| (url.clone(), self.get(url).unwrap_or_default()))
+            .collect()
+    }
+
+    pub fn policy(&self) -> SkipPolicy {
+        *self.policy.read().unwrap()
+    }
+
+    pub fn set_policy(&self, policy: SkipPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// `urls` without the relays the policy skips, fastest first.
+    ///
+    /// Never empty when `urls` is not: if every relay would be skipped they
+    /// are all kept.
+    pub fn usable(&self, urls: &[Url]) -> Vec<Url> {
+        let policy = self.policy();
+        let mut ranked = self.snapshot(urls);
+        ranked.sort_by_key(|(_, h)| (h.is_dead(), h.latency.or(h.eose).unwrap_or(Duration::MAX)));
+        let kept: Vec<Url> = ranked
+            .iter()
+            .filter(|(_, h)| !(policy.skip_dead && h.is_dead()))
+            .filter(|(_, h)| !policy.slow_after.map_or(false, |t| h.is_slow(t)))
+            .map(|(url, _)| url.clone())
+            .collect();
+        if kept.is_empty() {
+            ranked.into_iter().map(|(url, _)| url).collect()
+        } else {
+            kept
+        }
+    }
+
+    /// Starts the EOSE timer of `sub_id` on each relay
+    pub fn record_request(&self, urls: &[Url], sub_id: &SubscriptionId) {
+        let now = now_ms();
+        for url in urls {
+            self.requests.insert((url.clone(), sub_id.clone()), now);
+        }
+    }
+
+    pub fn record_latency(&self, url: &Url, latency: Duration) {
+        let mut health = self.health.entry(url.clone()).or_default();
+        health.latency = Some(ewma(health.latency, latency));
+    }
+
+    pub fn record_error(&self, url: &Url) {
+        self.health.entry(url.clone()).or_default().errors += 1;
+    }
+
+    pub fn forget(&self, url: &Url) {
+        self.health.remove(url);
+        self.requests.retain(|(u, _), _| u != url);
+    }
+
+    /// The scheduled reconnect of `url` goes out, the next drop is its outcome
+    fn retrying(&self, url: &Url) {
+        if let Some(mut health) = self.health.get_mut(url) {
+            health.next_retry = None;
+        }
+    }
+
+    /// Updates the stats from a pool notification, returns the delay
+    /// before reconnecting when the relay went down
+    pub fn handle(&self, notification: &RelayPoolNotification) -> Option<Duration> {
+        match notification {
+            RelayPoolNotification::RelayStatus { relay_url, status } => {
+                let mut health = self.health.entry(relay_url.clone()).or_default();
+                match ConnectionState::from(*status) {
+                    ConnectionState::Connected => health.connected(),
+                    ConnectionState::Disconnected => {
+                        // Disconnected and Terminated both arrive for one drop,
+                        // the second finds the retry already scheduled
+                        if health.state == ConnectionState::Disconnected
+                            && health.next_retry.is_some()
+                        {
+                            return None;
+                        }
+                        health.disconnected();
+                        let backoff = health.backoff();
+                        health.next_retry = Some(now_ms() + backoff.as_millis() as f64);
+                        return Some(backoff);
+                    }
+                    ConnectionState::Connecting => {
+                        health.state = ConnectionState::Connecting;
+                    }
+                }
+                None
+            }
+            RelayPoolNotification::Message { relay_url, message } => {
+                match message {
+                    RelayMessage::EndOfStoredEvents(sub_id) => {
+                        if let Some((_, start)) =
+                            self.requests.remove(&(relay_url.clone(), sub_id.clone()))
+                        {
+                            let elapsed = Duration::from_millis((now_ms() - start).max(0.0) as u64);
+                            let mut health = self.health.entry(relay_url.clone()).or_default();
+                            health.eose = Some(ewma(health.eose, elapsed));
+                        }
+                    }
+                    RelayMessage::Notice { .. } => {
+                        self.health.entry(relay_url.clone()).or_default().notices += 1;
+                    }
+                    RelayMessage::Closed {
+                        subscription_id, ..
+                    } => {
+                        self.requests
+                            .remove(&(relay_url.clone(), subscription_id.clone()));
+                        self.record_error(relay_url);
+                    }
+                    _ => {}
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Measures the round trip of an empty request to `url`
+    pub async fn probe(&self, pool: &SharedPool, url: &Url) {
+        let start = now_ms();
+        let result = pool
+            .client()
+            .get_events_from(
+                [url.clone()],
+                vec![Filter::new().limit(0)],
+                Some(PROBE_TIMEOUT),
+            )
+            .await;
+        match result {
+            Ok(_) => self.record_latency(
+                url,
+                Duration::from_millis((now_ms() - start).max(0.0) as u64),
+            ),
+            Err(e) => {
+                tracing::warn!("probe {} failed: {:?}", url, e);
+                self.record_error(url);
+            }
+        }
+    }
+
+    /// Records a notification of `pool`, then probes a relay that came up
+    /// or schedules the reconnect of one that went down
+    pub fn on_notification(&self, pool: &SharedPool, notification: &RelayPoolNotification) {
+        let reconnect = self.handle(notification);
+        let RelayPoolNotification::RelayStatus { relay_url, status } = notification else {
+            return;
+        };
+        let monitor = self.clone();
+        let pool = pool.clone();
+        let relay_url = relay_url.clone();
+        if *status == RelayStatus::Connected {
+            spawn_local(async move { monitor.probe(&pool, &relay_url).await });
+        } else if let Some(delay) = reconnect {
+            spawn_local(async move { monitor.reconnect(&pool, relay_url, delay).await });
+        }
+    }
+
+    async fn reconnect(&self, pool: &SharedPool, url: Url, delay: Duration) {
+        TimeoutFuture::new(delay.as_millis() as u32).await;
+        // released meanwhile, or already back
+        if pool.ref_count(&url) == 0 {
+            self.forget(&url);
+            return;
+        }
+        if self
+            .get(&url)
+            .map_or(false, |h| h.state == ConnectionState::Connected)
+        {
+            return;
+        }
+        tracing::info!("reconnecting to {} after {:?}", url, delay);
+        self.retrying(&url);
+        if let Err(e) = pool.client().connect_relay(url.clone()).await {
+            tracing::warn!("reconnect to {} failed: {:?}", url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn status(url: &Url, status: RelayStatus) -> RelayPoolNotification {
+        RelayPoolNotification::RelayStatus {
+            relay_url: url.clone(),
+            status,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_backoff_doubles_until_connected() {
+        let monitor = RelayMonitor::new();
+        let url = Url::parse("wss://relay.damus.io").unwrap();
+        monitor.handle(&status(&url, RelayStatus::Connected));
+        let first = monitor.handle(&status(&url, RelayStatus::Disconnected));
+        assert_eq!(first, Some(Duration::from_secs(1)));
+        // a single drop is not a dead relay yet
+        assert!(!monitor.get(&url).unwrap().is_dead());
+        monitor.handle(&status(&url, RelayStatus::Connecting));
+        let second = monitor.handle(&status(&url, RelayStatus::Disconnected));
+        assert_eq!(second, Some(Duration::from_secs(2)));
+        assert!(monitor.get(&url).unwrap().is_dead());
+
+        monitor.handle(&status(&url, RelayStatus::Connected));
+        let health = monitor.get(&url).unwrap();
+        assert_eq!(health.state, ConnectionState::Connected);
+        assert_eq!(health.failures, 0);
+        assert!(health.next_retry.is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_consecutive_failed_retries() {
+        let monitor = RelayMonitor::new();
+        let url = Url::parse("wss://relay.damus.io").unwrap();
+        monitor.handle(&status(&url, RelayStatus::Connected));
+        let first = monitor.handle(&status(&url, RelayStatus::Disconnected));
+        assert_eq!(first, Some(Duration::from_secs(1)));
+        assert_eq!(monitor.handle(&status(&url, RelayStatus::Terminated)), None);
+
+        // the retries fail straight to Disconnected, without a Connecting
+        monitor.retrying(&url);
+        let second = monitor.handle(&status(&url, RelayStatus::Disconnected));
+        assert_eq!(second, Some(Duration::from_secs(2)));
+        assert_eq!(monitor.handle(&status(&url, RelayStatus::Terminated)), None);
+        monitor.retrying(&url);
+        let third = monitor.handle(&status(&url, RelayStatus::Disconnected));
+        assert_eq!(third, Some(Duration::from_secs(4)));
+
+        let health = monitor.get(&url).unwrap();
+        assert_eq!(health.failures, 2);
+        assert!(health.is_dead());
+        assert!(health.next_retry.is_some());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_usable_skips_dead_and_slow() {
+        let monitor = RelayMonitor::new();
+        let fast = Url::parse("wss://nos.lol").unwrap();
+        let slow = Url::parse("wss://nostr.wine").unwrap();
+        let dead = Url::parse("wss://relay.damus.io").unwrap();
+        monitor.handle(&status(&dead, RelayStatus::Disconnected));
+        monitor.record_latency(&fast, Duration::from_millis(100));
+        monitor.record_latency(&slow, Duration::from_secs(3));
+
+        let urls = vec![dead.clone(), slow.clone(), fast.clone()];
+        assert_eq!(monitor.usable(&urls), vec![fast.clone(), slow.clone()]);
+
+        monitor.set_policy(SkipPolicy {
+            skip_dead: true,
+            slow_after: Some(Duration::from_secs(1)),
+        });
+        assert_eq!(monitor.usable(&urls), vec![fast]);
+        // everything skipped falls back to the whole set
+        assert_eq!(monitor.usable(&[dead.clone()]), vec![dead]);
+    }
+}
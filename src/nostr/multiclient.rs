@@ -1,17 +1,32 @@
+use std::collections::{BTreeSet, HashSet};
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use cached::{Cached, TimedCache};
 use dashmap::DashMap;
 use nostr_indexeddb::database::Order;
 use nostr_indexeddb::WebDatabase;
-use nostr_sdk::{Client, ClientBuilder, Event, Filter};
+use nostr_sdk::database::DynNostrDatabase;
+use nostr_sdk::{
+    Client, ClientBuilder, Event, EventId, Filter, NegentropyOptions, RelayOptions,
+    SubscribeAutoCloseOptions, SubscriptionId, Timestamp, Url,
+};
 use thiserror::Error;
-use tokio::sync::{Mutex, Notify};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{watch, Mutex, OnceCell};
+use wasm_bindgen_futures::spawn_local;
 
+use super::auth::Authenticator;
+use super::delivery;
+use super::monitor::RelayMonitor;
+use super::outbox::Outbox;
+use super::publish::PublishResult;
+use super::register::Register;
 use super::utils::hash_filter;
 use crate::init::NOSTR_DB_NAME;
+use crate::store::subscription::RelaySet;
 use crate::store::{self, CBWebDatabase, CAPYBASTR_DBNAME};
 
 #[derive(Debug, Error)]
@@ -28,81 +43,418 @@ pub enum Error {
     QueryFailedOrNotCached,
     #[error("Client with key {0} not found")]
     ClientNotFoundByKey(String),
+    #[error("Invalid relay url: {0}")]
+    InvalidUrl(String),
+    #[error("{0} is not in the relay set")]
+    NotInSet(Url),
+    #[error("{0} can't reconcile: {1}")]
+    Reconcile(Url, String),
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// The relay connections shared by every relay set, one websocket per url.
+///
+/// Relay sets acquire the urls they use and release them when they drop
+/// them; a relay is disconnected once no set references it anymore.
+/// Reconnecting is left to the [`RelayMonitor`], which backs off
+/// exponentially instead of retrying at a fixed interval.
 #[derive(Debug, Clone)]
-pub struct HashedClient {
+pub struct SharedPool {
     client: Arc<Client>,
-    hash: u64,
+    refs: Arc<DashMap<Url, usize>>,
+    // held while a relay is added or removed, so a reference is only
+    // handed out once the relay is in the pool
+    changing: Arc<Mutex<()>>,
+    monitor: RelayMonitor,
+    authenticator: Authenticator,
+    // the delivery queue, opened once for every publish and retry
+    pending: Arc<OnceCell<CBWebDatabase>>,
+}
+
+unsafe impl Send for SharedPool {}
+unsafe impl Sync for SharedPool {}
+
+#[allow(clippy::arc_with_non_send_sync)]
+impl SharedPool {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client: Arc::new(client),
+            refs: Arc::new(DashMap::new()),
+            changing: Arc::new(Mutex::new(())),
+            monitor: RelayMonitor::new(),
+            authenticator: Authenticator::new(),
+            pending: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// The pool backed by the shared event database
+    pub async fn open() -> Result<Self> {
+        let db = WebDatabase::open(NOSTR_DB_NAME).await?;
+        Ok(Self::new(ClientBuilder::new().database(db).build()))
+    }
+
+    /// The client holding every connection, queries through it reach all relays of the pool
+    pub(crate) fn client(&self) -> Arc<Client> {
+        self.client.clone()
+    }
+
+    pub fn monitor(&self) -> RelayMonitor {
+        self.monitor.clone()
+    }
+
+    pub fn authenticator(&self) -> Authenticator {
+        self.authenticator.clone()
+    }
+
+    /// The database holding the events still owed to some relays
+    pub async fn pending_db(&self) -> Result<CBWebDatabase> {
+        let db = self
+            .pending
+            .get_or_try_init(|| CBWebDatabase::open(CAPYBASTR_DBNAME))
+            .await?;
+        Ok(db.clone())
+    }
+
+    /// Hands each notification of the pool to the monitor, the
+    /// authenticator and the delivery queue, for as long as the app runs.
+    /// They spawn what takes time, a lag only loses the notifications
+    /// missed.
+    pub fn watch(&self) {
+        let pool = self.clone();
+        spawn_local(async move {
+            let mut notifications = pool.client.notifications();
+            loop {
+                let notification = match notifications.recv().await {
+                    Ok(notification) => notification,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("pool missed {} notifications", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                pool.monitor.on_notification(&pool, &notification);
+                pool.authenticator.on_notification(&pool, &notification);
+                delivery::on_notification(&pool, &notification);
+            }
+        });
+    }
+
+    pub fn ref_count(&self, url: &Url) -> usize {
+        self.refs.get(url).map(|count| *count).unwrap_or_default()
+    }
+
+    /// Takes a reference on `url`, connecting to it on the first one. No
+    /// reference is kept if the relay can't be added.
+    pub async fn acquire(&self, url: &Url) -> Result<()> {
+        let _changing = self.changing.lock().await;
+        if let Some(mut count) = self.refs.get_mut(url) {
+            *count += 1;
+            return Ok(());
+        }
+        let opts = RelayOptions::new().reconnect(false);
+        self.client.add_relay_with_opts(url.clone(), opts).await?;
+        if let Err(e) = self.client.connect_relay(url.clone()).await {
+            if let Err(e) = self.client.remove_relay(url.clone()).await {
+                tracing::warn!("removing {} failed: {:?}", url, e);
+            }
+            return Err(e.into());
+        }
+        self.refs.insert(url.clone(), 1);
+        Ok(())
+    }
+
+    /// Drops a reference on `url`, the last one closes the connection
+    pub async fn release(&self, url: &Url) -> Result<()> {
+        let _changing = self.changing.lock().await;
+        let last = match self.refs.get_mut(url) {
+            Some(mut count) => {
+                *count = count.saturating_sub(1);
+                *count == 0
+            }
+            None => false,
+        };
+        if last {
+            self.refs.remove(url);
+            self.monitor.forget(url);
+            self.authenticator.forget(url);
+            self.client.remove_relay(url.clone()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// What an edit changed in the relays of a set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelaySetDiff {
+    pub added: Vec<Url>,
+    pub removed: Vec<Url>,
+    /// Key of the `EventCache` entries made stale
+    pub old_hash: u64,
+    pub new_hash: u64,
+}
+
+impl RelaySetDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A relay set: a view over the relays of a [`SharedPool`].
+///
+/// The hash identifies the set of relays and keys the `EventCache`. Reads,
+/// subscriptions and publishing through the view only reach its own relays.
+/// Clones share the relays, an edit reaches every holder of the view.
+#[derive(Debug, Clone)]
+pub struct HashedClient {
+    pool: SharedPool,
+    relays: Arc<RwLock<BTreeSet<Url>>>,
+    hash: Arc<AtomicU64>,
 }
 
 unsafe impl Send for HashedClient {}
 unsafe impl Sync for HashedClient {}
 
-#[allow(clippy::arc_with_non_send_sync)]
 impl HashedClient {
+    /// A view owning its own pool, with the relays already added to `client`
     pub async fn new(client: Client) -> Self {
-        let hash = Self::_hash(&client).await;
+        let urls: Vec<Url> = client.relays().await.into_keys().collect();
+        let pool = SharedPool::new(client);
+        for url in urls.iter() {
+            pool.refs.insert(url.clone(), 1);
+        }
+        let relays: BTreeSet<Url> = urls.into_iter().collect();
         Self {
-            client: Arc::new(client),
-            hash,
+            pool,
+            hash: Arc::new(AtomicU64::new(Self::_hash(&relays))),
+            relays: Arc::new(RwLock::new(relays)),
         }
     }
 
-    async fn _hash(client: &nostr_sdk::Client) -> u64 {
-        let relays = client.relays().await;
+    /// An empty view over `pool`
+    pub fn with_pool(pool: SharedPool) -> Self {
+        Self {
+            pool,
+            relays: Arc::new(RwLock::new(BTreeSet::new())),
+            hash: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn _hash(relays: &BTreeSet<Url>) -> u64 {
         if relays.is_empty() {
             return 0;
         }
-        let mut sorted_keys: Vec<_> = relays.keys().collect();
-        sorted_keys.sort();
         let mut hasher = DefaultHasher::new();
-        for key in sorted_keys {
-            key.hash(&mut hasher);
+        for url in relays {
+            url.hash(&mut hasher);
         }
         hasher.finish()
     }
 
-    pub fn client(&self) -> Arc<Client> {
-        self.client.clone()
+    fn rehash(&self) -> u64 {
+        let hash = Self::_hash(&self.relays.read().unwrap());
+        self.hash.store(hash, Ordering::Relaxed);
+        hash
+    }
+
+    /// Whether `other` is a clone of this view, not just one over the same relays
+    pub fn same_set(&self, other: &HashedClient) -> bool {
+        Arc::ptr_eq(&self.relays, &other.relays)
+    }
+
+    /// The shared client, use the methods of the view to stay within the set
+    pub(crate) fn client(&self) -> Arc<Client> {
+        self.pool.client()
+    }
+
+    /// The event database, shared by every set
+    pub fn database(&self) -> Arc<DynNostrDatabase> {
+        self.pool.client.database()
+    }
+
+    /// The delivery queue of the pool, see [`SharedPool::pending_db`]
+    pub async fn pending_db(&self) -> Result<CBWebDatabase> {
+        self.pool.pending_db().await
     }
 
     pub fn hash(&self) -> u64 {
-        self.hash
+        self.hash.load(Ordering::Relaxed)
+    }
+
+    pub fn relays(&self) -> Vec<Url> {
+        self.relays.read().unwrap().iter().cloned().collect()
+    }
+
+    /// The relays queries go to, without those the monitor skips
+    pub fn query_relays(&self) -> Vec<Url> {
+        self.pool.monitor.usable(&self.relays())
     }
 
     //connect after add_relay
     pub async fn add_relay(&mut self, url: &str) -> Result<bool> {
-        let result = self.client.add_relay(url).await?;
-        if result {
-            self.hash = Self::_hash(&self.client).await;
+        let url = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_string()))?;
+        if self.relays.read().unwrap().contains(&url) {
+            return Ok(false);
         }
-        self.client.connect().await;
+        self.pool.acquire(&url).await?;
+        self.relays.write().unwrap().insert(url);
+        self.rehash();
         //todo add db operation
-        Ok(result)
+        Ok(true)
     }
 
     //connect afeter add_relays
     pub async fn add_relays(&mut self, urls: Vec<&str>) -> Result<()> {
-        self.client.add_relays(urls).await?;
-        self.hash = Self::_hash(&self.client).await;
-        self.client.connect().await;
+        for url in urls {
+            self.add_relay(url).await?;
+        }
         //todo add db operation
         Ok(())
     }
 
     pub async fn remove_relay(&mut self, url: &str) -> Result<()> {
-        self.client.remove_relay(url).await?;
-        self.hash = Self::_hash(&self.client).await;
+        let url = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_string()))?;
+        let removed = self.relays.write().unwrap().remove(&url);
+        if removed {
+            self.rehash();
+            self.pool.release(&url).await?;
+        }
         Ok(())
     }
 
     pub async fn remove_all_relays(&mut self) -> Result<()> {
-        self.client.remove_all_relays().await?;
+        let relays = std::mem::take(&mut *self.relays.write().unwrap());
+        self.hash.store(0, Ordering::Relaxed);
+        for url in relays {
+            self.pool.release(&url).await?;
+        }
+        Ok(())
+    }
+
+    /// Brings the view to exactly `relays`: connects the new ones and
+    /// releases the dropped ones. Nothing happens on an invalid url, and a
+    /// relay that can't be added rolls back the ones added before it.
+    pub async fn apply(&mut self, relays: &[String]) -> Result<RelaySetDiff> {
+        let wanted = relays
+            .iter()
+            .map(|url| Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_string())))
+            .collect::<Result<BTreeSet<Url>>>()?;
+        let current = self.relays.read().unwrap().clone();
+        let diff = RelaySetDiff {
+            added: wanted.difference(&current).cloned().collect(),
+            removed: current.difference(&wanted).cloned().collect(),
+            old_hash: self.hash(),
+            new_hash: Self::_hash(&wanted),
+        };
+        for (i, url) in diff.added.iter().enumerate() {
+            if let Err(e) = self.pool.acquire(url).await {
+                for added in diff.added[..i].iter() {
+                    self.relays.write().unwrap().remove(added);
+                    if let Err(e) = self.pool.release(added).await {
+                        tracing::warn!("releasing {} failed: {:?}", added, e);
+                    }
+                }
+                self.rehash();
+                return Err(e);
+            }
+            self.relays.write().unwrap().insert(url.clone());
+        }
+        for url in diff.removed.iter() {
+            self.relays.write().unwrap().remove(url);
+            // out of the set either way, the hash must follow
+            if let Err(e) = self.pool.release(url).await {
+                tracing::warn!("releasing {} failed: {:?}", url, e);
+            }
+        }
+        self.rehash();
+        Ok(diff)
+    }
+
+    pub async fn get_events_of(
+        &self,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>> {
+        Ok(self
+            .pool
+            .client
+            .get_events_from(self.query_relays(), filters, timeout)
+            .await?)
+    }
+
+    /// Like [`Self::get_events_of`], from some of the relays of the set
+    pub async fn get_events_from(
+        &self,
+        urls: Vec<Url>,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>> {
+        let relays = self.relays();
+        if let Some(url) = urls.iter().find(|url| !relays.contains(url)) {
+            return Err(Error::NotInSet(url.clone()));
+        }
+        Ok(self
+            .pool
+            .client
+            .get_events_from(urls, filters, timeout)
+            .await?)
+    }
+
+    /// Reconciles the stored events of `filter` with `url` (NIP-77).
+    /// Returns the ids of those only we have and how many were received.
+    pub async fn reconcile(
+        &self,
+        url: &Url,
+        filter: Filter,
+        opts: NegentropyOptions,
+    ) -> Result<(HashSet<EventId>, usize)> {
+        if !self.relays.read().unwrap().contains(url) {
+            return Err(Error::NotInSet(url.clone()));
+        }
+        let output = self
+            .pool
+            .client
+            .reconcile_with([url.clone()], filter, opts)
+            .await?;
+        if !output.success.contains(url) {
+            let reason = output
+                .failed
+                .get(url)
+                .and_then(|reason| reason.clone())
+                .unwrap_or_default();
+            return Err(Error::Reconcile(url.clone(), reason));
+        }
+        Ok((
+            output.local.iter().copied().collect(),
+            output.received.len(),
+        ))
+    }
+
+    pub async fn subscribe_with_id(
+        &self,
+        id: SubscriptionId,
+        filters: Vec<Filter>,
+        opts: Option<SubscribeAutoCloseOptions>,
+    ) -> Result<()> {
+        let relays = self.relays();
+        self.pool.monitor.record_request(&relays, &id);
+        self.pool
+            .client
+            .subscribe_with_id_to(relays, id, filters, opts)
+            .await?;
         Ok(())
     }
+
+    /// Sends `event` to the relays of the set, a failure to send at all
+    /// counts as failed on each of them so it can be retried
+    pub async fn send_event(&self, event: Event) -> PublishResult {
+        let relays = self.relays();
+        let event_id = event.id;
+        match self.pool.client.send_event_to(relays.clone(), event).await {
+            Ok(output) => PublishResult::new(event_id, output.success, output.failed),
+            Err(e) => PublishResult::failed(event_id, &relays, &e.to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -124,6 +476,8 @@ impl NostrQuery {
 #[derive(Debug, Clone)]
 pub struct MultiClient {
     clients: DashMap<String, HashedClient>,
+    pool: Arc<OnceCell<SharedPool>>,
+    outbox: Outbox,
 }
 
 impl Default for MultiClient {
@@ -134,18 +488,53 @@ impl Default for MultiClient {
 
 impl MultiClient {
     pub fn new() -> Self {
+        let pool = Arc::new(OnceCell::new());
         Self {
             clients: DashMap::new(),
+            outbox: Outbox::new(pool.clone(), Some(Duration::from_secs(5))),
+            pool,
         }
     }
 
+    /// The connections shared by all relay sets
+    pub async fn pool(&self) -> Result<SharedPool> {
+        let pool = self
+            .pool
+            .get_or_try_init(|| async {
+                let pool = SharedPool::open().await?;
+                pool.watch();
+                delivery::watch(pool.clone());
+                Ok::<_, Error>(pool)
+            })
+            .await?;
+        Ok(pool.clone())
+    }
+
+    /// Health of the relays of all sets
+    pub async fn monitor(&self) -> Result<RelayMonitor> {
+        Ok(self.pool().await?.monitor())
+    }
+
+    /// NIP-42 state of the relays of all sets
+    pub async fn authenticator(&self) -> Result<Authenticator> {
+        Ok(self.pool().await?.authenticator())
+    }
+
+    /// NIP-65 routing shared by all relay sets
+    pub fn outbox(&self) -> Outbox {
+        self.outbox.clone()
+    }
+
     pub async fn register(&self, name: String, hc: HashedClient) {
         self.clients.insert(name, hc);
     }
 
     pub async fn change_key(&self, old_key: &str, new_key: String) -> Result<()> {
         if let Some((_, client)) = self.clients.remove(old_key) {
-            self.clients.insert(new_key, client);
+            // a view left behind under the new name would keep its relays open
+            if let Some(mut replaced) = self.clients.insert(new_key, client) {
+                replaced.remove_all_relays().await?;
+            }
             Ok(())
         } else {
             Err(Error::ClientNotFoundByKey(old_key.to_string()))
@@ -162,28 +551,189 @@ impl MultiClient {
         }
 
         let database = CBWebDatabase::open(CAPYBASTR_DBNAME).await?;
-        let db = WebDatabase::open(NOSTR_DB_NAME).await?;
-        let client_builder = ClientBuilder::new().database(db);
-        let client = client_builder.build();
         let relay_set_info = database.get_relay_set(name.to_string()).await?;
+        self.create(&relay_set_info).await
+    }
 
-        let mut hc = HashedClient::new(client).await;
-        let relays: Vec<&str> = relay_set_info.relays.iter().map(|s| s.as_str()).collect();
+    /// Registers a view over the shared pool for `relay_set`
+    pub async fn create(&self, relay_set: &RelaySet) -> Result<HashedClient> {
+        let pool = self.pool().await?;
+        pool.authenticator().set_policies(relay_set);
+        let mut hc = HashedClient::with_pool(pool);
+        let relays: Vec<&str> = relay_set.relays.iter().map(|s| s.as_str()).collect();
         hc.add_relays(relays).await?;
-        self.register(name.to_string(), hc.clone()).await;
-
+        self.register(relay_set.name.clone(), hc.clone()).await;
         Ok(hc)
     }
+
+    /// Applies an edit of the set registered as `old_name` to its live view,
+    /// renamed to the name of `relay_set`. `None` if the set was never
+    /// opened, it is created from the saved set when first used.
+    pub async fn update(
+        &self,
+        old_name: &str,
+        relay_set: &RelaySet,
+    ) -> Result<Option<(HashedClient, RelaySetDiff)>> {
+        let Some(mut hc) = self.get_client(old_name).await else {
+            return Ok(None);
+        };
+        self.pool().await?.authenticator().set_policies(relay_set);
+        let diff = hc.apply(&relay_set.relays).await?;
+        // renamed once the relays are in, a failed edit leaves the view as it was
+        if old_name != relay_set.name {
+            self.change_key(old_name, relay_set.name.clone()).await?;
+        }
+        Ok(Some((hc, diff)))
+    }
+
+    /// Unregisters a relay set, closing the relays no other set uses
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        if let Some((_, mut hc)) = self.clients.remove(name) {
+            hc.remove_all_relays().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies a saved edit of a relay set to everything running on it: the
+/// view, the subscriptions `register` holds for it and the cached queries
+pub async fn apply_relay_set_edit(
+    multiclient: &MultiClient,
+    register: &Register,
+    cache: &EventCache,
+    old_name: &str,
+    relay_set: &RelaySet,
+) -> Result<Option<RelaySetDiff>> {
+    let Some((hc, diff)) = multiclient.update(old_name, relay_set).await? else {
+        return Ok(None);
+    };
+    if diff.is_empty() {
+        return Ok(Some(diff));
+    }
+    if let Err(e) = register.apply_relay_set_diff(&hc, &diff).await {
+        tracing::warn!(
+            "re-issuing subscriptions of {} failed: {:?}",
+            relay_set.name,
+            e
+        );
+    }
+    cache.invalidate(diff.old_hash).await;
+    Ok(Some(diff))
 }
 
-type Cache = Arc<Mutex<TimedCache<NostrQuery, Vec<Event>>>>;
-type PendingQueries = Arc<DashMap<NostrQuery, ()>>;
+// a cached query older than this is served, then refreshed in the background
+const REFRESH_AFTER: u64 = 30;
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
 
+#[derive(Debug, Clone)]
+struct CachedQuery {
+    filters: Vec<Filter>,
+    /// Relays of the relay set the query was asked to
+    relays: HashSet<Url>,
+    /// Newest first
+    events: Vec<Event>,
+    refreshed_at: Timestamp,
+}
+
+impl CachedQuery {
+    fn new(
+        filters: Vec<Filter>,
+        relays: HashSet<Url>,
+        events: Vec<Event>,
+        refreshed_at: Timestamp,
+    ) -> Self {
+        let mut cached = Self {
+            filters,
+            relays,
+            events: Vec::new(),
+            refreshed_at,
+        };
+        cached.merge(events);
+        cached
+    }
+
+    /// Whether `event` from `relay_url` belongs to the result
+    fn accepts(&self, relay_url: &Url, event: &Event) -> bool {
+        self.relays.contains(relay_url) && self.filters.iter().any(|f| f.match_event(event))
+    }
+
+    /// Where a refresh picks up: the newest event held
+    fn cursor(&self) -> Option<Timestamp> {
+        self.events.first().map(|event| event.created_at())
+    }
+
+    fn is_stale(&self) -> bool {
+        Timestamp::now().as_u64() > self.refreshed_at.as_u64() + REFRESH_AFTER
+    }
+
+    /// Adds the events not held yet, returns whether the result changed
+    fn merge(&mut self, events: impl IntoIterator<Item = Event>) -> bool {
+        let before: Vec<EventId> = self.events.iter().map(|event| event.id).collect();
+        for event in events {
+            if !self.events.iter().any(|held| held.id == event.id) {
+                self.events.push(event);
+            }
+        }
+        if self.events.len() == before.len() {
+            return false;
+        }
+        self.events
+            .sort_by(|a, b| b.created_at().cmp(&a.created_at()));
+        self.trim();
+        !self.events.iter().map(|event| event.id).eq(before)
+    }
+
+    /// Keeps what the relays would return: the newest `limit` events of
+    /// each filter. An event no filter matches locally, like a search
+    /// result, was the relay's call and stays.
+    fn trim(&mut self) {
+        let mut left: Vec<Option<usize>> = self.filters.iter().map(|f| f.limit).collect();
+        let filters = &self.filters;
+        self.events.retain(|event| {
+            let matching: Vec<usize> = (0..filters.len())
+                .filter(|i| filters[*i].match_event(event))
+                .collect();
+            let keep =
+                matching.is_empty() || matching.iter().any(|i| left[*i].map_or(true, |n| n > 0));
+            if keep {
+                for i in matching {
+                    if let Some(n) = left[i].as_mut() {
+                        *n = n.saturating_sub(1);
+                    }
+                }
+            }
+            keep
+        });
+    }
+}
+
+// removes the entry of a query in flight even if its future is dropped,
+// which also drops the sender and fails the waiters
+struct PendingGuard<'a> {
+    pending_queries: &'a PendingQueries,
+    query: NostrQuery,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending_queries.remove(&self.query);
+    }
+}
+
+type Cache = Arc<Mutex<TimedCache<NostrQuery, CachedQuery>>>;
+// the waiters of a query in flight, the sender is dropped if it fails
+type PendingQueries = Arc<DashMap<NostrQuery, watch::Receiver<Option<Vec<Event>>>>>;
+type Subscribers = Arc<DashMap<NostrQuery, watch::Sender<Vec<Event>>>>;
+
+/// Query results by relay set, served stale while revalidating: a cached
+/// or locally stored result comes back right away and is refreshed from
+/// the relays in the background, asking only for what is newer. Events
+/// arriving on live subscriptions are merged in by the [`Register`].
 #[derive(Debug, Clone)]
 pub struct EventCache {
     cache: Cache,
     pending_queries: PendingQueries,
-    notify: Arc<Notify>,
+    subscribers: Subscribers,
 }
 
 impl EventCache {
@@ -193,88 +743,202 @@ impl EventCache {
                 lifespan, capacity,
             ))),
             pending_queries: Arc::new(DashMap::new()),
-            notify: Arc::new(Notify::new()),
+            subscribers: Arc::new(DashMap::new()),
         }
     }
 
-    pub async fn cached_get_events_of(
+    /// Drops the queries cached for the relay set hashing to `client_hash`
+    pub async fn invalidate(&self, client_hash: u64) -> usize {
+        let mut cache = self.cache.lock().await;
+        let stale: Vec<NostrQuery> = cache
+            .get_store()
+            .keys()
+            .filter(|query| query.client_hash == client_hash)
+            .cloned()
+            .collect();
+        for query in stale.iter() {
+            cache.cache_remove(query);
+        }
+        stale.len()
+    }
+
+    /// The results of `filters` on `client`, pushed again each time a
+    /// refresh or a live event changes them
+    pub async fn subscribe(
         &self,
         client: &HashedClient,
-        filters: Vec<Filter>,
-        timeout: Option<Duration>,
-    ) -> Result<Vec<Event>> {
-        let query = NostrQuery::new(client.hash(), &filters);
+        filters: &Vec<Filter>,
+    ) -> watch::Receiver<Vec<Event>> {
+        let query = NostrQuery::new(client.hash(), filters);
+        let current = self
+            .cache
+            .lock()
+            .await
+            .cache_get(&query)
+            .map(|cached| cached.events.clone())
+            .unwrap_or_default();
+        self.subscribers
+            .entry(query)
+            .or_insert_with(|| watch::channel(current).0)
+            .subscribe()
+    }
 
-        // First, check the cache
-        {
-            let mut cache = self.cache.lock().await;
-            if let Some(cached_result) = cache.cache_get(&query) {
-                return Ok(cached_result.clone());
+    fn publish(&self, query: &NostrQuery, events: Vec<Event>) {
+        let subscribed = match self.subscribers.get(query) {
+            Some(sender) if sender.receiver_count() > 0 => {
+                sender.send_replace(events);
+                true
             }
+            Some(_) => false,
+            None => return,
+        };
+        if !subscribed {
+            self.subscribers.remove(query);
         }
+    }
 
-        // If not cached, check if query is already pending
-        {
-            if self.pending_queries.contains_key(&query) {
-                self.notify.notified().await; // Wait for the ongoing query to finish
-                let mut cache = self.cache.lock().await;
-                if let Some(cached_result) = cache.cache_get(&query) {
-                    return Ok(cached_result.clone());
-                } else {
-                    return Err(Error::QueryFailedOrNotCached);
-                }
-            } else {
-                self.pending_queries.insert(query.clone(), ());
-            }
+    /// Merges `event`, just arrived from `relay_url` on a subscription, into
+    /// the cached queries it matches whose relay set has that relay. Returns
+    /// how many changed.
+    pub async fn on_event(&self, relay_url: &Url, event: &Event) -> usize {
+        let updated: Vec<(NostrQuery, Vec<Event>)> = {
+            let mut cache = self.cache.lock().await;
+            let matching: Vec<NostrQuery> = cache
+                .get_store()
+                .iter()
+                .filter(|(_, (_, cached))| cached.accepts(relay_url, event))
+                .map(|(query, _)| query.clone())
+                .collect();
+            matching
+                .into_iter()
+                .filter_map(|query| {
+                    let cached = cache.cache_get_mut(&query)?;
+                    cached
+                        .merge([event.clone()])
+                        .then(|| (query, cached.events.clone()))
+                })
+                .collect()
+        };
+        for (query, events) in updated.iter() {
+            self.publish(query, events.clone());
         }
+        updated.len()
+    }
 
-        // Perform the query
-        let result = {
-            let db_events = match client
-                .client
-                .database()
-                .query(filters.clone(), Order::Desc)
-                .await
-            {
-                Ok(result) => result,
-                Err(_) => {
-                    vec![]
-                    // self.pending_queries.remove(&query);
-                    // self.notify.notify_waiters();
-                    // return Err(Error::Client(e));
-                }
+    /// Asks the relays of `client` for what is newer than the cached
+    /// result in the background, then merges and pushes it
+    fn revalidate(&self, client: &HashedClient, query: NostrQuery, cached: CachedQuery) {
+        if self.pending_queries.contains_key(&query) {
+            return;
+        }
+        let (sender, receiver) = watch::channel(None);
+        self.pending_queries.insert(query.clone(), receiver);
+        let event_cache = self.clone();
+        let client = client.clone();
+        spawn_local(async move {
+            let _pending = PendingGuard {
+                pending_queries: &event_cache.pending_queries,
+                query: query.clone(),
             };
-            if !db_events.is_empty() {
-                db_events
-            } else {
-                match client.client.get_events_of(filters.clone(), timeout).await {
-                    Ok(result) => result,
-                    Err(e) => {
-                        self.pending_queries.remove(&query);
-                        self.notify.notify_waiters();
-                        return Err(Error::Client(e));
+            let filters: Vec<Filter> = match cached.cursor() {
+                Some(since) => cached
+                    .filters
+                    .iter()
+                    .map(|filter| filter.clone().since(since))
+                    .collect(),
+                None => cached.filters.clone(),
+            };
+            match client.get_events_of(filters, Some(REFRESH_TIMEOUT)).await {
+                Ok(events) => {
+                    let (current, changed) = {
+                        let mut cache = event_cache.cache.lock().await;
+                        // live events may have come in meanwhile
+                        let mut current = cache.cache_get(&query).cloned().unwrap_or(cached);
+                        let changed = current.merge(events);
+                        current.refreshed_at = Timestamp::now();
+                        cache.cache_set(query.clone(), current.clone());
+                        (current, changed)
+                    };
+                    sender.send_replace(Some(current.events.clone()));
+                    if changed {
+                        event_cache.publish(&query, current.events);
                     }
                 }
+                Err(e) => tracing::warn!("refreshing a cached query failed: {:?}", e),
             }
-        };
+        });
+    }
 
-        // Cache the result
-        {
-            let mut cache = self.cache.lock().await;
-            cache.cache_set(query.clone(), result.clone());
+    pub async fn cached_get_events_of(
+        &self,
+        client: &HashedClient,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>> {
+        let query = NostrQuery::new(client.hash(), &filters);
+
+        // First, check the cache, a stale result is refreshed afterwards
+        let cached = self.cache.lock().await.cache_get(&query).cloned();
+        if let Some(cached) = cached {
+            let events = cached.events.clone();
+            if cached.is_stale() {
+                self.revalidate(client, query, cached);
+            }
+            return Ok(events);
         }
 
-        // Remove from pending and notify other waiters
-        self.pending_queries.remove(&query);
-        self.notify.notify_waiters();
+        // If not cached, wait for the same query if it is already pending
+        let pending = self
+            .pending_queries
+            .get(&query)
+            .map(|receiver| receiver.clone());
+        if let Some(mut receiver) = pending {
+            let result = receiver
+                .wait_for(Option::is_some)
+                .await
+                .map(|events| events.clone().unwrap_or_default());
+            return result.map_err(|_| Error::QueryFailedOrNotCached);
+        }
+        let (sender, receiver) = watch::channel(None);
+        self.pending_queries.insert(query.clone(), receiver);
+        let pending = PendingGuard {
+            pending_queries: &self.pending_queries,
+            query: query.clone(),
+        };
+
+        // Stored events come back right away, relays are asked afterwards
+        let db_events = client
+            .database()
+            .query(filters.clone(), Order::Desc)
+            .await
+            .unwrap_or_default();
+        let relays: HashSet<Url> = client.relays().into_iter().collect();
+        let cached = if db_events.is_empty() {
+            let events = client.get_events_of(filters.clone(), timeout).await?;
+            CachedQuery::new(filters, relays, events, Timestamp::now())
+        } else {
+            CachedQuery::new(filters, relays, db_events, Timestamp::from(0))
+        };
+
+        self.cache
+            .lock()
+            .await
+            .cache_set(query.clone(), cached.clone());
+        sender.send_replace(Some(cached.events.clone()));
+        drop(pending);
+        self.publish(&query, cached.events.clone());
 
-        Ok(result)
+        let events = cached.events.clone();
+        if cached.is_stale() {
+            self.revalidate(client, query, cached);
+        }
+        Ok(events)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use nostr_sdk::{FromBech32, Kind, PublicKey};
+    use nostr_sdk::{EventBuilder, FromBech32, Keys, Kind, PublicKey};
     //use tokio::sync::oneshot;
     use wasm_bindgen_futures::spawn_local;
     use wasm_bindgen_test::*;
@@ -314,6 +978,156 @@ mod tests {
         assert_ne!(hc.hash(), 0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_shared_pool_ref_count() {
+        let pool = SharedPool::new(nostr_sdk::Client::default());
+        let url = Url::parse("wss://relay.damus.io").unwrap();
+        let mut a = HashedClient::with_pool(pool.clone());
+        let mut b = HashedClient::with_pool(pool.clone());
+        a.add_relays(vec!["wss://relay.damus.io", "wss://nos.lol"])
+            .await
+            .unwrap();
+        b.add_relay("wss://relay.damus.io").await.unwrap();
+        assert_eq!(pool.ref_count(&url), 2);
+        assert_eq!(pool.client().relays().await.len(), 2);
+        assert_ne!(a.hash(), b.hash());
+
+        a.remove_all_relays().await.unwrap();
+        assert_eq!(a.hash(), 0);
+        assert_eq!(pool.ref_count(&url), 1);
+        assert_eq!(pool.client().relays().await.len(), 1);
+
+        b.remove_relay("wss://relay.damus.io").await.unwrap();
+        assert_eq!(pool.ref_count(&url), 0);
+        assert!(pool.client().relays().await.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_apply_relay_set_edit() {
+        let pool = SharedPool::new(nostr_sdk::Client::default());
+        let kept = Url::parse("wss://relay.damus.io").unwrap();
+        let dropped = Url::parse("wss://nos.lol").unwrap();
+        let mut hc = HashedClient::with_pool(pool.clone());
+        hc.add_relays(vec!["wss://relay.damus.io", "wss://nos.lol"])
+            .await
+            .unwrap();
+        let view = hc.clone();
+        let old_hash = hc.hash();
+
+        let diff = hc
+            .apply(&[
+                "wss://relay.damus.io".to_string(),
+                "wss://nostr.oxtr.dev".to_string(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(diff.removed, vec![dropped.clone()]);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.old_hash, old_hash);
+        assert_ne!(diff.new_hash, old_hash);
+        // clones see the edit
+        assert!(view.same_set(&hc));
+        assert_eq!(view.hash(), diff.new_hash);
+        assert_eq!(view.relays().len(), 2);
+        assert_eq!(pool.ref_count(&kept), 1);
+        assert_eq!(pool.ref_count(&dropped), 0);
+
+        assert!(hc.apply(&["not a url".to_string()]).await.is_err());
+        assert_eq!(hc.hash(), diff.new_hash);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_relay_set_scope() {
+        let pool = SharedPool::new(nostr_sdk::Client::default());
+        let mut hc = HashedClient::with_pool(pool.clone());
+        hc.add_relay("wss://relay.damus.io").await.unwrap();
+        let mut other = HashedClient::with_pool(pool);
+        other.add_relay("wss://nos.lol").await.unwrap();
+
+        let outside = Url::parse("wss://nos.lol").unwrap();
+        let filter = Filter::new().kind(Kind::TextNote).limit(1);
+        assert!(matches!(
+            hc.get_events_from(vec![outside.clone()], vec![filter.clone()], None)
+                .await,
+            Err(Error::NotInSet(url)) if url == outside
+        ));
+        assert!(matches!(
+            hc.reconcile(&outside, filter, NegentropyOptions::new())
+                .await,
+            Err(Error::NotInSet(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_cache_merges_live_events() {
+        let keys = Keys::generate();
+        let note = |content: &str, created_at: u64| {
+            EventBuilder::text_note(content, [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let old = note("old", 1);
+        let filters = vec![Filter::new().kind(Kind::TextNote).author(keys.public_key())];
+        let relay = Url::parse("wss://relay.damus.io").unwrap();
+        let mut cached = CachedQuery::new(
+            filters.clone(),
+            HashSet::from([relay.clone()]),
+            vec![old.clone()],
+            Timestamp::now(),
+        );
+        assert_eq!(cached.cursor(), Some(Timestamp::from(1)));
+        assert!(!cached.merge([old.clone()]));
+        assert!(!cached.is_stale());
+
+        let hashed_client = HashedClient::new(nostr_sdk::Client::default()).await;
+        let cache = EventCache::new(30, 300);
+        let query = NostrQuery::new(hashed_client.hash(), &filters);
+        cache.cache.lock().await.cache_set(query, cached);
+        let mut receiver = cache.subscribe(&hashed_client, &filters).await;
+
+        let new = note("new", 2);
+        // a relay outside the relay set is not part of the result
+        let other = Url::parse("wss://nos.lol").unwrap();
+        assert_eq!(cache.on_event(&other, &new).await, 0);
+        assert_eq!(cache.on_event(&relay, &new).await, 1);
+        // a relay sending it again changes nothing
+        assert_eq!(cache.on_event(&relay, &new).await, 0);
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(*receiver.borrow_and_update(), vec![new, old]);
+        let reaction = EventBuilder::new(Kind::Reaction, "+", [])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(cache.on_event(&relay, &reaction).await, 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_cache_keeps_filter_limit() {
+        let keys = Keys::generate();
+        let note = |created_at: u64| {
+            EventBuilder::text_note("gm", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let filters = vec![Filter::new().kind(Kind::TextNote).limit(2)];
+        let mut cached = CachedQuery::new(
+            filters,
+            HashSet::new(),
+            vec![note(1), note(2), note(3)],
+            Timestamp::now(),
+        );
+        assert_eq!(cached.events.len(), 2);
+        assert_eq!(cached.cursor(), Some(Timestamp::from(3)));
+
+        // older than the limit window, the result stays the same
+        assert!(!cached.merge([note(0)]));
+        let newest = note(4);
+        assert!(cached.merge([newest.clone()]));
+        assert_eq!(cached.events.len(), 2);
+        assert_eq!(cached.events[0], newest);
+    }
+
     #[wasm_bindgen_test]
     async fn test_multi_client_cached_query() {
         let client = nostr_sdk::Client::default();
@@ -415,11 +1229,10 @@ mod tests {
             .await;
         let filter: Filter = Filter::new().kind(Kind::TextNote).author(public_key);
         let hc = multi_client.get_client("client1").await.unwrap();
-        let c = hc.client();
 
         // Create a oneshot channel.
         let (tx, rx) = tokio::sync::oneshot::channel();
-        let mut paginator = EventPaginator::new(c, vec![filter], None, 10, false);
+        let mut paginator = EventPaginator::new(hc, vec![filter], None, 10, false);
 
         spawn_local(async move {
             let e = paginator.next_page().await.unwrap();
@@ -0,0 +1,474 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use cached::{Cached, TimedCache};
+use nostr_indexeddb::database::Order;
+use nostr_sdk::nips::nip21::Nip21;
+use nostr_sdk::nips::nip65::RelayMetadata;
+use nostr_sdk::{
+    Client, Event, EventId, Filter, Kind, PublicKey, SubscriptionId, TagStandard, Timestamp, Url,
+};
+use thiserror::Error;
+use tokio::sync::{Mutex, OnceCell};
+
+use super::multiclient::{HashedClient, SharedPool};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Pool(#[from] super::multiclient::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// write relays an author is read from, more only adds duplicates
+const MAX_RELAYS_PER_AUTHOR: usize = 2;
+// relay lists rarely change, refetch them every 10 minutes
+const RELAY_LIST_LIFESPAN: u64 = 600;
+const RELAY_LIST_CAPACITY: usize = 2000;
+// relays opened for routing kept connected, the least recently used go first
+const MAX_ROUTING_RELAYS: usize = 20;
+
+/// A NIP-65 relay list (kind 10002)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RelayList {
+    pub read: Vec<Url>,
+    pub write: Vec<Url>,
+    pub created_at: Timestamp,
+}
+
+impl RelayList {
+    /// A relay without marker is used for both reading and writing
+    pub fn from_event(event: &Event) -> Self {
+        let mut list = Self {
+            created_at: event.created_at(),
+            ..Default::default()
+        };
+        for tag in event.tags() {
+            if let Some(TagStandard::RelayMetadata {
+                relay_url,
+                metadata,
+            }) = tag.clone().to_standardized()
+            {
+                if metadata != Some(RelayMetadata::Write) && !list.read.contains(&relay_url) {
+                    list.read.push(relay_url.clone());
+                }
+                if metadata != Some(RelayMetadata::Read) && !list.write.contains(&relay_url) {
+                    list.write.push(relay_url);
+                }
+            }
+        }
+        list
+    }
+}
+
+/// Groups `authors` by the write relays to read them from.
+///
+/// Every author is read from up to [`MAX_RELAYS_PER_AUTHOR`] of their write
+/// relays, preferring relays already picked for others to keep the number of
+/// connections low. Authors without a relay list are returned apart.
+pub fn route_authors(
+    relay_lists: &HashMap<PublicKey, RelayList>,
+    authors: &[PublicKey],
+) -> (HashMap<Url, Vec<PublicKey>>, Vec<PublicKey>) {
+    // how many authors write to each relay
+    let mut popularity: HashMap<&Url, usize> = HashMap::new();
+    for author in authors {
+        if let Some(list) = relay_lists.get(author) {
+            for url in list.write.iter() {
+                *popularity.entry(url).or_default() += 1;
+            }
+        }
+    }
+
+    let mut routes: HashMap<Url, Vec<PublicKey>> = HashMap::new();
+    let mut unrouted = Vec::new();
+    for author in authors {
+        let Some(list) = relay_lists
+            .get(author)
+            .filter(|list| !list.write.is_empty())
+        else {
+            unrouted.push(*author);
+            continue;
+        };
+        let mut candidates: Vec<&Url> = list.write.iter().collect();
+        candidates.sort_by_key(|url| {
+            (
+                !routes.contains_key(*url),
+                std::cmp::Reverse(popularity.get(url).copied().unwrap_or_default()),
+            )
+        });
+        for url in candidates.into_iter().take(MAX_RELAYS_PER_AUTHOR) {
+            routes.entry(url.clone()).or_default().push(*author);
+        }
+    }
+    (routes, unrouted)
+}
+
+/// Public keys mentioned with `nostr:npub...` or `nostr:nprofile...` in `content`
+pub fn mentioned_public_keys(content: &str) -> Vec<PublicKey> {
+    let mut ret = Vec::new();
+    for word in content.split(|c: char| c.is_whitespace() || c == ',' || c == ')') {
+        let pk = match Nip21::parse(word.trim_end_matches(['.', '!', '?'])) {
+            Ok(Nip21::Pubkey(pk)) => pk,
+            Ok(Nip21::Profile(profile)) => profile.public_key,
+            _ => continue,
+        };
+        if !ret.contains(&pk) {
+            ret.push(pk);
+        }
+    }
+    ret
+}
+
+/// Outbox model routing (NIP-65) on top of the relay sets of `MultiClient`.
+///
+/// Events of an author are read from the author's write relays and events are
+/// delivered to the read relays of the users they mention. Relays that are
+/// not part of a relay set are opened on demand in the shared pool, and
+/// released once more than [`MAX_ROUTING_RELAYS`] were used since.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    pool: Arc<OnceCell<SharedPool>>,
+    // relays the outbox holds a pool reference on, least recently used first
+    acquired: Arc<Mutex<VecDeque<Url>>>,
+    relay_lists: Arc<Mutex<TimedCache<PublicKey, RelayList>>>,
+    timeout: Option<Duration>,
+}
+
+impl Outbox {
+    pub fn new(pool: Arc<OnceCell<SharedPool>>, timeout: Option<Duration>) -> Self {
+        Self {
+            pool,
+            acquired: Arc::new(Mutex::new(VecDeque::new())),
+            relay_lists: Arc::new(Mutex::new(TimedCache::with_lifespan_and_capacity(
+                RELAY_LIST_LIFESPAN,
+                RELAY_LIST_CAPACITY,
+            ))),
+            timeout,
+        }
+    }
+
+    async fn connect_to(&self, urls: &[Url]) -> Result<Arc<Client>> {
+        let pool = self.pool.get_or_try_init(SharedPool::open).await?;
+        let mut acquired = self.acquired.lock().await;
+        for url in urls {
+            match acquired.iter().position(|held| held == url) {
+                Some(pos) => {
+                    acquired.remove(pos);
+                }
+                None => pool.acquire(url).await?,
+            }
+            acquired.push_back(url.clone());
+        }
+        // the relays of this call are at the back and stay
+        while acquired.len() > MAX_ROUTING_RELAYS.max(urls.len()) {
+            if let Some(url) = acquired.pop_front() {
+                if let Err(e) = pool.release(&url).await {
+                    tracing::warn!("releasing {} failed: {:?}", url, e);
+                }
+            }
+        }
+        Ok(pool.client())
+    }
+
+    /// Relay lists of `authors` from the cache, or else the newest of the
+    /// database and the relays of `fallback`.
+    ///
+    /// A list is asked from the relays again once its cache entry is older
+    /// than [`RELAY_LIST_LIFESPAN`], the stored one only stands in for
+    /// relays that don't answer.
+    pub async fn relay_lists(
+        &self,
+        fallback: &HashedClient,
+        authors: &[PublicKey],
+    ) -> Result<HashMap<PublicKey, RelayList>> {
+        let mut lists = HashMap::new();
+        let mut missing: Vec<PublicKey> = Vec::new();
+        {
+            let mut cache = self.relay_lists.lock().await;
+            for author in authors {
+                match cache.cache_get(author) {
+                    Some(list) => {
+                        lists.insert(*author, list.clone());
+                    }
+                    None => missing.push(*author),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(lists);
+        }
+
+        let filter = Filter::new().kind(Kind::RelayList).authors(missing.clone());
+        let mut events = fallback
+            .database()
+            .query(vec![filter.clone()], Order::Desc)
+            .await
+            .unwrap_or_default();
+        match fallback.get_events_of(vec![filter], self.timeout).await {
+            Ok(fetched) => events.extend(fetched),
+            Err(e) if !events.is_empty() => {
+                tracing::warn!("relay lists fetch failed, using stored ones: {:?}", e)
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut newest: HashMap<PublicKey, RelayList> = HashMap::new();
+        for event in events.iter() {
+            let list = RelayList::from_event(event);
+            match newest.get(&event.author()) {
+                Some(current) if current.created_at >= list.created_at => {}
+                _ => {
+                    newest.insert(event.author(), list);
+                }
+            }
+        }
+        let mut cache = self.relay_lists.lock().await;
+        for author in missing {
+            // authors without a list are cached as empty to not ask again
+            let list = newest.remove(&author).unwrap_or_default();
+            cache.cache_set(author, list.clone());
+            if !list.write.is_empty() || !list.read.is_empty() {
+                lists.insert(author, list);
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Splits `filters` by the write relays of their authors.
+    ///
+    /// Filters without authors and the authors without a relay list stay on
+    /// `fallback` and are returned with the `None` key.
+    pub async fn split_filters(
+        &self,
+        fallback: &HashedClient,
+        filters: Vec<Filter>,
+    ) -> Result<HashMap<Option<Url>, Vec<Filter>>> {
+        let authors: Vec<PublicKey> = filters
+            .iter()
+            .filter_map(|f| f.authors.as_ref())
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let relay_lists = self.relay_lists(fallback, &authors).await?;
+
+        let mut split: HashMap<Option<Url>, Vec<Filter>> = HashMap::new();
+        for filter in filters {
+            let Some(authors) = filter.authors.clone() else {
+                split.entry(None).or_default().push(filter);
+                continue;
+            };
+            let authors: Vec<PublicKey> = authors.into_iter().collect();
+            let (routes, unrouted) = route_authors(&relay_lists, &authors);
+            // `Filter::authors` extends the set, so the field is replaced
+            for (url, authors) in routes {
+                let mut f = filter.clone();
+                f.authors = Some(authors.into_iter().collect());
+                split.entry(Some(url)).or_default().push(f);
+            }
+            if !unrouted.is_empty() {
+                let mut f = filter.clone();
+                f.authors = Some(unrouted.into_iter().collect());
+                split.entry(None).or_default().push(f);
+            }
+        }
+        Ok(split)
+    }
+
+    /// Queries every author on their write relays, newest first
+    pub async fn get_events_of(
+        &self,
+        fallback: &HashedClient,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>> {
+        let split = self.split_filters(fallback, filters).await?;
+        let urls: Vec<Url> = split.keys().flatten().cloned().collect();
+        let client = self.connect_to(&urls).await?;
+
+        let queries = split.into_iter().map(|(url, filters)| {
+            let client = client.clone();
+            async move {
+                match url {
+                    Some(url) => client
+                        .get_events_from([url], filters, timeout)
+                        .await
+                        .map_err(Error::from),
+                    None => fallback
+                        .get_events_of(filters, timeout)
+                        .await
+                        .map_err(Error::from),
+                }
+            }
+        });
+
+        let mut seen: HashSet<EventId> = HashSet::new();
+        let mut events = Vec::new();
+        for result in futures::future::join_all(queries).await {
+            match result {
+                Ok(batch) => events.extend(batch.into_iter().filter(|e| seen.insert(e.id))),
+                // one unreachable relay must not hide the others
+                Err(e) => tracing::warn!("outbox query failed: {:?}", e),
+            }
+        }
+        events.sort_by(|a, b| b.created_at().cmp(&a.created_at()));
+        Ok(events)
+    }
+
+    /// Subscribes the routed part of `filters` on the write relays of the
+    /// authors and returns the filters left for `fallback`
+    pub async fn subscribe(
+        &self,
+        fallback: &HashedClient,
+        sub_id: SubscriptionId,
+        filters: Vec<Filter>,
+    ) -> Result<Vec<Filter>> {
+        let mut split = self.split_filters(fallback, filters).await?;
+        let rest = split.remove(&None).unwrap_or_default();
+        let urls: Vec<Url> = split.keys().flatten().cloned().collect();
+        let client = self.connect_to(&urls).await?;
+        for (url, filters) in split {
+            if let Some(url) = url {
+                if let Err(e) = client
+                    .subscribe_with_id_to([url], sub_id.clone(), filters, None)
+                    .await
+                {
+                    tracing::warn!("outbox subscribe failed: {:?}", e);
+                }
+            }
+        }
+        Ok(rest)
+    }
+
+    /// Sends `event`, already published to the relay set `client`, to the
+    /// author's write relays and to the read relays of every tagged user.
+    ///
+    /// Returns the extra relays that accepted it.
+    pub async fn deliver(&self, client: &HashedClient, event: &Event) -> Result<Vec<Url>> {
+        let mut recipients: Vec<PublicKey> = event.public_keys().copied().collect();
+        recipients.retain(|pk| *pk != event.author());
+        let mut authors = recipients.clone();
+        authors.push(event.author());
+        let relay_lists = self.relay_lists(client, &authors).await?;
+
+        let own: HashSet<Url> = client.relays().into_iter().collect();
+        let mut urls: Vec<Url> = Vec::new();
+        if let Some(list) = relay_lists.get(&event.author()) {
+            urls.extend(list.write.iter().cloned());
+        }
+        for pk in recipients.iter() {
+            if let Some(list) = relay_lists.get(pk) {
+                urls.extend(list.read.iter().take(MAX_RELAYS_PER_AUTHOR).cloned());
+            }
+        }
+        let urls: Vec<Url> = urls
+            .into_iter()
+            .filter(|url| !own.contains(url))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if urls.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let routing = self.connect_to(&urls).await?;
+        let mut delivered = Vec::new();
+        for url in urls {
+            match routing.send_event_to([url.clone()], event.clone()).await {
+                Ok(_) => delivered.push(url),
+                Err(e) => tracing::warn!("outbox delivery to {} failed: {:?}", url, e),
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys, ToBech32};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_relay_list_from_event() {
+        let keys = Keys::generate();
+        let event = EventBuilder::relay_list([
+            (url("wss://read.example.com"), Some(RelayMetadata::Read)),
+            (url("wss://write.example.com"), Some(RelayMetadata::Write)),
+            (url("wss://both.example.com"), None),
+        ])
+        .to_event(&keys)
+        .unwrap();
+        let list = RelayList::from_event(&event);
+        assert_eq!(
+            list.read,
+            vec![url("wss://read.example.com"), url("wss://both.example.com")]
+        );
+        assert_eq!(
+            list.write,
+            vec![
+                url("wss://write.example.com"),
+                url("wss://both.example.com")
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_route_authors() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let carol = Keys::generate().public_key();
+        let shared = url("wss://shared.example.com");
+        let lists = HashMap::from([
+            (
+                alice,
+                RelayList {
+                    write: vec![
+                        url("wss://a1.example.com"),
+                        url("wss://a2.example.com"),
+                        shared.clone(),
+                    ],
+                    ..Default::default()
+                },
+            ),
+            (
+                bob,
+                RelayList {
+                    write: vec![shared.clone()],
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let (routes, unrouted) = route_authors(&lists, &[alice, bob, carol]);
+        assert_eq!(unrouted, vec![carol]);
+        // the relay both write to is picked first
+        assert_eq!(routes.get(&shared).unwrap(), &vec![alice, bob]);
+        let alice_relays = routes.values().filter(|a| a.contains(&alice)).count();
+        assert_eq!(alice_relays, MAX_RELAYS_PER_AUTHOR);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mentioned_public_keys() {
+        let pk = Keys::generate().public_key();
+        let content = format!(
+            "hello nostr:{}, how are you? nostr:{}",
+            pk.to_bech32().unwrap(),
+            pk.to_bech32().unwrap()
+        );
+        assert_eq!(mentioned_public_keys(&content), vec![pk]);
+        assert!(mentioned_public_keys("no mentions here").is_empty());
+    }
+}
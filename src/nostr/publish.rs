@@ -1,207 +1,376 @@
 use nostr_sdk::nips::nip65::RelayMetadata;
 use nostr_sdk::nips::nip94::FileMetadata;
 use nostr_sdk::{
-    Client, Contact, Event, EventBuilder, EventId, Filter, Kind, Metadata, NostrSigner, PublicKey,
-    Tag, TagStandard, Timestamp, UncheckedUrl, Url,
+    Contact, Event, EventBuilder, EventId, Metadata, NostrSigner, PublicKey, Tag, Timestamp,
+    UncheckedUrl, Url,
 };
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::time::Duration;
 use thiserror::Error;
 
+use super::contacts::{
+    newest_contact_list, publish_contact_list, replace_contacts, with_contact, without_contact,
+};
+use super::delivery::queue_failed;
+use super::dm::gift_wrap;
+use super::multiclient::HashedClient;
+use super::outbox::{mentioned_public_keys, Outbox};
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
     Client(#[from] nostr_sdk::client::Error),
     #[error(transparent)]
     Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    GiftWrap(#[from] super::dm::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    ContactList(#[from] super::contacts::Error),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-macro_rules! sign_and_send_event {
-    ($client:expr, $signer:expr, $builder:expr) => {{
-        let event = $signer.sign_event_builder($builder).await?;
-        let eid = $client.send_event(event).await?;
-        Ok(*eid)
-    }};
+/// How a relay answered an event sent to it
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayOutcome {
+    /// OK true, or a duplicate the relay already had
+    Accepted,
+    /// OK false for a reason sending again won't change
+    Rejected(String),
+    /// No OK: not connected, timed out or told to come back later
+    Failed(String),
+}
+
+impl RelayOutcome {
+    /// Sorts the reason the pool gives for a relay it couldn't deliver to by
+    /// the machine-readable prefix of the OK message, if the relay sent one
+    fn from_failure(reason: Option<String>) -> Self {
+        let reason = reason.unwrap_or_else(|| "no answer".to_string());
+        match reason.split_once(':').map(|(prefix, _)| prefix) {
+            Some("duplicate") => Self::Accepted,
+            Some("blocked" | "invalid" | "pow" | "restricted") => Self::Rejected(reason),
+            _ => Self::Failed(reason),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+}
+
+impl fmt::Display for RelayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "accepted"),
+            Self::Rejected(reason) => write!(f, "rejected: {}", reason),
+            Self::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// The answer of every relay an event was sent to
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishResult {
+    pub event_id: EventId,
+    pub relays: BTreeMap<Url, RelayOutcome>,
+}
+
+impl PublishResult {
+    /// From the relays of the pool output that took the event and those that didn't
+    pub fn new(
+        event_id: EventId,
+        success: HashSet<Url>,
+        failed: HashMap<Url, Option<String>>,
+    ) -> Self {
+        let mut relays: BTreeMap<Url, RelayOutcome> = failed
+            .into_iter()
+            .map(|(url, reason)| (url, RelayOutcome::from_failure(reason)))
+            .collect();
+        for url in success {
+            relays.insert(url, RelayOutcome::Accepted);
+        }
+        Self { event_id, relays }
+    }
+
+    /// Nothing got out, e.g. none of `relays` was connected
+    pub fn failed(event_id: EventId, relays: &[Url], reason: &str) -> Self {
+        Self {
+            event_id,
+            relays: relays
+                .iter()
+                .map(|url| (url.clone(), RelayOutcome::Failed(reason.to_string())))
+                .collect(),
+        }
+    }
+
+    pub fn accepted(&self) -> usize {
+        self.relays
+            .values()
+            .filter(|outcome| **outcome == RelayOutcome::Accepted)
+            .count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.relays.len()
+    }
+
+    /// Relays worth sending the event to again
+    pub fn retry_relays(&self) -> Vec<Url> {
+        self.relays
+            .iter()
+            .filter(|(_, outcome)| outcome.is_retryable())
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// Takes in the answers to a later send, a relay that accepted once stays so
+    pub fn merge(&mut self, other: PublishResult) {
+        for (url, outcome) in other.relays {
+            if self.relays.get(&url) != Some(&RelayOutcome::Accepted) {
+                self.relays.insert(url, outcome);
+            }
+        }
+    }
+}
+
+impl fmt::Display for PublishResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sent to {}/{} relays", self.accepted(), self.total())
+    }
+}
+
+/// Sends the signed `event` to the relay set. The relays that didn't take
+/// it are queued to get it later, so a failed send only shows in the result.
+pub async fn send_and_queue(relay_set: &HashedClient, event: &Event) -> PublishResult {
+    let result = relay_set.send_event(event.clone()).await;
+    if result.retry_relays().is_empty() {
+        return result;
+    }
+    match relay_set.pending_db().await {
+        Ok(db) => {
+            if let Err(e) = queue_failed(&db, event, &result, 0).await {
+                tracing::warn!("queueing {} failed: {:?}", event.id, e);
+            }
+        }
+        Err(e) => tracing::warn!("queueing {} failed: {:?}", event.id, e),
+    }
+    result
+}
+
+/// Signs `builder` and sends it with [`send_and_queue`]. Only signing can
+/// fail, the event is kept for the relays that were offline.
+pub async fn sign_and_send(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    builder: EventBuilder,
+) -> Result<(Event, PublishResult)> {
+    let event = signer.sign_event_builder(builder).await?;
+    let result = send_and_queue(relay_set, &event).await;
+    Ok((event, result))
 }
 
 pub async fn publish_text_note(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     content: &str,
     tags: Vec<Tag>,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::text_note(content, tags).custom_created_at(Timestamp::now());
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
+}
+
+/// Publishes a note or reply to the relay set `hc`, tags the users
+/// mentioned in `content` and delivers it to the read relays of everyone
+/// tagged (NIP-65 outbox model). The result covers the relay set, the
+/// signed event comes along for a later retry or rebroadcast.
+pub async fn publish_text_note_routed(
+    hc: &HashedClient,
+    outbox: &Outbox,
+    signer: &NostrSigner,
+    content: &str,
+    mut tags: Vec<Tag>,
+) -> Result<(Event, PublishResult)> {
+    for pk in mentioned_public_keys(content) {
+        let tag = Tag::public_key(pk);
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    let builder = EventBuilder::text_note(content, tags).custom_created_at(Timestamp::now());
+    let (event, result) = sign_and_send(hc, signer, builder).await?;
+    // the relay set already has it, missing recipients only cost reach
+    if let Err(e) = outbox.deliver(hc, &event).await {
+        tracing::warn!("outbox delivery failed: {:?}", e);
+    }
+    Ok((event, result))
 }
 
 pub async fn repost(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     event: &Event,
     url: Option<UncheckedUrl>,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::repost(event, url);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn reaction(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     event: &Event,
     reaction: &str,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::reaction(event, reaction);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn new_channel(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     metadata: &Metadata,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::channel(metadata);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn set_channel_metadata(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     channel_id: EventId,
     metadata: &Metadata,
     url: Option<Url>,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::channel_metadata(channel_id, url, metadata);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn send_channel_msg(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     channel_id: EventId,
     msg: &str,
     relay_url: Url,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::channel_msg(channel_id, relay_url, msg);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn file_metadata(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     metadata: FileMetadata,
     description: &str,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::file_metadata(description, metadata);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
+/// Sends a NIP-17 direct message to the relay set, returns how the gift
+/// wrap addressed to `receiver` went
 pub async fn send_private_msg(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     receiver: PublicKey,
     message: &str,
     reply_to: Option<EventId>,
-) -> Result<EventId> {
-    let builder = EventBuilder::private_msg_rumor(receiver, message, reply_to);
-    sign_and_send_event!(client, signer, builder)
+) -> Result<PublishResult> {
+    let public_key = signer.public_key().await?;
+    let rumor =
+        EventBuilder::private_msg_rumor(receiver, message, reply_to).to_unsigned_event(public_key);
+    let wrapped = gift_wrap(signer, &receiver, &rumor).await?;
+    let result = send_and_queue(relay_set, &wrapped).await;
+    // keep a copy wrapped for ourselves so the conversation can be restored
+    if receiver != public_key {
+        let own = gift_wrap(signer, &public_key, &rumor).await?;
+        send_and_queue(relay_set, &own).await;
+    }
+    Ok(result)
 }
 
 pub async fn delete_event(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     event_ids: Vec<EventId>,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::delete(event_ids);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
 pub async fn set_relay_list(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
     relays: Vec<(Url, Option<RelayMetadata>)>,
-) -> Result<EventId> {
+) -> Result<PublishResult> {
     let builder = EventBuilder::relay_list(relays);
-    sign_and_send_event!(client, signer, builder)
+    let (_, result) = sign_and_send(relay_set, signer, builder).await?;
+    Ok(result)
 }
 
+/// Replaces the contacts of the newest contact list, keeping its other
+/// tags and content. Dropping contacts needs `confirmed`.
 pub async fn set_contact_list(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
+    db: &CBWebDatabase,
     contacts: Vec<Contact>,
-) -> Result<EventId> {
-    let builder = EventBuilder::contact_list(contacts);
-    sign_and_send_event!(client, signer, builder)
+    timeout: Option<Duration>,
+    confirmed: bool,
+) -> Result<PublishResult> {
+    let public_key = signer.public_key().await?;
+    let base = newest_contact_list(relay_set, db, &public_key, timeout).await?;
+    let tags = replace_contacts(base.as_ref(), contacts);
+    let (_, result) =
+        publish_contact_list(relay_set, signer, db, base.as_ref(), tags, 0, confirmed).await?;
+    Ok(result)
 }
 
 pub async fn unfollow(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
+    db: &CBWebDatabase,
     followee: PublicKey,
     timeout: Option<Duration>,
-) -> Result<EventId> {
-    let contacts = get_contact_list(client, signer, timeout).await?;
-    let contacts: Vec<Contact> = contacts
-        .into_iter()
-        .filter(|contact| contact.public_key != followee)
-        .collect();
-    let builder = EventBuilder::contact_list(contacts);
-    sign_and_send_event!(client, signer, builder)
+    confirmed: bool,
+) -> Result<PublishResult> {
+    let public_key = signer.public_key().await?;
+    let base = newest_contact_list(relay_set, db, &public_key, timeout).await?;
+    let tags = without_contact(base.as_ref(), &followee);
+    let (_, result) =
+        publish_contact_list(relay_set, signer, db, base.as_ref(), tags, 1, confirmed).await?;
+    Ok(result)
 }
 
+/// Adds `followee`, with the relay hint and petname it comes with, to the
+/// newest contact list. An existing entry keeps its own.
 pub async fn follow(
-    client: &Client,
+    relay_set: &HashedClient,
     signer: &NostrSigner,
-    followee: PublicKey,
+    db: &CBWebDatabase,
+    followee: Contact,
     timeout: Option<Duration>,
-    relay_url: Option<UncheckedUrl>,
-    alias: Option<String>,
-) -> Result<EventId> {
-    let contacts = get_contact_list(client, signer, timeout).await?;
-    let contacts: Vec<Contact> = contacts
-        .into_iter()
-        .filter(|contact| contact.public_key != followee)
-        .collect();
-    let contact = Contact::new(followee, relay_url, alias);
-    let mut contacts = contacts;
-    contacts.push(contact);
-    let builder = EventBuilder::contact_list(contacts);
-    sign_and_send_event!(client, signer, builder)
-}
-
-async fn get_contact_list_filters(signer: &NostrSigner) -> Result<Vec<Filter>> {
+    confirmed: bool,
+) -> Result<PublishResult> {
     let public_key = signer.public_key().await?;
-    let filter: Filter = Filter::new()
-        .author(public_key)
-        .kind(Kind::ContactList)
-        .limit(1);
-    Ok(vec![filter])
-}
-
-async fn get_contact_list(
-    client: &Client,
-    signer: &NostrSigner,
-    timeout: Option<Duration>,
-) -> Result<Vec<Contact>> {
-    let mut contact_list: Vec<Contact> = Vec::new();
-    let filters = get_contact_list_filters(signer).await?;
-    let events: Vec<Event> = client.get_events_of(filters, timeout).await?;
-
-    for event in events.into_iter() {
-        for tag in event.into_iter_tags() {
-            if let Some(TagStandard::PublicKey {
-                public_key,
-                relay_url,
-                alias,
-                uppercase: false,
-            }) = tag.to_standardized()
-            {
-                contact_list.push(Contact::new(public_key, relay_url, alias))
-            }
-        }
-    }
-
-    Ok(contact_list)
+    let base = newest_contact_list(relay_set, db, &public_key, timeout).await?;
+    let tags = with_contact(base.as_ref(), followee);
+    let (_, result) =
+        publish_contact_list(relay_set, signer, db, base.as_ref(), tags, 0, confirmed).await?;
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -209,12 +378,48 @@ mod tests {
     use std::str::FromStr;
 
     use nostr_sdk::bitcoin::hashes::sha256::Hash as Sha256Hash;
-    use nostr_sdk::{EventId, Filter, FromBech32, Keys, SecretKey, ToBech32};
+    use nostr_sdk::{Client, EventId, Filter, FromBech32, Keys, SecretKey, ToBech32};
     use wasm_bindgen_test::*;
 
     use super::*;
+    use crate::store::CAPYBASTR_DBNAME;
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
+    #[wasm_bindgen_test]
+    fn test_publish_result() {
+        let event_id = EventId::all_zeros();
+        let damus = Url::parse("wss://relay.damus.io").unwrap();
+        let nos = Url::parse("wss://nos.lol").unwrap();
+        let wine = Url::parse("wss://nostr.wine").unwrap();
+        let oxtr = Url::parse("wss://nostr.oxtr.dev").unwrap();
+        let mut result = PublishResult::new(
+            event_id,
+            HashSet::from([damus.clone()]),
+            HashMap::from([
+                (
+                    nos.clone(),
+                    Some("duplicate: already have this event".to_string()),
+                ),
+                (
+                    wine.clone(),
+                    Some("restricted: not a paid member".to_string()),
+                ),
+                (oxtr.clone(), None),
+            ]),
+        );
+        assert_eq!(result.to_string(), "sent to 2/4 relays");
+        assert_eq!(result.retry_relays(), vec![oxtr.clone()]);
+        assert!(matches!(result.relays[&wine], RelayOutcome::Rejected(_)));
+
+        result.merge(PublishResult::new(
+            event_id,
+            HashSet::from([oxtr.clone()]),
+            HashMap::from([(damus.clone(), None)]),
+        ));
+        assert_eq!(result.accepted(), 3);
+        assert!(result.retry_relays().is_empty());
+    }
+
     #[wasm_bindgen_test]
     async fn test_publish_text_note() {
         let private_key = SecretKey::from_bech32(
@@ -226,6 +431,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = publish_text_note(&client, signer, "Hello, world!", vec![]).await;
         assert!(result.is_ok());
     }
@@ -247,6 +453,7 @@ mod tests {
         client.connect().await;
         let event = client.get_events_of(vec![f], None).await.unwrap();
         //let url = UncheckedUrl::from("wss://relay.damus.io");
+        let client = HashedClient::new(client).await;
         let result = repost(&client, signer, &event[0], None).await;
         assert!(result.is_ok());
     }
@@ -267,9 +474,10 @@ mod tests {
         let f = Filter::new().event(eid);
         client.connect().await;
         let event = client.get_events_of(vec![f], None).await.unwrap();
+        let client = HashedClient::new(client).await;
         let result = reaction(&client, signer, &event[0], "👍").await;
-        if let Ok(event_id) = &result {
-            console_log!("Event ID: {:?}", event_id.to_bech32());
+        if let Ok(result) = &result {
+            console_log!("Event ID: {:?}", result.event_id.to_bech32());
         } else if let Err(e) = &result {
             console_log!("Error: {:?}", e);
         }
@@ -288,6 +496,7 @@ mod tests {
         client.add_relay("wss://relay.damus.io").await.unwrap();
         let metadata = Metadata::new();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = new_channel(&client, signer, &metadata).await;
         assert!(result.is_ok());
     }
@@ -307,6 +516,7 @@ mod tests {
             EventId::from_bech32("note1zlsz37aggmsc2nfzqjdsdw77qwyfqm3erxag5f75nz8tndkvs0uqllhywm")
                 .unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = set_channel_metadata(&client, signer, channel_id, &metadata, None).await;
         assert!(result.is_ok());
     }
@@ -326,6 +536,7 @@ mod tests {
                 .unwrap();
         let url = Url::parse("wss://relay.damus.io").unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = send_channel_msg(&client, signer, channel_id, "Hello, world!", url).await;
         assert!(result.is_ok());
     }
@@ -346,6 +557,7 @@ mod tests {
         client.add_relay("wss://relay.damus.io").await.unwrap();
         let metadata = FileMetadata::new(url, "image/jpeg", hash);
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = file_metadata(&client, signer, metadata, "Hello, world!").await;
         assert!(result.is_ok());
     }
@@ -365,6 +577,7 @@ mod tests {
         )
         .unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = send_private_msg(&client, signer, receiver, "Hello, world!", None).await;
         assert!(result.is_ok());
     }
@@ -383,6 +596,7 @@ mod tests {
             EventId::from_bech32("note1zlsz37aggmsc2nfzqjdsdw77qwyfqm3erxag5f75nz8tndkvs0uqllhywm")
                 .unwrap();
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = delete_event(&client, signer, vec![event_id]).await;
         assert!(result.is_ok());
     }
@@ -408,6 +622,7 @@ mod tests {
             ),
         ];
         client.connect().await;
+        let client = HashedClient::new(client).await;
         let result = set_relay_list(&client, signer, relays).await;
         assert!(result.is_ok());
     }
@@ -431,7 +646,9 @@ mod tests {
             None::<&str>,
         )];
         client.connect().await;
-        let result = set_contact_list(&client, signer, contacts).await;
+        let client = HashedClient::new(client).await;
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let result = set_contact_list(&client, signer, &db, contacts, None, false).await;
         assert!(result.is_ok());
     }
 
@@ -451,7 +668,10 @@ mod tests {
         )
         .unwrap();
         client.connect().await;
-        let result = follow(&client, signer, followee, None, None, None).await;
+        let client = HashedClient::new(client).await;
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let followee = Contact::new(followee, None, None::<&str>);
+        let result = follow(&client, signer, &db, followee, None, false).await;
         assert!(result.is_ok());
     }
 
@@ -471,7 +691,9 @@ mod tests {
         )
         .unwrap();
         client.connect().await;
-        let result = unfollow(&client, signer, followee, None).await;
+        let client = HashedClient::new(client).await;
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let result = unfollow(&client, signer, &db, followee, None, false).await;
         assert!(result.is_ok());
     }
 }
@@ -1,11 +1,24 @@
-use dashmap::mapref::entry::Entry;
-use dashmap::DashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use dashmap::{DashMap, DashSet};
+use gloo_timers::future::TimeoutFuture;
 use nostr_sdk::{
-    Client, Filter, RelayMessage, RelayPoolNotification, SubscribeAutoCloseOptions, SubscriptionId,
+    Client, Event, EventId, Filter, RelayMessage, RelayPoolNotification, RelayStatus,
+    SubscribeAutoCloseOptions, SubscriptionId, Timestamp, Url,
 };
-use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::Stream;
+use wasm_bindgen_futures::spawn_local;
+
+use super::multiclient::{EventCache, HashedClient, RelaySetDiff};
 
 #[derive(Error, Debug)]
 pub enum RegisterError {
@@ -13,24 +26,258 @@ pub enum RegisterError {
     SubscriptionNotFound,
     #[error(transparent)]
     Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
 }
 
 type Result<T> = std::result::Result<T, RegisterError>;
 
-pub type NotificationHandler = Arc<
-    dyn Fn(
-            RelayPoolNotification,
-        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<bool>> + Send>>
-        + Send
-        + Sync,
->;
+/// What happened to a registered subscription
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubEvent {
+    Event {
+        relay_url: Url,
+        event: Box<Event>,
+    },
+    /// Events of a batched subscription, see [`Register::set_batch_options`]
+    Events {
+        events: Vec<Event>,
+        /// Left out since the last batch because the buffer was full
+        dropped: usize,
+    },
+    /// The relay sent what it had stored, what follows is new
+    Eose {
+        relay_url: Url,
+    },
+    /// The relay ended the subscription, see [`super::is_auth_required`]
+    Closed {
+        relay_url: Url,
+        reason: String,
+    },
+    /// A NOTICE of a relay the subscription runs on
+    Notice {
+        relay_url: Url,
+        message: String,
+    },
+    /// Sent again to a relay that reconnected, from `since` on
+    Resubscribed {
+        relay_url: Url,
+        since: Option<Timestamp>,
+    },
+}
+
+/// Where a subscription stands on one relay
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubState {
+    /// Sent, stored events still coming
+    Pending,
+    /// Past EOSE
+    Live,
+    Closed(String),
+}
+
+/// What a full batch buffer does with one more event
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// The new event is dropped
+    Drop,
+    /// The oldest buffered event makes room, the handler gets the newest
+    #[default]
+    Coalesce,
+}
+
+/// How a batched subscription delivers its events
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchOptions {
+    /// A batch goes out as soon as this many events wait
+    pub max_events: usize,
+    /// Or at the latest this long after the last one
+    pub max_wait: Duration,
+    /// Events kept while the handler is still busy with the last batch
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            max_events: 50,
+            max_wait: Duration::from_secs(1),
+            capacity: 1000,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+// ids remembered to drop the copies other relays send
+const SEEN_CAPACITY: usize = 10_000;
+
+/// The events of a batched subscription waiting to go out
+struct Batch {
+    options: BatchOptions,
+    events: VecDeque<Event>,
+    seen: HashSet<EventId>,
+    seen_order: VecDeque<EventId>,
+    dropped: usize,
+}
+
+impl Batch {
+    fn new(options: BatchOptions) -> Self {
+        Self {
+            options,
+            events: VecDeque::new(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Buffers `event` unless another relay sent it already, returns
+    /// whether a batch is due
+    fn push(&mut self, event: Event) -> bool {
+        if !self.seen.insert(event.id) {
+            return false;
+        }
+        self.seen_order.push_back(event.id);
+        if self.seen_order.len() > SEEN_CAPACITY {
+            if let Some(id) = self.seen_order.pop_front() {
+                self.seen.remove(&id);
+            }
+        }
+        if self.events.len() >= self.options.capacity {
+            self.dropped += 1;
+            match self.options.overflow {
+                OverflowPolicy::Drop => return true,
+                OverflowPolicy::Coalesce => {
+                    self.events.pop_front();
+                }
+            }
+        }
+        self.events.push_back(event);
+        self.events.len() >= self.options.max_events
+    }
+
+    fn take(&mut self) -> Option<SubEvent> {
+        if self.events.is_empty() {
+            return None;
+        }
+        Some(SubEvent::Events {
+            events: self.events.drain(..).collect(),
+            dropped: std::mem::take(&mut self.dropped),
+        })
+    }
+}
+
+/// The events of a registered subscription, dropping it ends the
+/// subscription on the relays
+pub struct SubStream {
+    receiver: UnboundedReceiverStream<SubEvent>,
+    // batches sent and not taken yet, new events wait in the buffer meanwhile
+    queued: Arc<AtomicUsize>,
+}
+
+impl Stream for SubStream {
+    type Item = SubEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SubEvent>> {
+        let polled = Pin::new(&mut self.receiver).poll_next(cx);
+        if let Poll::Ready(Some(SubEvent::Events { .. })) = &polled {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        polled
+    }
+}
+
+struct Subscription {
+    client: Client,
+    // the set it follows along edits, with the filters to send its new relays
+    relay_set: Option<(HashedClient, Vec<Filter>)>,
+    // auto-closing subscriptions are not sent again
+    keep_alive: bool,
+    states: HashMap<Url, SubState>,
+    last_seen: Option<Timestamp>,
+    stop: bool,
+    batch: Option<Batch>,
+    queued: Arc<AtomicUsize>,
+    sender: mpsc::UnboundedSender<SubEvent>,
+}
+
+impl Subscription {
+    fn new(client: &Client, relays: Vec<Url>, keep_alive: bool) -> (Self, SubStream) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let subscription = Self {
+            client: client.clone(),
+            relay_set: None,
+            keep_alive,
+            states: relays
+                .into_iter()
+                .map(|url| (url, SubState::Pending))
+                .collect(),
+            last_seen: None,
+            stop: false,
+            batch: None,
+            queued: queued.clone(),
+            sender,
+        };
+        let stream = SubStream {
+            receiver: UnboundedReceiverStream::new(receiver),
+            queued,
+        };
+        (subscription, stream)
+    }
+
+    /// What to send for `event`: itself, a batch now due or nothing yet
+    fn on_event(&mut self, relay_url: Url, event: Box<Event>) -> Option<SubEvent> {
+        let Some(batch) = self.batch.as_mut() else {
+            return Some(SubEvent::Event { relay_url, event });
+        };
+        if batch.push(*event) {
+            self.take_batch()
+        } else {
+            None
+        }
+    }
+
+    /// The buffered events, unless the handler has yet to take the last batch
+    fn take_batch(&mut self) -> Option<SubEvent> {
+        if self.queued.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        self.batch.as_mut()?.take()
+    }
+
+    fn send(&self, sub_event: SubEvent) -> bool {
+        let batch = matches!(sub_event, SubEvent::Events { .. });
+        if batch {
+            self.queued.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.sender.send(sub_event).is_err() {
+            if batch {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+            }
+            return false;
+        }
+        true
+    }
+}
 
-type HandlerStatus = Arc<RwLock<bool>>;
-type HandlerMap = DashMap<SubscriptionId, (NotificationHandler, HandlerStatus)>;
+type SubscriptionMap = DashMap<SubscriptionId, Subscription>;
 
+/// The live subscriptions of the app and where each one stands on every
+/// relay. The messages of a relay reach the [`SubStream`] of their
+/// subscription; a relay coming back gets the subscriptions it was
+/// serving again, from the last event seen on.
+///
+/// One loop follows the notifications of a pool, see
+/// [`Register::handle_notifications`].
 #[derive(Clone)]
 pub struct Register {
-    handlers: Arc<HandlerMap>,
+    subscriptions: Arc<SubscriptionMap>,
+    // kept up to date with the events coming in
+    cache: Option<EventCache>,
+    // the pools a notification loop runs for, by client address
+    listening: Arc<DashSet<usize>>,
 }
 
 impl Default for Register {
@@ -42,23 +289,36 @@ impl Default for Register {
 impl Register {
     pub fn new() -> Self {
         Self {
-            handlers: Arc::new(DashMap::new()),
+            subscriptions: Arc::new(DashMap::new()),
+            cache: None,
+            listening: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// A register merging the events it receives into `cache`
+    pub fn with_cache(cache: EventCache) -> Self {
+        Self {
+            cache: Some(cache),
+            ..Self::new()
         }
     }
 
     pub async fn set_stop_flag(&self, sub_id: &SubscriptionId, value: bool) {
-        if let Entry::Occupied(mut entry) = self.handlers.entry(sub_id.clone()) {
-            let (_, stop_flag) = entry.get_mut();
-            let mut stop_flag = stop_flag.write().await;
-            *stop_flag = value;
+        if let Some(mut subscription) = self.subscriptions.get_mut(sub_id) {
+            subscription.stop = value;
         }
     }
     pub async fn get_sub_flag(&self, sub_id: &SubscriptionId) -> bool {
-        if let Some(entry) = self.handlers.get(sub_id) {
-            let (_, stop_flag) = entry.value();
-            return *stop_flag.read().await;
-        }
-        false
+        self.subscriptions
+            .get(sub_id)
+            .map_or(false, |subscription| subscription.stop)
+    }
+
+    /// Where `sub_id` stands on each relay it was sent to
+    pub fn states(&self, sub_id: &SubscriptionId) -> Option<HashMap<Url, SubState>> {
+        self.subscriptions
+            .get(sub_id)
+            .map(|subscription| subscription.states.clone())
     }
 
     pub async fn add_subscription(
@@ -66,52 +326,313 @@ impl Register {
         client: &Client,
         sub_id: SubscriptionId,
         filters: Vec<Filter>,
-        handler: NotificationHandler,
         opts: Option<SubscribeAutoCloseOptions>,
-    ) -> Result<()> {
-        client
+    ) -> Result<SubStream> {
+        let keep_alive = opts.is_none();
+        let relays: Vec<Url> = client.relays().await.into_keys().collect();
+        let (subscription, stream) = Subscription::new(client, relays, keep_alive);
+        self.subscriptions.insert(sub_id.clone(), subscription);
+        client.subscribe_with_id(sub_id, filters, opts).await;
+        Ok(stream)
+    }
+
+    /// Like [`Self::add_subscription`], restricted to the relays of `relay_set`
+    pub async fn add_relay_set_subscription(
+        &self,
+        relay_set: &HashedClient,
+        sub_id: SubscriptionId,
+        filters: Vec<Filter>,
+        opts: Option<SubscribeAutoCloseOptions>,
+    ) -> Result<SubStream> {
+        // an auto-closing subscription is over before the set changes
+        let keep_alive = opts.is_none();
+        let (mut subscription, stream) =
+            Subscription::new(&relay_set.client(), relay_set.relays(), keep_alive);
+        if keep_alive {
+            subscription.relay_set = Some((relay_set.clone(), filters.clone()));
+        }
+        self.subscriptions.insert(sub_id.clone(), subscription);
+        if let Err(e) = relay_set
             .subscribe_with_id(sub_id.clone(), filters, opts)
-            .await;
-        self.handlers
-            .insert(sub_id.clone(), (handler, Arc::new(RwLock::new(false))));
+            .await
+        {
+            self.subscriptions.remove(&sub_id);
+            return Err(e.into());
+        }
+        Ok(stream)
+    }
+
+    /// Follows an edit of `relay_set` with its live subscriptions: they are
+    /// sent to the added relays and closed on the removed ones still open
+    /// for other sets. Returns how many subscriptions moved.
+    pub async fn apply_relay_set_diff(
+        &self,
+        relay_set: &HashedClient,
+        diff: &RelaySetDiff,
+    ) -> Result<usize> {
+        let subs: Vec<(SubscriptionId, Vec<Filter>)> = self
+            .subscriptions
+            .iter_mut()
+            .filter_map(|mut entry| {
+                let filters = match &entry.relay_set {
+                    Some((hc, filters)) if hc.same_set(relay_set) => filters.clone(),
+                    _ => return None,
+                };
+                for url in diff.added.iter() {
+                    entry.states.insert(url.clone(), SubState::Pending);
+                }
+                for url in diff.removed.iter() {
+                    entry.states.remove(url);
+                }
+                Some((entry.key().clone(), filters))
+            })
+            .collect();
+        let client = relay_set.client();
+        for (sub_id, filters) in subs.iter() {
+            if !diff.added.is_empty() {
+                client
+                    .subscribe_with_id_to(diff.added.clone(), sub_id.clone(), filters.clone(), None)
+                    .await?;
+            }
+            for url in diff.removed.iter() {
+                // gone from the pool when no other set uses it
+                if let Ok(relay) = client.relay(url.clone()).await {
+                    if let Err(e) = relay.unsubscribe(sub_id.clone()).await {
+                        tracing::warn!("closing {} on {} failed: {:?}", sub_id, url, e);
+                    }
+                }
+            }
+        }
+        Ok(subs.len())
+    }
+
+    /// Follows a subscription already sent through the pool of `relay_set`,
+    /// e.g. to the outbox relays of its authors
+    pub fn attach(&self, relay_set: &HashedClient, sub_id: SubscriptionId) -> SubStream {
+        let (subscription, stream) = Subscription::new(&relay_set.client(), vec![], true);
+        self.subscriptions.insert(sub_id, subscription);
+        stream
+    }
+
+    /// Delivers the events of `sub_id` in [`SubEvent::Events`] batches
+    /// instead of one by one, without the copies sent by other relays
+    pub fn set_batch_options(&self, sub_id: &SubscriptionId, options: BatchOptions) -> Result<()> {
+        let queued = {
+            let mut subscription = self
+                .subscriptions
+                .get_mut(sub_id)
+                .ok_or(RegisterError::SubscriptionNotFound)?;
+            subscription.batch = Some(Batch::new(options));
+            subscription.queued.clone()
+        };
+        let register = self.clone();
+        let sub_id = sub_id.clone();
+        let wait_ms = options.max_wait.as_millis().min(u32::MAX as u128) as u32;
+        spawn_local(async move {
+            loop {
+                TimeoutFuture::new(wait_ms).await;
+                // gone, or replaced by another subscription with the same id
+                let current = register
+                    .subscriptions
+                    .get(&sub_id)
+                    .map_or(false, |subscription| {
+                        Arc::ptr_eq(&subscription.queued, &queued)
+                    });
+                if !current {
+                    break;
+                }
+                register.dispatch(&sub_id, Subscription::take_batch).await;
+            }
+        });
         Ok(())
     }
 
+    /// Forgets `sub_id` and sends CLOSE for it to the relays
     pub async fn remove_subscription(&self, sub_id: &SubscriptionId) {
-        self.handlers.remove(sub_id);
+        if let Some((_, subscription)) = self.subscriptions.remove(sub_id) {
+            subscription.client.unsubscribe(sub_id.clone()).await;
+        }
     }
 
-    async fn handle_notification(&self, notification: RelayPoolNotification) -> Result<bool> {
-        if let RelayPoolNotification::Message {
-            message: RelayMessage::Event {
-                subscription_id, ..
-            },
-            ..
-        } = &notification
-        {
-            if let Some(entry) = self.handlers.get(subscription_id) {
-                let (handler, stop_flag) = entry.value();
-                let result = (handler)(notification.clone()).await?;
-                let stop_flag_val = *stop_flag.read().await;
-                return Ok(result || stop_flag_val);
+    /// Runs `update` on the subscription `sub_id` and hands what it
+    /// returns to the stream. Returns whether the subscription is stopped;
+    /// a stopped one or one with a dropped stream ends on the relays.
+    async fn dispatch(
+        &self,
+        sub_id: &SubscriptionId,
+        update: impl FnOnce(&mut Subscription) -> Option<SubEvent>,
+    ) -> bool {
+        let stop = match self.subscriptions.get_mut(sub_id) {
+            Some(mut subscription) => {
+                let dropped = match update(&mut subscription) {
+                    Some(sub_event) => !subscription.send(sub_event),
+                    None => subscription.sender.is_closed(),
+                };
+                subscription.stop || dropped
+            }
+            None => return false,
+        };
+        if stop {
+            // unless it was registered again in the meantime
+            if let Some((_, subscription)) =
+                self.subscriptions.remove_if(sub_id, |_, subscription| {
+                    subscription.stop || subscription.sender.is_closed()
+                })
+            {
+                subscription.client.unsubscribe(sub_id.clone()).await;
             }
         }
-        Ok(false)
+        stop
     }
 
-    pub async fn handle_notifications(&self, client: &Client) -> Result<()> {
+    /// Sends the subscriptions `relay_url` was serving again after a
+    /// reconnect, asking only for what came after the last event seen
+    async fn resubscribe(&self, relay_url: &Url) {
+        let subs: Vec<(SubscriptionId, Client, Option<Timestamp>)> = self
+            .subscriptions
+            .iter_mut()
+            .filter(|entry| entry.keep_alive && entry.states.contains_key(relay_url))
+            .map(|mut entry| {
+                entry.states.insert(relay_url.clone(), SubState::Pending);
+                (entry.key().clone(), entry.client.clone(), entry.last_seen)
+            })
+            .collect();
+        for (sub_id, client, since) in subs {
+            let Ok(relay) = client.relay(relay_url.clone()).await else {
+                continue;
+            };
+            let Some(filters) = relay.subscription(&sub_id).await else {
+                continue;
+            };
+            let filters = match since {
+                Some(since) => filters.into_iter().map(|f| f.since(since)).collect(),
+                None => filters,
+            };
+            if let Err(e) = client
+                .subscribe_with_id_to([relay_url.clone()], sub_id.clone(), filters, None)
+                .await
+            {
+                tracing::warn!("resubscribing {} on {} failed: {:?}", sub_id, relay_url, e);
+                continue;
+            }
+            let sub_event = SubEvent::Resubscribed {
+                relay_url: relay_url.clone(),
+                since,
+            };
+            self.dispatch(&sub_id, |_| Some(sub_event)).await;
+        }
+    }
+
+    /// Updates the state of the subscription a relay message is about and
+    /// passes it on. Returns whether that subscription is stopped.
+    async fn handle_notification(&self, notification: RelayPoolNotification) -> Result<bool> {
+        let (relay_url, message) = match notification {
+            RelayPoolNotification::Message { relay_url, message } => (relay_url, message),
+            RelayPoolNotification::RelayStatus {
+                relay_url,
+                status: RelayStatus::Connected,
+            } => {
+                self.resubscribe(&relay_url).await;
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        };
+        let stop = match message {
+            RelayMessage::Event {
+                subscription_id,
+                event,
+            } => {
+                if let Some(cache) = &self.cache {
+                    cache.on_event(&relay_url, &event).await;
+                }
+                let created_at = event.created_at();
+                self.dispatch(&subscription_id, |subscription| {
+                    if subscription
+                        .last_seen
+                        .map_or(true, |seen| created_at > seen)
+                    {
+                        subscription.last_seen = Some(created_at);
+                    }
+                    subscription
+                        .states
+                        .entry(relay_url.clone())
+                        .or_insert(SubState::Pending);
+                    subscription.on_event(relay_url, event)
+                })
+                .await
+            }
+            RelayMessage::EndOfStoredEvents(subscription_id) => {
+                let sub_event = SubEvent::Eose {
+                    relay_url: relay_url.clone(),
+                };
+                self.dispatch(&subscription_id, |subscription| {
+                    subscription.states.insert(relay_url, SubState::Live);
+                    Some(sub_event)
+                })
+                .await
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } => {
+                let sub_event = SubEvent::Closed {
+                    relay_url: relay_url.clone(),
+                    reason: message.clone(),
+                };
+                self.dispatch(&subscription_id, |subscription| {
+                    subscription
+                        .states
+                        .insert(relay_url, SubState::Closed(message));
+                    Some(sub_event)
+                })
+                .await
+            }
+            RelayMessage::Notice { message } => {
+                let sub_ids: Vec<SubscriptionId> = self
+                    .subscriptions
+                    .iter()
+                    .filter(|entry| entry.states.contains_key(&relay_url))
+                    .map(|entry| entry.key().clone())
+                    .collect();
+                for sub_id in sub_ids {
+                    let sub_event = SubEvent::Notice {
+                        relay_url: relay_url.clone(),
+                        message: message.clone(),
+                    };
+                    self.dispatch(&sub_id, |_| Some(sub_event)).await;
+                }
+                false
+            }
+            _ => false,
+        };
+        Ok(stop)
+    }
+
+    /// Handles the relay messages of the pool `relay_set` is a view of
+    /// until the pool shuts down. Returns right away if a loop already runs
+    /// for that pool, so every relay message is handled once.
+    pub async fn handle_notifications(&self, relay_set: &HashedClient) -> Result<()> {
+        let client = relay_set.client();
+        let pool = Arc::as_ptr(&client) as usize;
+        if !self.listening.insert(pool) {
+            return Ok(());
+        }
         tracing::info!("Register::handle_notifications");
-        client
-            .handle_notifications(|notification| {
-                let register = self.clone();
-                async move {
-                    register
-                        .handle_notification(notification)
-                        .await
-                        .map_err(Into::into)
+        let mut notifications = client.notifications();
+        loop {
+            match notifications.recv().await {
+                Ok(notification) => {
+                    if let Err(e) = self.handle_notification(notification).await {
+                        tracing::error!("handling a notification failed: {:?}", e);
+                    }
                 }
-            })
-            .await?;
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("register missed {} notifications", missed)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+        self.listening.remove(&pool);
         Ok(())
     }
 }
@@ -119,9 +640,10 @@ impl Register {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::testhelper::{sleep, test_hander::create_console_log_handler};
+    use crate::testhelper::{sleep, test_hander::log_sub_events};
+    use futures::StreamExt;
     use nostr_sdk::FromBech32;
-    use nostr_sdk::{EventId, Filter, PublicKey, SubscriptionId};
+    use nostr_sdk::{EventBuilder, EventId, Filter, Keys, PublicKey, SubscriptionId};
     use std::rc::Rc;
     use std::sync::Mutex;
     use wasm_bindgen_futures::spawn_local;
@@ -144,8 +666,6 @@ mod tests {
         let client2 = Client::default();
         client2.add_relay("wss://nos.lol").await.unwrap();
         client2.connect().await;
-        let console_log_handler: NotificationHandler = create_console_log_handler();
-
         let filter1 = Filter::new().id(event_id1).limit(1);
         let filter2: Filter = Filter::new().id(event_id2).limit(1);
 
@@ -153,20 +673,17 @@ mod tests {
 
         let r = register.clone();
         // Add the subscription for test1
-        r.add_subscription(
-            &client1,
-            SubscriptionId::new("test1"),
-            vec![filter1],
-            console_log_handler.clone(),
-            None,
-        )
-        .await
-        .unwrap();
+        let stream = r
+            .add_subscription(&client1, SubscriptionId::new("test1"), vec![filter1], None)
+            .await
+            .unwrap();
+        spawn_local(log_sub_events(stream));
 
         // Handle notifications for test1
         let r = register.clone();
+        let relay_set = HashedClient::new(client1).await;
         spawn_local(async move {
-            r.handle_notifications(&client1).await.unwrap();
+            r.handle_notifications(&relay_set).await.unwrap();
         });
 
         let r = register.clone();
@@ -179,20 +696,17 @@ mod tests {
         let r = register.clone();
         // Add the subscription for test2 after stopping test1
         sleep(3000).await.unwrap();
-        r.add_subscription(
-            &client2,
-            SubscriptionId::new("test2"),
-            vec![filter2],
-            console_log_handler.clone(),
-            None,
-        )
-        .await
-        .unwrap();
+        let stream = r
+            .add_subscription(&client2, SubscriptionId::new("test2"), vec![filter2], None)
+            .await
+            .unwrap();
+        spawn_local(log_sub_events(stream));
 
         // Handle notifications for test2
         let r = register.clone();
+        let relay_set = HashedClient::new(client2).await;
         spawn_local(async move {
-            r.handle_notifications(&client2).await.unwrap();
+            r.handle_notifications(&relay_set).await.unwrap();
         });
 
         sleep(5000).await.unwrap();
@@ -211,22 +725,20 @@ mod tests {
         let register = Register::default();
         let filter = Filter::new().id(event_id).limit(1);
 
-        let console_log_handler: NotificationHandler = create_console_log_handler();
-
-        register
+        let stream = register
             .add_subscription(
                 &client,
                 SubscriptionId::new("test_seen_on_relays"),
                 vec![filter],
-                console_log_handler,
                 None,
             )
             .await
             .unwrap();
+        spawn_local(log_sub_events(stream));
 
-        let cc = Rc::clone(&client);
+        let relay_set = HashedClient::new((*client).clone()).await;
         spawn_local(async move {
-            register.handle_notifications(&cc).await.unwrap();
+            register.handle_notifications(&relay_set).await.unwrap();
         });
 
         sleep(2000).await.unwrap();
@@ -266,35 +778,34 @@ mod tests {
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
         let register = Register::default();
-        let console_log_handler: NotificationHandler = create_console_log_handler();
-        register
+        let stream = register
             .add_subscription(
                 &client.clone(),
                 SubscriptionId::generate(),
                 vec![brian_search],
-                console_log_handler.clone(),
                 None,
             )
             .await
             .unwrap();
+        spawn_local(log_sub_events(stream));
 
-        register
+        let stream = register
             .add_subscription(
                 &client.clone(),
                 SubscriptionId::generate(),
                 vec![filter2],
-                console_log_handler.clone(),
                 None,
             )
             .await
             .unwrap();
+        spawn_local(log_sub_events(stream));
 
         // Uncomment the following line to see the logs
         // register.handle_notifications(&client).await.unwrap();
     }
 
     #[wasm_bindgen_test(async)]
-    async fn test_handler_as_closure() {
+    async fn test_sub_stream() {
         let brian_search = Filter::new().author(
             PublicKey::from_bech32(
                 "npub1tmnfxwvvyx56kt8m904r78umhehwhpgpcpfakelh505r5ve2d2cqa0jccl",
@@ -311,31 +822,130 @@ mod tests {
         let counter = Arc::new(Mutex::new(0));
 
         let register = Register::default();
-        register
+        let mut stream = register
             .add_subscription(
                 &client.clone(),
                 SubscriptionId::generate(),
                 vec![brian_search],
-                Arc::new({
-                    let counter = Arc::clone(&counter);
-                    move |notification| {
-                        let counter = Arc::clone(&counter);
-                        Box::pin(async move {
-                            // console_log!("Received notification: {:?}", notification);
-                            let mut counter_lock = counter.lock().unwrap();
-                            *counter_lock += 1;
-                            console_log!("Counter: {}", *counter_lock);
-                            Ok(false)
-                        })
-                    }
-                }),
                 None,
             )
             .await
             .unwrap();
+        spawn_local({
+            let counter = Arc::clone(&counter);
+            async move {
+                while let Some(SubEvent::Event { .. }) = stream.next().await {
+                    let mut counter_lock = counter.lock().unwrap();
+                    *counter_lock += 1;
+                    console_log!("Counter: {}", *counter_lock);
+                }
+            }
+        });
 
         // Uncomment the following line to see the logs
-        register.handle_notifications(&client).await.unwrap();
+        let relay_set = HashedClient::new(client).await;
+        register.handle_notifications(&relay_set).await.unwrap();
+    }
+
+    #[wasm_bindgen_test(async)]
+    async fn test_lifecycle() {
+        let register = Register::new();
+        let client = HashedClient::new(Client::default()).await;
+        let sub_id = SubscriptionId::new("test_lifecycle");
+        let url = Url::parse("wss://nos.lol").unwrap();
+        let message = |message| RelayPoolNotification::Message {
+            relay_url: url.clone(),
+            message,
+        };
+        let mut stream = register.attach(&client, sub_id.clone());
+
+        let eose = message(RelayMessage::EndOfStoredEvents(sub_id.clone()));
+        assert!(!register.handle_notification(eose.clone()).await.unwrap());
+        assert_eq!(register.states(&sub_id).unwrap()[&url], SubState::Live);
+        assert_eq!(
+            stream.next().await,
+            Some(SubEvent::Eose {
+                relay_url: url.clone()
+            })
+        );
+
+        let closed = message(RelayMessage::Closed {
+            subscription_id: sub_id.clone(),
+            message: "error: shutting down".to_string(),
+        });
+        register.handle_notification(closed).await.unwrap();
+        assert_eq!(
+            register.states(&sub_id).unwrap()[&url],
+            SubState::Closed("error: shutting down".to_string())
+        );
+        assert!(matches!(stream.next().await, Some(SubEvent::Closed { .. })));
+
+        // a dropped stream ends the subscription
+        drop(stream);
+        assert!(register.handle_notification(eose).await.unwrap());
+        assert!(register.states(&sub_id).is_none());
+    }
+
+    #[wasm_bindgen_test(async)]
+    async fn test_one_loop_per_pool() {
+        let register = Register::new();
+        let relay_set = HashedClient::new(Client::default()).await;
+        spawn_local({
+            let register = register.clone();
+            let relay_set = relay_set.clone();
+            async move {
+                register.handle_notifications(&relay_set).await.unwrap();
+            }
+        });
+        sleep(100).await.unwrap();
+        // the pool is followed already, a second loop doesn't start
+        register.handle_notifications(&relay_set).await.unwrap();
+        assert_eq!(register.listening.len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_batch() {
+        let keys = Keys::generate();
+        let note = |content: &str| {
+            EventBuilder::text_note(content, [])
+                .to_event(&keys)
+                .unwrap()
+        };
+        let options = BatchOptions {
+            max_events: 2,
+            capacity: 3,
+            ..Default::default()
+        };
+        let mut batch = Batch::new(options);
+        let first = note("first");
+        assert!(!batch.push(first.clone()));
+        // the same event from another relay
+        assert!(!batch.push(first.clone()));
+        assert!(batch.push(note("second")));
+        assert!(batch.push(note("third")));
+        // full, the oldest makes room
+        let fourth = note("fourth");
+        assert!(batch.push(fourth.clone()));
+        let Some(SubEvent::Events { events, dropped }) = batch.take() else {
+            panic!("no batch");
+        };
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], fourth);
+        assert_eq!(dropped, 1);
+        assert!(batch.take().is_none());
+
+        let mut batch = Batch::new(BatchOptions {
+            overflow: OverflowPolicy::Drop,
+            ..options
+        });
+        for content in ["a", "b", "c", "d"] {
+            batch.push(note(content));
+        }
+        let Some(SubEvent::Events { events, dropped }) = batch.take() else {
+            panic!("no batch");
+        };
+        assert_eq!(events[0].content, "a");
+        assert_eq!((events.len(), dropped), (3, 1));
     }
 
 }
@@ -0,0 +1,198 @@
+use std::fmt;
+
+use nostr_sdk::nips::nip11::RelayInformationDocument;
+use nostr_sdk::{Filter, Timestamp, Url};
+use thiserror::Error;
+
+use crate::store::{CBWebDatabase, CBwebDatabaseError};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Database(#[from] CBwebDatabaseError),
+    #[error("Invalid relay url: {0}")]
+    InvalidUrl(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+// relay documents rarely change, refetch them once a day
+const RELAY_INFO_LIFESPAN: u64 = 24 * 60 * 60;
+const NIP_SEARCH: u16 = 50;
+
+/// The http(s) url serving the NIP-11 document of a ws(s) relay url
+fn info_url(relay_url: &str) -> Result<Url> {
+    let mut url = Url::parse(relay_url).map_err(|_| Error::InvalidUrl(relay_url.to_string()))?;
+    let scheme = match url.scheme() {
+        "wss" => "https",
+        "ws" => "http",
+        _ => return Err(Error::InvalidUrl(relay_url.to_string())),
+    };
+    url.set_scheme(scheme)
+        .map_err(|_| Error::InvalidUrl(relay_url.to_string()))?;
+    Ok(url)
+}
+
+/// Fetches the NIP-11 information document of `relay_url`
+pub async fn fetch_relay_info(relay_url: &str) -> Result<RelayInformationDocument> {
+    let document = reqwest::Client::new()
+        .get(info_url(relay_url)?)
+        .header("Accept", "application/nostr+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(document)
+}
+
+/// The information document of `relay_url`, from the cache while it is
+/// fresh. A stale copy is still returned if the relay can't be reached.
+pub async fn get_relay_info(
+    db: &CBWebDatabase,
+    relay_url: &str,
+) -> Result<RelayInformationDocument> {
+    let now = Timestamp::now().as_u64();
+    let cached = db.get_relay_info(relay_url).await?;
+    if let Some((document, fetched_at)) = &cached {
+        if now.saturating_sub(*fetched_at) < RELAY_INFO_LIFESPAN {
+            return Ok(document.clone());
+        }
+    }
+    match fetch_relay_info(relay_url).await {
+        Ok(document) => {
+            db.save_relay_info(relay_url, &document, now).await?;
+            Ok(document)
+        }
+        Err(e) => match cached {
+            Some((document, _)) => {
+                tracing::warn!("relay info of {} is stale: {:?}", relay_url, e);
+                Ok(document)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Something a subscription asks for that the relay says it doesn't do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unsupported {
+    /// NIP-50 search without NIP-50 in `supported_nips`
+    Search,
+    /// A limit above the relay's `max_limit`, results will be cut
+    Limit { requested: usize, max: usize },
+    /// More filters than `max_filters`
+    Filters { requested: usize, max: usize },
+    /// Reads need NIP-42 authentication
+    AuthRequired,
+    /// Reads need a paid account
+    PaymentRequired,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Search => write!(f, "does not support search (NIP-50)"),
+            Self::Limit { requested, max } => {
+                write!(f, "returns at most {} events, {} requested", max, requested)
+            }
+            Self::Filters { requested, max } => {
+                write!(f, "accepts at most {} filters, {} used", max, requested)
+            }
+            Self::AuthRequired => write!(f, "requires authentication (NIP-42)"),
+            Self::PaymentRequired => write!(f, "requires payment"),
+        }
+    }
+}
+
+/// What of `filters` `document` says the relay won't serve
+pub fn check_filters(document: &RelayInformationDocument, filters: &[Filter]) -> Vec<Unsupported> {
+    let mut unsupported = Vec::new();
+    let supports = |nip: u16| {
+        document
+            .supported_nips
+            .as_ref()
+            .map_or(false, |nips| nips.contains(&nip))
+    };
+    if filters.iter().any(|f| f.search.is_some()) && !supports(NIP_SEARCH) {
+        unsupported.push(Unsupported::Search);
+    }
+    let Some(limitation) = &document.limitation else {
+        return unsupported;
+    };
+    if let Some(max) = limitation.max_limit.map(|max| max as usize) {
+        let requested = filters.iter().filter_map(|f| f.limit).max().unwrap_or(0);
+        if requested > max {
+            unsupported.push(Unsupported::Limit { requested, max });
+        }
+    }
+    if let Some(max) = limitation.max_filters.map(|max| max as usize) {
+        if filters.len() > max {
+            unsupported.push(Unsupported::Filters {
+                requested: filters.len(),
+                max,
+            });
+        }
+    }
+    if limitation.auth_required == Some(true) {
+        unsupported.push(Unsupported::AuthRequired);
+    }
+    if limitation.payment_required == Some(true) {
+        unsupported.push(Unsupported::PaymentRequired);
+    }
+    unsupported
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::nips::nip11::Limitation;
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_info_url() {
+        assert_eq!(
+            info_url("wss://nos.lol").unwrap().as_str(),
+            "https://nos.lol/"
+        );
+        assert_eq!(
+            info_url("ws://localhost:7777").unwrap().as_str(),
+            "http://localhost:7777/"
+        );
+        assert!(info_url("https://nos.lol").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_check_filters() {
+        let document = RelayInformationDocument {
+            supported_nips: Some(vec![1, 11]),
+            limitation: Some(Limitation {
+                max_limit: Some(100),
+                auth_required: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let filters = vec![Filter::new().search("nostr").limit(500)];
+        assert_eq!(
+            check_filters(&document, &filters),
+            vec![
+                Unsupported::Search,
+                Unsupported::Limit {
+                    requested: 500,
+                    max: 100
+                },
+                Unsupported::AuthRequired,
+            ]
+        );
+        assert_eq!(
+            check_filters(&document, &[Filter::new().limit(20)]),
+            vec![Unsupported::AuthRequired]
+        );
+    }
+}
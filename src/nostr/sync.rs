@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use dashmap::DashSet;
+use nostr_indexeddb::database::Order;
+use nostr_sdk::{
+    Event, EventId, Filter, JsonUtil, Kind, NegentropyOptions, PublicKey, RelayStatus, Timestamp,
+    Url,
+};
+use thiserror::Error;
+
+use super::multiclient::{HashedClient, MultiClient};
+use super::relay_info::fetch_relay_info;
+use crate::store::subscription::CustomSub;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Database(#[from] nostr_indexeddb::database::DatabaseError),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error("{0} is not connected")]
+    NotConnected(Url),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+const NIP_NEGENTROPY: u16 = 77;
+// what relays without NIP-77 answer a NEG-OPEN with
+const UNSUPPORTED_REPLIES: [&str; 4] = [
+    "not supported",
+    "unsupported",
+    "unknown cmd",
+    "negentropy disabled",
+];
+
+// relays known not to do NIP-77, not asked again for the session
+fn no_negentropy() -> &'static DashSet<Url> {
+    static NO_NEGENTROPY: OnceLock<DashSet<Url>> = OnceLock::new();
+    NO_NEGENTROPY.get_or_init(DashSet::new)
+}
+
+/// How a relay was brought up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMethod {
+    /// NIP-77 set reconciliation, only the missing events travel
+    Negentropy,
+    /// Everything newer than the newest event stored, for relays without NIP-77
+    Since,
+}
+
+/// What syncing a filter with one relay did
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelaySync {
+    pub url: Url,
+    pub method: SyncMethod,
+    /// Events we didn't have
+    pub received: usize,
+    /// Stored events the relay didn't send again
+    pub events_saved: usize,
+    pub bytes_saved: usize,
+}
+
+/// What a sync saved compared to downloading its filters again
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    pub relays: Vec<RelaySync>,
+}
+
+impl SyncReport {
+    pub fn received(&self) -> usize {
+        self.relays.iter().map(|relay| relay.received).sum()
+    }
+
+    pub fn events_saved(&self) -> usize {
+        self.relays.iter().map(|relay| relay.events_saved).sum()
+    }
+
+    pub fn bytes_saved(&self) -> usize {
+        self.relays.iter().map(|relay| relay.bytes_saved).sum()
+    }
+
+    pub fn merge(&mut self, other: SyncReport) {
+        self.relays.extend(other.relays);
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} new events, {} events ({} KiB) not downloaded again",
+            self.received(),
+            self.events_saved(),
+            self.bytes_saved() / 1024
+        )
+    }
+}
+
+/// How many of `events` there are and their size on the wire
+fn savings<'a>(events: impl Iterator<Item = &'a Event>) -> (usize, usize) {
+    events.fold((0, 0), |(count, bytes), event| {
+        (count + 1, bytes + event.as_json().len())
+    })
+}
+
+/// Where a since-based catch-up starts: the newest event stored
+fn since_cursor(stored: &[Event]) -> Option<Timestamp> {
+    stored.iter().map(|event| event.created_at()).max()
+}
+
+/// Whether a reconciliation that failed with `reason` means `url` doesn't
+/// do NIP-77, rather than a timeout or a dropped connection
+async fn lacks_negentropy(url: &Url, reason: &str) -> bool {
+    let reason = reason.to_lowercase();
+    if UNSUPPORTED_REPLIES
+        .iter()
+        .any(|reply| reason.contains(reply))
+    {
+        return true;
+    }
+    match fetch_relay_info(url.as_str()).await {
+        Ok(document) => !document
+            .supported_nips
+            .map_or(false, |nips| nips.contains(&NIP_NEGENTROPY)),
+        Err(_) => false,
+    }
+}
+
+/// Syncs `filter` with `url`, a relay of `relay_set`, by reconciliation
+/// if the relay supports it
+async fn sync_relay(
+    relay_set: &HashedClient,
+    url: &Url,
+    filter: &Filter,
+    stored: &[Event],
+) -> Result<RelaySync> {
+    let connected = match relay_set.client().relay(url).await {
+        Ok(relay) => relay.status().await == RelayStatus::Connected,
+        Err(_) => false,
+    };
+    if !connected {
+        return Err(Error::NotConnected(url.clone()));
+    }
+
+    let opts = NegentropyOptions::new().initial_timeout(SYNC_TIMEOUT);
+    if !no_negentropy().contains(url) {
+        match relay_set.reconcile(url, filter.clone(), opts).await {
+            Ok((local, received)) => {
+                // what only we have is not downloaded either way
+                let (events_saved, bytes_saved) =
+                    savings(stored.iter().filter(|event| !local.contains(&event.id)));
+                return Ok(RelaySync {
+                    url: url.clone(),
+                    method: SyncMethod::Negentropy,
+                    received,
+                    events_saved,
+                    bytes_saved,
+                });
+            }
+            Err(e) => {
+                // a timeout or a drop says nothing about NIP-77, try again next time
+                let unsupported = match &e {
+                    super::multiclient::Error::Reconcile(_, reason) => {
+                        lacks_negentropy(url, reason).await
+                    }
+                    _ => false,
+                };
+                tracing::debug!("{} can't reconcile: {:?}", url, e);
+                if unsupported {
+                    no_negentropy().insert(url.clone());
+                }
+            }
+        }
+    }
+
+    let since = since_cursor(stored);
+    let mut catch_up = filter.clone();
+    if let Some(since) = since {
+        catch_up = catch_up.since(since);
+    }
+    let events = relay_set
+        .get_events_from(vec![url.clone()], vec![catch_up], Some(SYNC_TIMEOUT))
+        .await?;
+    let known: HashSet<EventId> = stored.iter().map(|event| event.id).collect();
+    let mut received = 0;
+    for event in events.iter().filter(|event| !known.contains(&event.id)) {
+        relay_set.database().save_event(event).await?;
+        received += 1;
+    }
+    let (events_saved, bytes_saved) = savings(
+        stored
+            .iter()
+            .filter(|event| Some(event.created_at()) < since),
+    );
+    Ok(RelaySync {
+        url: url.clone(),
+        method: SyncMethod::Since,
+        received,
+        events_saved,
+        bytes_saved,
+    })
+}
+
+/// Brings the events of `filter` in the database up to date with each
+/// relay of `relay_set`. A relay failing is left out of the report.
+pub async fn sync_filter(relay_set: &HashedClient, filter: Filter) -> Result<SyncReport> {
+    let stored = relay_set
+        .database()
+        .query(vec![filter.clone()], Order::Desc)
+        .await?;
+    let mut report = SyncReport::default();
+    for url in relay_set.query_relays() {
+        match sync_relay(relay_set, &url, &filter, &stored).await {
+            Ok(relay) => report.relays.push(relay),
+            Err(e) => tracing::warn!("sync with {} failed: {:?}", url, e),
+        }
+    }
+    Ok(report)
+}
+
+/// What is kept in sync for an account, the contact list first: its own
+/// events, then the direct messages sent to it
+pub fn account_filters(public_key: PublicKey) -> Vec<Filter> {
+    vec![
+        Filter::new().author(public_key).kind(Kind::ContactList),
+        Filter::new()
+            .kind(Kind::EncryptedDirectMessage)
+            .pubkey(public_key),
+        Filter::new().kind(Kind::GiftWrap).pubkey(public_key),
+        Filter::new().author(public_key),
+    ]
+}
+
+pub async fn sync_account(relay_set: &HashedClient, public_key: PublicKey) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for filter in account_filters(public_key) {
+        match sync_filter(relay_set, filter).await {
+            Ok(filter_report) => report.merge(filter_report),
+            Err(e) => tracing::warn!("account sync failed: {:?}", e),
+        }
+    }
+    Ok(report)
+}
+
+/// Syncs the subscriptions kept alive, each with its own relay set
+pub async fn sync_subs(multiclient: &MultiClient, subs: &[CustomSub]) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+    for sub in subs.iter().filter(|sub| sub.keep_alive) {
+        let relay_set = match multiclient.get_or_create(&sub.relay_set).await {
+            Ok(relay_set) => relay_set,
+            Err(e) => {
+                tracing::warn!("sync of {} failed: {:?}", sub.name, e);
+                continue;
+            }
+        };
+        for filter in sub.get_filters() {
+            match sync_filter(&relay_set, filter).await {
+                Ok(filter_report) => report.merge(filter_report),
+                Err(e) => tracing::warn!("sync of {} failed: {:?}", sub.name, e),
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{EventBuilder, Keys};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_lacks_negentropy() {
+        // nothing listens there, the relay document can't be fetched
+        let url = Url::parse("ws://127.0.0.1:1").unwrap();
+        assert!(lacks_negentropy(&url, "negentropy not supported").await);
+        assert!(lacks_negentropy(&url, "ERROR: bad msg: unknown cmd").await);
+        assert!(!lacks_negentropy(&url, "timeout").await);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_since_savings() {
+        let keys = Keys::generate();
+        let stored: Vec<Event> = [100, 200, 300]
+            .into_iter()
+            .map(|at| {
+                EventBuilder::text_note("gm", [])
+                    .custom_created_at(Timestamp::from(at))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        let since = since_cursor(&stored);
+        assert_eq!(since, Some(Timestamp::from(300)));
+
+        // the newest one comes again with the catch-up
+        let (events, bytes) = savings(
+            stored
+                .iter()
+                .filter(|event| Some(event.created_at()) < since),
+        );
+        assert_eq!(events, 2);
+        assert_eq!(bytes, stored[0].as_json().len() + stored[1].as_json().len());
+
+        let report = SyncReport {
+            relays: vec![
+                RelaySync {
+                    url: Url::parse("wss://nos.lol").unwrap(),
+                    method: SyncMethod::Since,
+                    received: 1,
+                    events_saved: events,
+                    bytes_saved: bytes,
+                },
+                RelaySync {
+                    url: Url::parse("wss://nostr.wine").unwrap(),
+                    method: SyncMethod::Negentropy,
+                    received: 0,
+                    events_saved: 3,
+                    bytes_saved: 2048,
+                },
+            ],
+        };
+        assert_eq!(report.received(), 1);
+        assert_eq!(report.events_saved(), 5);
+        assert_eq!(report.bytes_saved(), bytes + 2048);
+    }
+}
@@ -82,6 +82,38 @@ pub fn get_newest_event(events: &[Event]) -> Option<&Event> {
 pub fn get_oldest_event(events: &[Event]) -> Option<&Event> {
     events.iter().min_by_key(|event| event.created_at())
 }
+
+/// Extracts the amount in millisatoshis from the human readable part of a bolt11 invoice.
+///
+/// Returns `None` if the invoice is malformed or doesn't carry an amount.
+pub fn bolt11_amount_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.trim().to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    let hrp = &invoice[..invoice.rfind('1')?];
+    let hrp = hrp.strip_prefix("ln")?;
+
+    // skip the currency prefix (bc, tb, bcrt, ...)
+    let amount = hrp.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        return None;
+    }
+
+    let (digits, multiplier) = match amount.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => (&amount[..amount.len() - 1], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse().ok()?;
+
+    // 1 BTC = 100_000_000_000 msats
+    match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        Some('p') if value % 10 == 0 => Some(value / 10),
+        _ => None,
+    }
+}
 /*
 pub async fn query_events_from_db(
     client: &Client,
@@ -91,3 +123,52 @@ pub async fn query_events_from_db(
     Ok(events)
 }
 */
+
+const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+// tagged field type of the description hash ('h')
+const BOLT11_DESCRIPTION_HASH: u8 = 23;
+// 7 words of timestamp, 104 of signature and 6 of checksum
+const BOLT11_TIMESTAMP_LEN: usize = 7;
+const BOLT11_TRAILER_LEN: usize = 104 + 6;
+
+/// Extracts the description hash (`h` field) from a bolt11 invoice.
+///
+/// The checksum and signature are not verified.
+pub fn bolt11_description_hash(invoice: &str) -> Option<[u8; 32]> {
+    let invoice = invoice.trim().to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    let data = &invoice[invoice.rfind('1')? + 1..];
+    let words = data
+        .chars()
+        .map(|c| BECH32_CHARSET.find(c).map(|i| i as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    if words.len() < BOLT11_TIMESTAMP_LEN + BOLT11_TRAILER_LEN {
+        return None;
+    }
+    let mut fields = &words[BOLT11_TIMESTAMP_LEN..words.len() - BOLT11_TRAILER_LEN];
+
+    while fields.len() >= 3 {
+        let tag = fields[0];
+        let len = ((fields[1] as usize) << 5) | fields[2] as usize;
+        let value = fields.get(3..3 + len)?;
+        // 52 words carry 256 bits and 4 bits of padding
+        if tag == BOLT11_DESCRIPTION_HASH && len == 52 {
+            let mut hash = [0u8; 32];
+            let mut acc: u32 = 0;
+            let mut bits = 0;
+            let mut i = 0;
+            for word in value {
+                acc = (acc << 5) | *word as u32;
+                bits += 5;
+                if bits >= 8 && i < hash.len() {
+                    bits -= 8;
+                    hash[i] = (acc >> bits) as u8;
+                    i += 1;
+                }
+            }
+            return Some(hash);
+        }
+        fields = &fields[3 + len..];
+    }
+    None
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+
+use nostr_sdk::nips::nip47::{
+    ListTransactionsRequestParams, LookupInvoiceResponseResult, MakeInvoiceRequestParams,
+    NostrWalletConnectURI,
+};
+use nostr_sdk::{PublicKey, Url, NWC};
+use thiserror::Error;
+
+use crate::account::{check_passphrase, decrypt_ncryptsec, encrypt_ncryptsec};
+use crate::store::wallet::WalletConnection;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Uri(#[from] nostr_sdk::nips::nip47::Error),
+    #[error(transparent)]
+    Nwc(#[from] nostr_sdk::nwc::Error),
+    #[error(transparent)]
+    Account(#[from] crate::account::Error),
+    #[error("Wrong passphrase")]
+    Locked,
+    #[error("Corrupted wallet connection")]
+    Corrupted,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A Nostr Wallet Connect (NIP-47) client
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    name: String,
+    inner: NWC,
+}
+
+impl Wallet {
+    pub fn new(name: &str, uri: &str) -> Result<Self> {
+        let uri = NostrWalletConnectURI::from_str(uri)?;
+        Ok(Self {
+            name: name.to_string(),
+            inner: NWC::new(uri),
+        })
+    }
+
+    /// The connection of `uri` to store, its secret encrypted under `passphrase`
+    pub fn seal(user: &str, name: &str, uri: &str, passphrase: &str) -> Result<WalletConnection> {
+        check_passphrase(passphrase)?;
+        let uri = NostrWalletConnectURI::from_str(uri)?;
+        Ok(WalletConnection {
+            id: WalletConnection::id(user, name),
+            user: user.to_string(),
+            name: name.to_string(),
+            wallet_public_key: uri.public_key.to_hex(),
+            relay: uri.relay_url.to_string(),
+            lud16: uri.lud16.clone(),
+            ncryptsec: encrypt_ncryptsec(&uri.secret, passphrase)?,
+        })
+    }
+
+    /// Decrypts a stored connection and connects to its relay
+    pub fn unlock(connection: &WalletConnection, passphrase: &str) -> Result<Self> {
+        let secret =
+            decrypt_ncryptsec(&connection.ncryptsec, passphrase).map_err(|_| Error::Locked)?;
+        let public_key =
+            PublicKey::from_hex(&connection.wallet_public_key).map_err(|_| Error::Corrupted)?;
+        let relay = Url::parse(&connection.relay).map_err(|_| Error::Corrupted)?;
+        let uri = NostrWalletConnectURI::new(public_key, relay, secret, connection.lud16.clone());
+        Ok(Self {
+            name: connection.name.clone(),
+            inner: NWC::new(uri),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Pays a bolt11 invoice and returns the preimage
+    pub async fn pay_invoice(&self, invoice: &str) -> Result<String> {
+        Ok(self.inner.pay_invoice(invoice).await?)
+    }
+
+    /// Balance in millisatoshis
+    pub async fn get_balance(&self) -> Result<u64> {
+        Ok(self.inner.get_balance().await?)
+    }
+
+    /// Creates a bolt11 invoice for `amount_msats`
+    pub async fn make_invoice(
+        &self,
+        amount_msats: u64,
+        description: Option<String>,
+    ) -> Result<String> {
+        let params = MakeInvoiceRequestParams {
+            amount: amount_msats,
+            description,
+            description_hash: None,
+            expiry: None,
+        };
+        let result = self.inner.make_invoice(params).await?;
+        Ok(result.invoice)
+    }
+
+    pub async fn list_transactions(
+        &self,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    ) -> Result<Vec<LookupInvoiceResponseResult>> {
+        let params = ListTransactionsRequestParams {
+            from: None,
+            until: None,
+            limit,
+            offset,
+            unpaid: None,
+            transaction_type: None,
+        };
+        Ok(self.inner.list_transactions(params).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const URI: &str = "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c";
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[wasm_bindgen_test]
+    fn test_unlock_wallet() {
+        assert!(matches!(
+            Wallet::seal("test_user", "alby", URI, "1234"),
+            Err(Error::Account(crate::account::Error::WeakPassphrase))
+        ));
+        let connection = Wallet::seal("test_user", "alby", URI, PASSPHRASE).unwrap();
+        assert!(!connection.ncryptsec.is_empty());
+        // the secret is not stored in clear
+        assert!(!serde_json::to_string(&connection)
+            .unwrap()
+            .contains("71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c"));
+
+        let wallet = Wallet::unlock(&connection, PASSPHRASE).unwrap();
+        assert_eq!(wallet.name(), "alby");
+
+        let locked = Wallet::unlock(&connection, "wrong horse battery staple");
+        assert!(matches!(locked, Err(Error::Locked)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_invalid_uri() {
+        assert!(Wallet::new("broken", "https://example.com").is_err());
+    }
+}
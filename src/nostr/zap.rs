@@ -0,0 +1,464 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use gloo_timers::future::TimeoutFuture;
+use nostr_sdk::bitcoin::bech32;
+use nostr_sdk::nips::nip57::ZapRequestData;
+use nostr_sdk::{
+    Event, EventBuilder, EventId, Filter, JsonUtil, Kind, Metadata, NostrSigner, NostrZapper,
+    PublicKey, Timestamp, UncheckedUrl, WebLNZapper,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::Stream;
+use wasm_bindgen_futures::spawn_local;
+
+use super::fetch::{get_metadata, Zap};
+use super::multiclient::HashedClient;
+use super::utils::bolt11_amount_msats;
+use super::wallet::Wallet;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Fetch(#[from] super::fetch::Error),
+    #[error(transparent)]
+    RelaySet(#[from] super::multiclient::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("No lightning address found in metadata")]
+    NoLightningAddress,
+    #[error("Invalid lightning address: {0}")]
+    InvalidLightningAddress(String),
+    #[error("LNURL service error: {0}")]
+    Lnurl(String),
+    #[error("Recipient does not accept nostr zaps")]
+    NostrNotAllowed,
+    #[error("Amount of {0} msats is out of the allowed range")]
+    AmountOutOfRange(u64),
+    #[error("Invoice amount does not match the requested amount")]
+    InvoiceAmountMismatch,
+    #[error("Payment failed: {0}")]
+    Payment(String),
+    #[error("Zap receipt not seen")]
+    ReceiptNotFound,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Progress of a zap, in the order the states are reported
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZapState {
+    ResolvingLnurl,
+    RequestingInvoice,
+    Paying { invoice: String },
+    WaitingReceipt,
+    ReceiptSeen(Zap),
+    Failed(String),
+}
+
+/// LNURL-pay parameters returned by the recipient's lightning service (LUD-06)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayInfo {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    #[serde(default)]
+    pub allows_nostr: bool,
+    pub nostr_pubkey: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZapOptions {
+    pub recipient: PublicKey,
+    pub event_id: Option<EventId>,
+    pub amount_msats: u64,
+    pub comment: String,
+}
+
+impl ZapOptions {
+    pub fn new(recipient: PublicKey, amount_msats: u64) -> Self {
+        Self {
+            recipient,
+            event_id: None,
+            amount_msats,
+            comment: String::new(),
+        }
+    }
+
+    pub fn event(self, event_id: EventId) -> Self {
+        Self {
+            event_id: Some(event_id),
+            ..self
+        }
+    }
+
+    pub fn comment<S: Into<String>>(self, comment: S) -> Self {
+        Self {
+            comment: comment.into(),
+            ..self
+        }
+    }
+}
+
+/// Resolves the LNURL-pay endpoint from a profile, preferring lud16 over lud06.
+///
+/// Returns the endpoint and, for lud06, the bech32 lnurl to put in the zap request.
+pub fn lnurl_pay_url(metadata: &Metadata) -> Result<(String, Option<String>)> {
+    lnurl_pay_url_with_scheme(metadata, "https")
+}
+
+/// [`lnurl_pay_url`] with the scheme a lud16 address is resolved over
+pub(crate) fn lnurl_pay_url_with_scheme(
+    metadata: &Metadata,
+    scheme: &str,
+) -> Result<(String, Option<String>)> {
+    if let Some(lud16) = metadata.lud16.as_ref().filter(|s| !s.is_empty()) {
+        let (name, domain) = lud16
+            .split_once('@')
+            .ok_or_else(|| Error::InvalidLightningAddress(lud16.clone()))?;
+        if name.is_empty() || domain.is_empty() {
+            return Err(Error::InvalidLightningAddress(lud16.clone()));
+        }
+        return Ok((
+            format!("{}://{}/.well-known/lnurlp/{}", scheme, domain, name),
+            None,
+        ));
+    }
+
+    if let Some(lud06) = metadata.lud06.as_ref().filter(|s| !s.is_empty()) {
+        let lnurl = lud06.trim().to_lowercase();
+        let lnurl = lnurl.strip_prefix("lightning:").unwrap_or(&lnurl);
+        let (_, data) =
+            bech32::decode(lnurl).map_err(|_| Error::InvalidLightningAddress(lud06.clone()))?;
+        let url =
+            String::from_utf8(data).map_err(|_| Error::InvalidLightningAddress(lud06.clone()))?;
+        return Ok((url, Some(lnurl.to_string())));
+    }
+
+    Err(Error::NoLightningAddress)
+}
+
+fn receipt_signers() -> &'static DashMap<PublicKey, PublicKey> {
+    static SIGNERS: OnceLock<DashMap<PublicKey, PublicKey>> = OnceLock::new();
+    SIGNERS.get_or_init(DashMap::new)
+}
+
+/// The key the lightning service of `recipient` signs zap receipts with,
+/// the `nostrPubkey` of its LNURL-pay endpoint
+pub async fn zap_receipt_signer(
+    relay_set: &HashedClient,
+    recipient: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<PublicKey> {
+    if let Some(signer) = receipt_signers().get(recipient) {
+        return Ok(*signer);
+    }
+    let metadata = get_metadata(relay_set, recipient, timeout).await?;
+    let (pay_url, _) = lnurl_pay_url(&metadata)?;
+    let info = fetch_pay_info(&pay_url).await?;
+    let signer = match info.nostr_pubkey {
+        Some(signer) if info.allows_nostr => signer,
+        _ => return Err(Error::NostrNotAllowed),
+    };
+    receipt_signers().insert(*recipient, signer);
+    Ok(signer)
+}
+
+async fn get_json(url: &str) -> Result<Value> {
+    let value: Value = reqwest::get(url).await?.json().await?;
+    if value.get("status").and_then(Value::as_str) == Some("ERROR") {
+        let reason = value
+            .get("reason")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        return Err(Error::Lnurl(reason.to_string()));
+    }
+    Ok(value)
+}
+
+pub async fn fetch_pay_info(url: &str) -> Result<LnurlPayInfo> {
+    let value = get_json(url).await?;
+    serde_json::from_value(value).map_err(|e| Error::Lnurl(e.to_string()))
+}
+
+/// Calls the LNURL callback with a signed zap request and returns the bolt11 invoice
+pub async fn request_invoice(
+    info: &LnurlPayInfo,
+    amount_msats: u64,
+    zap_request: &Event,
+    lnurl: Option<&str>,
+) -> Result<String> {
+    if !info.allows_nostr || info.nostr_pubkey.is_none() {
+        return Err(Error::NostrNotAllowed);
+    }
+    if amount_msats < info.min_sendable || amount_msats > info.max_sendable {
+        return Err(Error::AmountOutOfRange(amount_msats));
+    }
+
+    let separator = if info.callback.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let mut url = format!(
+        "{}{}amount={}&nostr={}",
+        info.callback,
+        separator,
+        amount_msats,
+        urlencoding::encode(&zap_request.as_json())
+    );
+    if let Some(lnurl) = lnurl {
+        url.push_str(&format!("&lnurl={}", lnurl));
+    }
+
+    let value = get_json(&url).await?;
+    let invoice = value
+        .get("pr")
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::Lnurl("callback returned no invoice".to_string()))?;
+
+    if bolt11_amount_msats(invoice) != Some(amount_msats) {
+        return Err(Error::InvoiceAmountMismatch);
+    }
+    Ok(invoice.to_string())
+}
+
+/// How zap invoices get paid
+#[derive(Debug, Clone)]
+pub enum PaymentBackend {
+    /// Browser extension (Alby, Mutiny, ...)
+    WebLN,
+    /// Nostr Wallet Connect (NIP-47)
+    Nwc(Wallet),
+}
+
+impl PaymentBackend {
+    pub async fn pay(&self, invoice: &str) -> Result<()> {
+        match self {
+            PaymentBackend::WebLN => {
+                let zapper = WebLNZapper::new()
+                    .await
+                    .map_err(|e| Error::Payment(e.to_string()))?;
+                zapper
+                    .pay(invoice.to_string())
+                    .await
+                    .map_err(|e| Error::Payment(e.to_string()))?;
+            }
+            PaymentBackend::Nwc(wallet) => {
+                wallet
+                    .pay_invoice(invoice)
+                    .await
+                    .map_err(|e| Error::Payment(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Polls the relays until the receipt for `invoice` shows up, or `timeout` has passed
+pub async fn wait_for_receipt(
+    relay_set: &HashedClient,
+    info: &LnurlPayInfo,
+    recipient: PublicKey,
+    invoice: &str,
+    since: Timestamp,
+    timeout: Duration,
+) -> Result<Zap> {
+    let filter = Filter::new()
+        .kind(Kind::ZapReceipt)
+        .pubkey(recipient)
+        .since(since);
+    let deadline = Timestamp::now().as_u64() + timeout.as_secs();
+
+    while Timestamp::now().as_u64() < deadline {
+        let events = relay_set
+            .get_events_of(vec![filter.clone()], Some(Duration::from_secs(3)))
+            .await?;
+        for event in events.iter() {
+            // only the lightning service's key may publish receipts
+            if Some(event.author()) != info.nostr_pubkey {
+                continue;
+            }
+            if let Ok(zap) = Zap::try_from(event) {
+                if event.iter_tags().any(|tag| tag.content() == Some(invoice)) {
+                    return Ok(zap);
+                }
+            }
+        }
+        TimeoutFuture::new(2_000).await;
+    }
+
+    Err(Error::ReceiptNotFound)
+}
+
+async fn zap_with_progress(
+    relay_set: &HashedClient,
+    signer: &NostrSigner,
+    backend: &PaymentBackend,
+    opts: ZapOptions,
+    timeout: Option<Duration>,
+    tx: &mpsc::UnboundedSender<ZapState>,
+) -> Result<Zap> {
+    let _ = tx.send(ZapState::ResolvingLnurl);
+    let metadata = get_metadata(relay_set, &opts.recipient, timeout).await?;
+    let (pay_url, lnurl) = lnurl_pay_url(&metadata)?;
+    let info = fetch_pay_info(&pay_url).await?;
+
+    let _ = tx.send(ZapState::RequestingInvoice);
+    let relays: Vec<UncheckedUrl> = relay_set
+        .relays()
+        .into_iter()
+        .map(UncheckedUrl::from)
+        .collect();
+    let mut data = ZapRequestData::new(opts.recipient, relays)
+        .amount(opts.amount_msats)
+        .message(opts.comment.clone());
+    if let Some(event_id) = opts.event_id {
+        data = data.event_id(event_id);
+    }
+    if let Some(lnurl) = lnurl.clone() {
+        data = data.lnurl(lnurl);
+    }
+    let zap_request = signer
+        .sign_event_builder(EventBuilder::public_zap_request(data))
+        .await?;
+    let invoice = request_invoice(&info, opts.amount_msats, &zap_request, lnurl.as_deref()).await?;
+
+    let _ = tx.send(ZapState::Paying {
+        invoice: invoice.clone(),
+    });
+    let since = Timestamp::now() - 60;
+    backend.pay(&invoice).await?;
+
+    let _ = tx.send(ZapState::WaitingReceipt);
+    wait_for_receipt(
+        relay_set,
+        &info,
+        opts.recipient,
+        &invoice,
+        since,
+        Duration::from_secs(60),
+    )
+    .await
+}
+
+/// Zaps a note or a profile (NIP-57).
+///
+/// The returned stream yields every step of the flow and ends after
+/// [`ZapState::ReceiptSeen`] or [`ZapState::Failed`].
+pub fn send_zap(
+    relay_set: HashedClient,
+    signer: NostrSigner,
+    backend: PaymentBackend,
+    opts: ZapOptions,
+    timeout: Option<Duration>,
+) -> impl Stream<Item = ZapState> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    spawn_local(async move {
+        match zap_with_progress(&relay_set, &signer, &backend, opts, timeout, &tx).await {
+            Ok(zap) => {
+                let _ = tx.send(ZapState::ReceiptSeen(zap));
+            }
+            Err(e) => {
+                tracing::error!("zap failed: {:?}", e);
+                let _ = tx.send(ZapState::Failed(e.to_string()));
+            }
+        }
+    });
+
+    UnboundedReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::{FromBech32, Keys};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // served by `node scripts/mock-lnurl.mjs`
+    const MOCK_LUD16: &str = "alice@127.0.0.1:3001";
+
+    // the mock server speaks plain http
+    fn mock_pay_url() -> String {
+        let metadata = Metadata::new().lud16(MOCK_LUD16);
+        lnurl_pay_url_with_scheme(&metadata, "http").unwrap().0
+    }
+
+    #[wasm_bindgen_test]
+    fn test_lnurl_pay_url() {
+        let metadata = Metadata::new().lud16("satoshi@getalby.com");
+        let (url, lnurl) = lnurl_pay_url(&metadata).unwrap();
+        assert_eq!(url, "https://getalby.com/.well-known/lnurlp/satoshi");
+        assert!(lnurl.is_none());
+
+        assert_eq!(
+            mock_pay_url(),
+            "http://127.0.0.1:3001/.well-known/lnurlp/alice"
+        );
+
+        let metadata = Metadata::new().lud06(
+            "LNURL1DP68GURN8GHJ7ETCV9KHQMR99E3K7MF09EMK2MRV944KUMMHDCHKCMN4WFK8QTMNV96X7UMGDYW3AD68",
+        );
+        let (url, lnurl) = lnurl_pay_url(&metadata).unwrap();
+        assert_eq!(url, "https://example.com/.well-known/lnurlp/satoshi");
+        assert!(lnurl.unwrap().starts_with("lnurl1"));
+
+        assert!(matches!(
+            lnurl_pay_url(&Metadata::new()),
+            Err(Error::NoLightningAddress)
+        ));
+        assert!(matches!(
+            lnurl_pay_url(&Metadata::new().lud16("not-an-address")),
+            Err(Error::InvalidLightningAddress(_))
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_fetch_pay_info() {
+        let info = fetch_pay_info(&mock_pay_url()).await.unwrap();
+        assert!(info.allows_nostr);
+        assert!(info.nostr_pubkey.is_some());
+        assert!(info.min_sendable <= info.max_sendable);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_request_invoice() {
+        let keys = Keys::generate();
+        let signer: NostrSigner = keys.into();
+        let recipient = PublicKey::from_bech32(
+            "npub1zfss807aer0j26mwp2la0ume0jqde3823rmu97ra6sgyyg956e0s6xw445",
+        )
+        .unwrap();
+
+        let info = fetch_pay_info(&mock_pay_url()).await.unwrap();
+
+        let data = ZapRequestData::new(recipient, vec![UncheckedUrl::from("wss://nos.lol")])
+            .amount(21_000);
+        let zap_request = signer
+            .sign_event_builder(EventBuilder::public_zap_request(data))
+            .await
+            .unwrap();
+
+        let invoice = request_invoice(&info, 21_000, &zap_request, None)
+            .await
+            .unwrap();
+        assert_eq!(bolt11_amount_msats(&invoice), Some(21_000));
+
+        let too_much = request_invoice(&info, info.max_sendable + 1000, &zap_request, None).await;
+        assert!(matches!(too_much, Err(Error::AmountOutOfRange(_))));
+    }
+}
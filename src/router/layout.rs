@@ -1,21 +1,25 @@
 use std::collections::HashMap;
 
 use dioxus::prelude::*;
-use nostr_sdk::SubscriptionId;
+use nostr_sdk::{Event, SubscriptionId};
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
 use web_sys::window;
 
-use crate::init::{MODAL_MANAGER, SUB_COUNTERS};
-use crate::nostr::{Register, ReplyTreeManager};
+use crate::account::session::Session;
+use crate::account::MIN_PASSPHRASE_LEN;
+use crate::init::{switch_account, upgrade_account, LAST_LOGINED_KEY, MODAL_MANAGER, NOT_LOGGED_IN_USER_NAME, SUB_COUNTERS};
+use crate::nostr::{
+    publish_text_note_routed, MultiClient, PublishResult, Register, ReplyTreeManager,
+};
 use crate::store::subscription::CustomSub;
-struct UserItem {
-    avatar: &'static str,
-    username: &'static str,
-}
+use crate::store::{AccountType, CBWebDatabase, User, DEFAULT_RELAY_SET_KEY};
+use crate::utils::js::alert;
+
+const DEFAULT_AVATAR: &str = "https://img.alicdn.com/imgextra/i2/O1CN01fI8HqB20dQg3rqybI_!!6000000006872-2-tps-2880-120.png";
 // use crate::views::
 use crate::components::icons::*;
-use crate::components::{Button, Message};
+use crate::components::{Button, Message, PublishStatus};
 use crate::router::*;
 use crate::utils::format::splite_by_replys;
 use crate::init::SUB_SYSTEM_FILERS;
@@ -33,25 +37,103 @@ pub fn Layout() -> Element {
         }
     };
     let messageContent = use_signal(|| String::from(""));
+    let mut show = use_signal(|| false);
+    let mut contentText = use_signal(|| String::from(""));
+    // the last note sent and how its relays answered
+    let mut last_publish = use_signal(|| None::<(Event, PublishResult)>);
     // golbal replytree manager cache
     use_context_provider(|| Signal::new(ReplyTreeManager::new(200)));
-    let users = [UserItem{
-            avatar: "https://img.alicdn.com/imgextra/i2/O1CN01fI8HqB20dQg3rqybI_!!6000000006872-2-tps-2880-120.png",
-            username: "James LisaLisaLisaLisaLisaLisaLisa"
-        },
-        UserItem{
-            avatar: "https://img.alicdn.com/imgextra/i2/O1CN01fI8HqB20dQg3rqybI_!!6000000006872-2-tps-2880-120.png",
-            username: "Tom"
-        },
-        UserItem{
-            avatar: "https://img.alicdn.com/imgextra/i2/O1CN01fI8HqB20dQg3rqybI_!!6000000006872-2-tps-2880-120.png",
-            username: "Lisa"
-        },
-    ];
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let all_users = use_context::<Signal<Vec<User>>>();
+    let mut session = use_context::<Signal<Session>>();
+    // account waiting for its passphrase before the switch
+    let mut unlocking = use_signal(|| None::<User>);
+    let mut passphrase = use_signal(String::new);
+    // legacy PIN accounts pick a real passphrase on their first unlock
+    let mut new_passphrase = use_signal(String::new);
+
+    let mut handle_switch = move |user: User, passphrase: Option<String>| {
+        spawn(async move {
+            match switch_account(session, multiclient, cb_database_db, user, passphrase).await {
+                Ok(_) => {
+                    unlocking.set(None);
+                    show.set(false);
+                }
+                Err(e) => alert(format!("Switch account failed: {}", e)).await,
+            }
+        });
+    };
+
+    // watch-only and logged out sessions stop here with a "no signer" error
+    let handle_upgrade = move |user: User| {
+        spawn(async move {
+            let new = new_passphrase();
+            match upgrade_account(cb_database_db, all_users, user, &passphrase(), &new).await {
+                Ok(upgraded) => {
+                    new_passphrase.set(String::new());
+                    handle_switch(upgraded, Some(new));
+                }
+                Err(e) => alert(format!("Unlock failed: {}", e)).await,
+            }
+        });
+    };
+
+    let handle_send = move |_| {
+        let content = contentText();
+        if content.is_empty() {
+            return;
+        }
+        spawn(async move {
+            let signer = session.read().signer().cloned();
+            let signer = match signer {
+                Ok(signer) => signer,
+                Err(e) => {
+                    alert(e.to_string()).await;
+                    return;
+                }
+            };
+            let clients = multiclient();
+            match clients.get_or_create(DEFAULT_RELAY_SET_KEY).await {
+                Ok(hc) => match publish_text_note_routed(
+                    &hc,
+                    &clients.outbox(),
+                    &signer,
+                    &content,
+                    vec![],
+                )
+                .await
+                {
+                    Ok((event, result)) => {
+                        contentText.set(String::new());
+                        edit.set(false);
+                        last_publish.set(Some((event, result)));
+                    }
+                    Err(e) => alert(format!("Publish failed: {}", e)).await,
+                },
+                Err(e) => tracing::error!("publish client Error: {:?}", e),
+            }
+        });
+    };
+
+    let handle_logout = move |_| {
+        session.set(Session::logged_out());
+        show.set(false);
+        spawn(async move {
+            if let Ok(pool) = multiclient().pool().await {
+                pool.authenticator().change_signer(&pool, None);
+            }
+            let db = cb_database_db.read().clone();
+            if let Err(e) = db
+                .save_misc(LAST_LOGINED_KEY.to_string(), NOT_LOGGED_IN_USER_NAME.to_string())
+                .await
+            {
+                tracing::error!("save last login error: {:?}", e);
+            }
+        });
+    };
     let path: Route = use_route();
-    let mut contentText = use_signal(|| String::from(""));
 
-    let mut show = use_signal(|| false);
     let mut sub_register = use_context::<Signal<Register>>();
     let root_click_pos = use_context::<Signal<(f64, f64)>>();
 
@@ -120,11 +202,15 @@ pub fn Layout() -> Element {
                             class: "user-avatar flex items-center",
                             img{
                                 class: "user-avatar-img",
-                                src: "https://img.alicdn.com/imgextra/i2/O1CN01fI8HqB20dQg3rqybI_!!6000000006872-2-tps-2880-120.png",
+                                src: "{DEFAULT_AVATAR}",
                             }
                             h1{
                                 class: "user-name text-overflow",
-                                "User Test "
+                                if session.read().public_key().is_some() {
+                                    "{session.read().user().name}"
+                                } else {
+                                    "Not logged in"
+                                }
                             }
                         }
                         if !show(){
@@ -144,6 +230,7 @@ pub fn Layout() -> Element {
                                 class: "flex items-center",
                                 button {
                                     class: "user-trigger-item-button log-button",
+                                    onclick: handle_logout,
                                     "Logout"
                                 },
                                 button {
@@ -156,18 +243,31 @@ pub fn Layout() -> Element {
                                 dangerous_inner_html: "{COPY_ALL}",
                             }
                         }
-                        for user in users.iter() {
+                        for user in all_users.read().iter().filter(|u| !matches!(u.inner, AccountType::NotLoggedIn(_))).cloned() {
                             div{
-                                class: "user-trigger-item flex items-center justify-between pl-0",
+                                class: "user-trigger-item flex items-center justify-between pl-0 cursor-pointer",
+                                onclick: {
+                                    let user = user.clone();
+                                    move |_| {
+                                        // local keys are unlocked with their passphrase first
+                                        if user.inner.is_encrypted() {
+                                            passphrase.set(String::new());
+                                            new_passphrase.set(String::new());
+                                            unlocking.set(Some(user.clone()));
+                                        } else {
+                                            handle_switch(user.clone(), None);
+                                        }
+                                    }
+                                },
                                 div {
                                     class: "user-info flex items-center",
                                     img{
                                         class: "user-avatar-img",
-                                        src: "{user.avatar}",
+                                        src: "{DEFAULT_AVATAR}",
                                     },
                                     h1{
                                         class: "user-name text-overflow",
-                                        "{user.username}"
+                                        "{user.name}"
                                     }
                                 }
                                 div {
@@ -176,6 +276,48 @@ pub fn Layout() -> Element {
                                 }
                             }
                         }
+                        if let Some(user) = unlocking() {
+                            if user.inner.is_legacy() {
+                                div{
+                                    class: "user-trigger-item flex items-center justify-between",
+                                    input {
+                                        class: "relay-ipt mr-10",
+                                        r#type: "password",
+                                        placeholder: "PIN of {user.name}",
+                                        value: "{passphrase}",
+                                        oninput: move |event| passphrase.set(event.value()),
+                                    }
+                                    input {
+                                        class: "relay-ipt mr-10",
+                                        r#type: "password",
+                                        placeholder: "New passphrase, {MIN_PASSPHRASE_LEN}+ characters",
+                                        value: "{new_passphrase}",
+                                        oninput: move |event| new_passphrase.set(event.value()),
+                                    }
+                                    button {
+                                        class: "user-trigger-item-button pro-button",
+                                        onclick: move |_| handle_upgrade(user.clone()),
+                                        "Unlock"
+                                    }
+                                }
+                            } else {
+                                div{
+                                    class: "user-trigger-item flex items-center justify-between",
+                                    input {
+                                        class: "relay-ipt mr-10",
+                                        r#type: "password",
+                                        placeholder: "Passphrase for {user.name}",
+                                        value: "{passphrase}",
+                                        oninput: move |event| passphrase.set(event.value()),
+                                    }
+                                    button {
+                                        class: "user-trigger-item-button pro-button",
+                                        onclick: move |_| handle_switch(user.clone(), Some(passphrase())),
+                                        "Unlock"
+                                    }
+                                }
+                            }
+                        }
                     }
                 }
                 div {
@@ -204,6 +346,13 @@ pub fn Layout() -> Element {
                         },
                         "New Note"
                     }
+                    if let Some((event, result)) = last_publish() {
+                        PublishStatus {
+                            event: event.clone(),
+                            result,
+                            on_result: move |result| last_publish.set(Some((event.clone(), result))),
+                        }
+                    }
                     div{
                       class:"show-{edit}",
                       div{
@@ -263,6 +412,7 @@ pub fn Layout() -> Element {
                         }
                         button{
                           class:"note-button send-style",
+                          onclick: handle_send,
                           "Send"
                         }
                         button{
@@ -2,8 +2,8 @@ use dioxus::prelude::*;
 
 use crate::components::icons::*;
 use crate::views::{
-    Bookmark, Channel, Group, Home, Message, NewSubscription, NoteDetail, NoteList, Profile, Relay,
-    Search, Settings, Subscription, Test,
+    Bookmark, Channel, Group, Home, Message, MnemonicAccount, NewSubscription, NoteDetail,
+    NoteList, Profile, Relay, Search, Settings, SignerLogin, Subscription, Test, WalletSettings,
 };
 mod layout;
 mod page_not_found;
@@ -46,6 +46,15 @@ pub enum Route {
     #[route("/settings")]
     Settings {},
 
+    #[route("/settings/wallet")]
+    WalletSettings {},
+
+    #[route("/settings/login")]
+    SignerLogin {},
+
+    #[route("/settings/mnemonic")]
+    MnemonicAccount {},
+
     #[route("/test/:id")]
     Test { id: i32 },
 
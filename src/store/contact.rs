@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// A kind 3 event of an account, kept so a wiped follow list can be restored
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ContactListVersion {
+    /// Hex id of the event
+    pub id: String,
+    /// Hex public key of the author
+    pub author: String,
+    pub created_at: u64,
+    /// The event as JSON
+    pub event: String,
+}
@@ -1,31 +1,45 @@
+pub mod contact;
 pub mod error;
+pub mod pending;
 pub mod subscription;
 pub mod user;
+pub mod wallet;
 
+use std::collections::HashMap;
 use std::future::IntoFuture;
 use std::rc::Rc;
 
+use contact::ContactListVersion;
 pub use error::CBwebDatabaseError;
 use indexed_db_futures::idb_object_store::IdbObjectStoreParameters;
 use indexed_db_futures::request::{IdbOpenDbRequestLike, OpenDbRequest};
 use indexed_db_futures::web_sys::IdbTransactionMode;
 use indexed_db_futures::{IdbDatabase, IdbKeyPath, IdbQuerySource, IdbVersionChangeEvent};
+use nostr_sdk::nips::nip11::RelayInformationDocument;
+use pending::PendingEvent;
 use serde_wasm_bindgen::{from_value, to_value};
 use subscription::{CustomSub, RelaySet};
 pub use user::{AccountType, User};
+use wallet::WalletConnection;
 use wasm_bindgen::JsValue;
 use web_sys::IdbIndexParameters;
 
 pub const CAPYBASTR_DBNAME: &str = "capybastr-db";
 
-const CURRENT_DB_VERSION: u32 = 2;
+const CURRENT_DB_VERSION: u32 = 5;
 const RELAY_SET_CF: &str = "relay-set";
 const CUSTOM_SUB_CF: &str = "custom-sub";
 const USER_CF: &str = "user";
 const MISC_CF: &str = "misc";
+const WALLET_CF: &str = "wallet";
+const PENDING_EVENT_CF: &str = "pending-event";
+const CONTACT_LIST_CF: &str = "contact-list";
 
 // Some entries keys & values
 pub const DEFAULT_RELAY_SET_KEY: &str = "default"; // This record cannot be removed
+const DM_READ_MARKERS_PREFIX: &str = "dm_read_markers:";
+const NOTIFICATIONS_CURSOR_PREFIX: &str = "notifications_cursor:";
+const RELAY_INFO_PREFIX: &str = "relay_info:";
 
 #[derive(Clone)]
 pub struct CBWebDatabase {
@@ -93,6 +107,48 @@ impl CBWebDatabase {
                         let _misc_store = evt.db().create_object_store(MISC_CF)?;
                     }
                 }
+
+                // Apply migration 2->3
+                if old_version <= 2 {
+                    // Init wallet store
+                    let mut create_store_params = IdbObjectStoreParameters::new();
+                    let key_path = IdbKeyPath::str("id");
+                    create_store_params.key_path(Some(&key_path));
+                    let wallet_store = evt
+                        .db()
+                        .create_object_store_with_params(WALLET_CF, &create_store_params)?;
+                    wallet_store.create_index_with_params(
+                        "user",
+                        &IdbKeyPath::str("user"),
+                        IdbIndexParameters::new().unique(false),
+                    )?;
+                }
+
+                // Apply migration 3->4
+                if old_version <= 3 {
+                    // Init pending-event store
+                    let mut create_store_params = IdbObjectStoreParameters::new();
+                    let key_path = IdbKeyPath::str("id");
+                    create_store_params.key_path(Some(&key_path));
+                    evt.db()
+                        .create_object_store_with_params(PENDING_EVENT_CF, &create_store_params)?;
+                }
+
+                // Apply migration 4->5
+                if old_version <= 4 {
+                    // Init contact-list store
+                    let mut create_store_params = IdbObjectStoreParameters::new();
+                    let key_path = IdbKeyPath::str("id");
+                    create_store_params.key_path(Some(&key_path));
+                    let contact_list_store = evt
+                        .db()
+                        .create_object_store_with_params(CONTACT_LIST_CF, &create_store_params)?;
+                    contact_list_store.create_index_with_params(
+                        "author",
+                        &IdbKeyPath::str("author"),
+                        IdbIndexParameters::new().unique(false),
+                    )?;
+                }
                 Ok(())
             },
         ));
@@ -208,9 +264,14 @@ impl CBWebDatabase {
             // Update the name
             relay_set.name.clone_from(&new_relay_set.name);
             relay_set.relays.clone_from(&new_relay_set.relays);
+            relay_set.auth.clone_from(&new_relay_set.auth);
             relay_set_value =
                 to_value(&relay_set).map_err(CBwebDatabaseError::DeserializationError)?;
 
+            // The name is the key, a renamed set must not stay under the old one
+            if old_name != new_relay_set.name {
+                relay_set_store.delete(&relay_set_key)?;
+            }
             // Put the updated entry
             relay_set_store.put_val(&relay_set_value)?;
         } else {
@@ -493,12 +554,249 @@ impl CBWebDatabase {
         tx.await.into_result()?;
         Ok(())
     }
+
+    /// Read markers of `account`, conversation id -> last read timestamp
+    pub async fn get_read_markers(
+        &self,
+        account: &str,
+    ) -> Result<HashMap<String, u64>, CBwebDatabaseError> {
+        match self
+            .get_misc(format!("{}{}", DM_READ_MARKERS_PREFIX, account))
+            .await?
+        {
+            Some(value) => Ok(serde_json::from_str(&value)?),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    pub async fn save_read_markers(
+        &self,
+        account: &str,
+        markers: &HashMap<String, u64>,
+    ) -> Result<(), CBwebDatabaseError> {
+        let value = serde_json::to_string(markers)?;
+        self.save_misc(format!("{}{}", DM_READ_MARKERS_PREFIX, account), value)
+            .await
+    }
+
+    /// Timestamp of the newest notification `account` has seen
+    pub async fn get_notifications_cursor(
+        &self,
+        account: &str,
+    ) -> Result<Option<u64>, CBwebDatabaseError> {
+        Ok(self
+            .get_misc(format!("{}{}", NOTIFICATIONS_CURSOR_PREFIX, account))
+            .await?
+            .and_then(|value| value.parse().ok()))
+    }
+
+    pub async fn save_notifications_cursor(
+        &self,
+        account: &str,
+        cursor: u64,
+    ) -> Result<(), CBwebDatabaseError> {
+        self.save_misc(
+            format!("{}{}", NOTIFICATIONS_CURSOR_PREFIX, account),
+            cursor.to_string(),
+        )
+        .await
+    }
+
+    /// The cached NIP-11 document of `url` and when it was fetched
+    pub async fn get_relay_info(
+        &self,
+        url: &str,
+    ) -> Result<Option<(RelayInformationDocument, u64)>, CBwebDatabaseError> {
+        match self
+            .get_misc(format!("{}{}", RELAY_INFO_PREFIX, url))
+            .await?
+        {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn save_relay_info(
+        &self,
+        url: &str,
+        document: &RelayInformationDocument,
+        fetched_at: u64,
+    ) -> Result<(), CBwebDatabaseError> {
+        let value = serde_json::to_string(&(document, fetched_at))?;
+        self.save_misc(format!("{}{}", RELAY_INFO_PREFIX, url), value)
+            .await
+    }
+
+    pub async fn save_pending_event(
+        &self,
+        pending: PendingEvent,
+    ) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(PENDING_EVENT_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(PENDING_EVENT_CF)?;
+        let value = to_value(&pending).map_err(CBwebDatabaseError::DeserializationError)?;
+        store.put_val(&value)?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    pub async fn remove_pending_event(&self, id: String) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(PENDING_EVENT_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(PENDING_EVENT_CF)?;
+        store.delete(&JsValue::from_str(&id))?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    pub async fn get_pending_events(&self) -> Result<Vec<PendingEvent>, CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(PENDING_EVENT_CF, IdbTransactionMode::Readonly)?;
+
+        let store = tx.object_store(PENDING_EVENT_CF)?;
+        let values = store.get_all()?.await?;
+
+        let mut pending = Vec::new();
+        for v in values.iter() {
+            match from_value::<PendingEvent>(v.clone()) {
+                Ok(p) => pending.push(p),
+                Err(e) => {
+                    tracing::error!("Error deserializing PendingEvent: {:?}", e);
+                    return Err(CBwebDatabaseError::DeserializationError(e));
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    pub async fn save_contact_list(
+        &self,
+        version: ContactListVersion,
+    ) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(CONTACT_LIST_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(CONTACT_LIST_CF)?;
+        let value = to_value(&version).map_err(CBwebDatabaseError::DeserializationError)?;
+        store.put_val(&value)?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    /// Every kept contact list of `author`, newest first
+    pub async fn get_contact_lists(
+        &self,
+        author: &str,
+    ) -> Result<Vec<ContactListVersion>, CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(CONTACT_LIST_CF, IdbTransactionMode::Readonly)?;
+
+        let store = tx.object_store(CONTACT_LIST_CF)?;
+        let index = store.index("author")?;
+        let values = index.get_all_with_key(&JsValue::from_str(author))?.await?;
+
+        let mut versions = Vec::new();
+        for v in values.iter() {
+            match from_value::<ContactListVersion>(v.clone()) {
+                Ok(version) => versions.push(version),
+                Err(e) => {
+                    tracing::error!("Error deserializing ContactListVersion: {:?}", e);
+                    return Err(CBwebDatabaseError::DeserializationError(e));
+                }
+            }
+        }
+        versions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(versions)
+    }
+
+    pub async fn save_wallet(&self, wallet: WalletConnection) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(WALLET_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(WALLET_CF)?;
+        let value = to_value(&wallet).map_err(CBwebDatabaseError::DeserializationError)?;
+        store.put_val(&value)?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    /// Saves a new connection, refusing to replace one of the same name
+    pub async fn add_wallet(&self, wallet: WalletConnection) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(WALLET_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(WALLET_CF)?;
+        if store.get(&JsValue::from_str(&wallet.id))?.await?.is_some() {
+            return Err(CBwebDatabaseError::InvalidOperation(format!(
+                "a wallet named {} already exists",
+                wallet.name
+            )));
+        }
+        let value = to_value(&wallet).map_err(CBwebDatabaseError::DeserializationError)?;
+        store.add_val(&value)?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    pub async fn remove_wallet(&self, id: String) -> Result<(), CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(WALLET_CF, IdbTransactionMode::Readwrite)?;
+
+        let store = tx.object_store(WALLET_CF)?;
+        store.delete(&JsValue::from_str(&id))?;
+
+        tx.await.into_result()?;
+        Ok(())
+    }
+
+    pub async fn get_wallets(
+        &self,
+        user: String,
+    ) -> Result<Vec<WalletConnection>, CBwebDatabaseError> {
+        let tx = self
+            .db
+            .transaction_on_one_with_mode(WALLET_CF, IdbTransactionMode::Readonly)?;
+
+        let store = tx.object_store(WALLET_CF)?;
+        let index = store.index("user")?;
+        let value = index.get_all_with_key(&JsValue::from_str(&user))?.await?;
+
+        let mut wallets = Vec::new();
+
+        for v in value.iter() {
+            match from_value::<WalletConnection>(v.clone()) {
+                Ok(wallet) => wallets.push(wallet),
+                Err(e) => {
+                    tracing::error!("Error deserializing WalletConnection: {:?}", e);
+                    return Err(CBwebDatabaseError::DeserializationError(e));
+                }
+            }
+        }
+
+        Ok(wallets)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use nostr_sdk::key::Keys;
 
+    use subscription::AuthPolicy;
     use user::NoLogin;
     use wasm_bindgen_test::*;
 
@@ -511,6 +809,7 @@ mod tests {
         let relay_set = RelaySet {
             name: "test".to_string(),
             relays: vec!["test".to_string()],
+            auth: HashMap::from([("test".to_string(), AuthPolicy::Always)]),
         };
         db.save_relay_set(relay_set.clone()).await.unwrap();
         let relay_set2 = db.get_relay_set("test".to_string()).await.unwrap();
@@ -520,6 +819,31 @@ mod tests {
         assert!(relay_set3.is_err());
     }
 
+    #[wasm_bindgen_test]
+    async fn test_relay_set_rename() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let relay_set = RelaySet {
+            name: "before".to_string(),
+            relays: vec!["wss://nos.lol".to_string()],
+            auth: HashMap::new(),
+        };
+        db.save_relay_set(relay_set.clone()).await.unwrap();
+        let renamed = RelaySet {
+            name: "after".to_string(),
+            auth: HashMap::from([("wss://nos.lol".to_string(), AuthPolicy::Never)]),
+            ..relay_set
+        };
+        db.relay_set_change("before".to_string(), renamed.clone())
+            .await
+            .unwrap();
+        assert!(db.get_relay_set("before".to_string()).await.is_err());
+        assert_eq!(
+            db.get_relay_set("after".to_string()).await.unwrap(),
+            renamed
+        );
+        db.remove_relay_set("after".to_string()).await.unwrap();
+    }
+
     #[wasm_bindgen_test]
     async fn test_user_db() {
         let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
@@ -539,4 +863,102 @@ mod tests {
         let user3 = db.get_user("test_user".to_string()).await;
         assert!(user3.is_err());
     }
+
+    #[wasm_bindgen_test]
+    async fn test_wallet_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let wallet = crate::nostr::Wallet::seal(
+            "test_user",
+            "alby",
+            "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c",
+            "correct horse battery staple",
+        )
+        .unwrap();
+        db.add_wallet(wallet.clone()).await.unwrap();
+        assert!(matches!(
+            db.add_wallet(wallet.clone()).await,
+            Err(CBwebDatabaseError::InvalidOperation(_))
+        ));
+        let wallets = db.get_wallets("test_user".to_string()).await.unwrap();
+        assert_eq!(wallets, vec![wallet.clone()]);
+        db.remove_wallet(wallet.id).await.unwrap();
+        let wallets = db.get_wallets("test_user".to_string()).await.unwrap();
+        assert!(wallets.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_markers_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let markers = HashMap::from([("a,b".to_string(), 1700000000u64)]);
+        db.save_read_markers("test_user", &markers).await.unwrap();
+        assert_eq!(db.get_read_markers("test_user").await.unwrap(), markers);
+        assert!(db.get_read_markers("nobody").await.unwrap().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_notifications_cursor_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        db.save_notifications_cursor("test_user", 1700000000)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_notifications_cursor("test_user").await.unwrap(),
+            Some(1700000000)
+        );
+        assert_eq!(db.get_notifications_cursor("nobody").await.unwrap(), None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_pending_event_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let pending = PendingEvent {
+            id: "test".to_string(),
+            event: "{}".to_string(),
+            relays: vec!["wss://nos.lol".to_string()],
+            attempts: 1,
+            next_attempt: 1700000000,
+            last_error: Some("no answer".to_string()),
+        };
+        db.save_pending_event(pending.clone()).await.unwrap();
+        assert!(db.get_pending_events().await.unwrap().contains(&pending));
+        db.remove_pending_event("test".to_string()).await.unwrap();
+        assert!(!db.get_pending_events().await.unwrap().contains(&pending));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_contact_list_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let author = Keys::generate().public_key().to_hex();
+        let version = |id: &str, created_at: u64| ContactListVersion {
+            id: id.to_string(),
+            author: author.clone(),
+            created_at,
+            event: "{}".to_string(),
+        };
+        db.save_contact_list(version("old", 1)).await.unwrap();
+        db.save_contact_list(version("new", 2)).await.unwrap();
+        db.save_contact_list(version("new", 2)).await.unwrap();
+        assert_eq!(
+            db.get_contact_lists(&author).await.unwrap(),
+            vec![version("new", 2), version("old", 1)]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_relay_info_db() {
+        let db = CBWebDatabase::open(CAPYBASTR_DBNAME).await.unwrap();
+        let document = RelayInformationDocument {
+            name: Some("test relay".to_string()),
+            supported_nips: Some(vec![1, 11, 50]),
+            ..Default::default()
+        };
+        db.save_relay_info("wss://test.relay", &document, 1700000000)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_relay_info("wss://test.relay").await.unwrap(),
+            Some((document, 1700000000))
+        );
+        assert_eq!(db.get_relay_info("wss://nobody").await.unwrap(), None);
+    }
 }
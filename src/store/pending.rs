@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// A signed event some relays still have to receive, kept across reloads
+/// until they took it or the retries run out
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PendingEvent {
+    /// Hex id of the event
+    pub id: String,
+    /// The event as JSON
+    pub event: String,
+    /// Relays owing an OK
+    pub relays: Vec<String>,
+    pub attempts: u32,
+    /// Unix time of the next retry
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use nostr_sdk::{EventId, Filter, Kind, PublicKey, SingleLetterTag, Timestamp};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
@@ -65,10 +67,48 @@ impl CustomSub {
     }
 }
 
+/// Whether to answer a relay's NIP-42 AUTH challenge
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthPolicy {
+    Always,
+    #[default]
+    Ask,
+    Never,
+}
+
+impl AuthPolicy {
+    pub const ALL: [AuthPolicy; 3] = [AuthPolicy::Always, AuthPolicy::Ask, AuthPolicy::Never];
+}
+
+impl std::fmt::Display for AuthPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthPolicy::Always => write!(f, "always"),
+            AuthPolicy::Ask => write!(f, "ask"),
+            AuthPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for AuthPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(AuthPolicy::Always),
+            "ask" => Ok(AuthPolicy::Ask),
+            "never" => Ok(AuthPolicy::Never),
+            _ => Err(format!("unknown auth policy: {}", s)),
+        }
+    }
+}
+
 /// RelaySet
 ///
 /// name: name of the relay set
 /// relays: list of relays
+/// auth: NIP-42 policy per relay, relays not listed ask
 ///
 /// # Example:
 ///
@@ -79,14 +119,21 @@ impl CustomSub {
 pub struct RelaySet {
     pub name: String,
     pub relays: Vec<String>,
+    #[serde(default)]
+    pub auth: HashMap<String, AuthPolicy>,
 }
 impl RelaySet {
     pub fn new(key: &usize) -> Self {
         Self {
             name: format!("RelaySet-{}", key),
             relays: vec![],
+            auth: HashMap::new(),
         }
     }
+
+    pub fn auth_policy(&self, relay: &str) -> AuthPolicy {
+        self.auth.get(relay).copied().unwrap_or_default()
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
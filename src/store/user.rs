@@ -15,6 +15,9 @@ pub enum AccountType {
     NotLoggedIn(NoLogin),
     Pub(OnlyPubkey),
     SecretKey(PinProtectedPrivkey),
+    Ncryptsec(NcryptsecPrivkey),
+    RemoteSigner(RemoteSignerSession),
+    Extension(ExtensionAccount),
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -59,6 +62,75 @@ impl PinProtectedPrivkey {
     }
 }
 
+/// A secret key encrypted with NIP-49 under a user chosen passphrase
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct NcryptsecPrivkey {
+    pub r#type: String,
+    pub ncryptsec: String,
+}
+
+impl NcryptsecPrivkey {
+    pub fn new(ncryptsec: String) -> Self {
+        Self {
+            r#type: "Ncryptsec".to_string(),
+            ncryptsec,
+        }
+    }
+}
+
+/// A NIP-46 session with a bunker, the user's key never leaves the remote signer
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RemoteSignerSession {
+    pub r#type: String,
+    pub pk: PublicKey,
+    pub bunker_uri: String,
+    /// Secret of the client keypair the bunker authorized, as a NIP-49
+    /// ncryptsec. It signs as the user until revoked from the bunker.
+    pub app_ncryptsec: String,
+}
+
+impl RemoteSignerSession {
+    pub fn new(pk: PublicKey, bunker_uri: String, app_ncryptsec: String) -> Self {
+        Self {
+            r#type: "RemoteSigner".to_string(),
+            pk,
+            bunker_uri,
+            app_ncryptsec,
+        }
+    }
+}
+
+/// Signs through the NIP-07 browser extension, `pk` is the key it reported at login
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExtensionAccount {
+    pub r#type: String,
+    pub pk: PublicKey,
+}
+
+impl ExtensionAccount {
+    pub fn new(pk: PublicKey) -> Self {
+        Self {
+            r#type: "Extension".to_string(),
+            pk,
+        }
+    }
+}
+
+impl AccountType {
+    /// Accounts holding a secret at rest, opened with a passphrase
+    pub fn is_encrypted(&self) -> bool {
+        matches!(
+            self,
+            AccountType::SecretKey(_) | AccountType::Ncryptsec(_) | AccountType::RemoteSigner(_)
+        )
+    }
+
+    /// Records from before passphrases, see `crate::account::upgrade_legacy`
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, AccountType::SecretKey(_))
+    }
+}
+
 impl Serialize for AccountType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -68,6 +140,9 @@ impl Serialize for AccountType {
             AccountType::NotLoggedIn(nl) => nl.serialize(serializer),
             AccountType::Pub(pk) => pk.serialize(serializer),
             AccountType::SecretKey(pin_key) => pin_key.serialize(serializer),
+            AccountType::Ncryptsec(key) => key.serialize(serializer),
+            AccountType::RemoteSigner(session) => session.serialize(serializer),
+            AccountType::Extension(account) => account.serialize(serializer),
         }
     }
 }
@@ -99,6 +174,24 @@ impl<'de> Deserialize<'de> for AccountType {
                     PinProtectedPrivkey::deserialize(value).map_err(serde::de::Error::custom)?;
                 Ok(AccountType::SecretKey(pin_protected_privkey))
             }
+            Some("Ncryptsec") => {
+                // Deserialize the entire value into a NcryptsecPrivkey variant
+                let ncryptsec_privkey =
+                    NcryptsecPrivkey::deserialize(value).map_err(serde::de::Error::custom)?;
+                Ok(AccountType::Ncryptsec(ncryptsec_privkey))
+            }
+            Some("RemoteSigner") => {
+                // Deserialize the entire value into a RemoteSignerSession variant
+                let session =
+                    RemoteSignerSession::deserialize(value).map_err(serde::de::Error::custom)?;
+                Ok(AccountType::RemoteSigner(session))
+            }
+            Some("Extension") => {
+                // Deserialize the entire value into a ExtensionAccount variant
+                let account =
+                    ExtensionAccount::deserialize(value).map_err(serde::de::Error::custom)?;
+                Ok(AccountType::Extension(account))
+            }
             _ => {
                 // Return an error if the "type" field does not match any known variant
                 Err(serde::de::Error::custom("Unknown account type"))
@@ -146,5 +239,37 @@ mod tests {
             secret_key,
             AccountType::SecretKey(PinProtectedPrivkey::new(encrypted_sk))
         );
+
+        let ncryptsec_json = serde_json::json!({
+            "type": "Ncryptsec",
+            "ncryptsec": "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfqtd67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6ksu8r89qk5z2zxfmu5gv8th8wclt0h4p",
+        });
+        let ncryptsec: AccountType = serde_json::from_value(ncryptsec_json).unwrap();
+        assert!(matches!(ncryptsec, AccountType::Ncryptsec(_)));
+
+        let keys = Keys::generate();
+        let bunker_uri = format!(
+            "bunker://{}?relay=wss%3A%2F%2Frelay.nsec.app",
+            keys.public_key().to_hex()
+        );
+        let session = RemoteSignerSession::new(
+            keys.public_key(),
+            bunker_uri,
+            "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfqtd67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6ksu8r89qk5z2zxfmu5gv8th8wclt0h4p".to_string(),
+        );
+        let json = serde_json::to_value(AccountType::RemoteSigner(session.clone())).unwrap();
+        let remote: AccountType = serde_json::from_value(json).unwrap();
+        assert_eq!(remote, AccountType::RemoteSigner(session));
+        assert!(!remote.is_legacy());
+
+        let extension_json = serde_json::json!({
+            "type": "Extension",
+            "pk": keys.public_key(),
+        });
+        let extension: AccountType = serde_json::from_value(extension_json).unwrap();
+        assert_eq!(
+            extension,
+            AccountType::Extension(ExtensionAccount::new(keys.public_key()))
+        );
     }
 }
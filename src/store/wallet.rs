@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// A Nostr Wallet Connect (NIP-47) connection owned by a [`crate::store::User`].
+///
+/// The URI secret, which can spend from the wallet, is kept as a NIP-49
+/// ncryptsec; the wallet key, relay and lightning address are in clear.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct WalletConnection {
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    /// Hex public key of the wallet service
+    pub wallet_public_key: String,
+    pub relay: String,
+    pub lud16: Option<String>,
+    pub ncryptsec: String,
+}
+
+impl WalletConnection {
+    pub fn id(user: &str, name: &str) -> String {
+        format!("{}:{}", user, name)
+    }
+}
@@ -27,34 +27,26 @@ pub mod test_data {
 }
 #[cfg(test)]
 pub mod test_hander {
-    use std::sync::Arc;
-
-    use nostr_sdk::prelude::*;
+    use futures::StreamExt;
     use wasm_bindgen_test::*;
 
-    use crate::nostr::NotificationHandler;
+    use crate::nostr::{SubEvent, SubStream};
 
-    pub fn create_console_log_handler() -> NotificationHandler {
-        Arc::new(|notification| {
-            Box::pin(async move {
-                match notification {
-                    RelayPoolNotification::Message {
-                        message: RelayMessage::Event { event, .. },
-                        ..
-                    } => {
-                        console_log!(
-                            "eventid: {:?}, author: {:?}, eventkind: {:?}, eventcontent: {:?}",
-                            event.id.to_string(),
-                            event.author().to_string(),
-                            event.kind,
-                            event.content
-                        );
-                        Ok(false) // Return true to stop the handling process
-                    }
-                    _ => Ok(false),
+    pub async fn log_sub_events(mut stream: SubStream) {
+        while let Some(sub_event) = stream.next().await {
+            match sub_event {
+                SubEvent::Event { event, .. } => {
+                    console_log!(
+                        "eventid: {:?}, author: {:?}, eventkind: {:?}, eventcontent: {:?}",
+                        event.id.to_string(),
+                        event.author().to_string(),
+                        event.kind,
+                        event.content
+                    );
                 }
-            })
-        })
+                sub_event => console_log!("{:?}", sub_event),
+            }
+        }
     }
 }
 
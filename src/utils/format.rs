@@ -313,3 +313,12 @@ pub fn remove_content_nostr_str(content: &str) -> String {
     let re: Regex = Regex::new(r"nostr:[^\s]+").unwrap();
     return re.replace_all(content, "").to_string();
 }
+
+/// Parses a 4 digit pin as typed in a pin input
+pub fn parse_pin(value: &str) -> Option<[u8; 4]> {
+    let digits: Vec<u8> = value
+        .chars()
+        .map(|c| c.to_digit(10).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    digits.try_into().ok()
+}
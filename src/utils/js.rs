@@ -84,6 +84,18 @@ pub async fn alert(msg: String) {
     eval.send(msg.into()).unwrap();
 }
 
+/// Asks `msg` with the browser's confirm dialog, `true` if the user accepted
+pub async fn confirm(msg: String) -> bool {
+    let mut eval: UseEval = eval(
+        r#"
+        let msg = await dioxus.recv();
+        dioxus.send(confirm(msg));
+        "#,
+    );
+    eval.send(msg.into()).unwrap();
+    matches!(eval.recv().await, Ok(Value::Bool(true)))
+}
+
 pub async fn note_srcoll_into_view(node_id: &str) {
     let eval: UseEval = eval(
         r#"
@@ -0,0 +1,352 @@
+use std::str::FromStr;
+
+use dioxus::prelude::*;
+use nostr_sdk::{Keys, SecretKey, Url};
+use qrcode::render::svg;
+use qrcode::QrCode;
+
+use crate::account::extension::{is_available, login_extension};
+use crate::account::remote::{connect, connect_bunker, nostrconnect_uri};
+use crate::account::{check_passphrase, decrypt_ncryptsec, encrypt_ncryptsec, unlock};
+use crate::init::LAST_LOGINED_KEY;
+use crate::store::user::NcryptsecPrivkey;
+use crate::store::{AccountType, CBWebDatabase, User};
+use crate::utils::js::{alert, confirm};
+
+const DEFAULT_CONNECT_RELAY: &str = "wss://relay.nsec.app";
+const APP_NAME: &str = "Capybastr";
+
+/// Stores a new account and makes it the last used one.
+///
+/// An existing account of the same name, and its key, is only replaced once
+/// the user confirms it.
+pub(super) async fn save_account(
+    cb_database_db: Signal<CBWebDatabase>,
+    mut all_users: Signal<Vec<User>>,
+    name: String,
+    inner: AccountType,
+) -> Result<(), String> {
+    let exists = all_users.read().iter().any(|u| u.name == name);
+    if exists
+        && !confirm(format!(
+            "An account named {} already exists, replace it and its key?",
+            name
+        ))
+        .await
+    {
+        return Err(format!("An account named {} already exists", name));
+    }
+    let user = User {
+        name: name.clone(),
+        inner,
+    };
+    let db = cb_database_db.read().clone();
+    db.save_user(user.clone())
+        .await
+        .map_err(|e| format!("Save account failed: {}", e))?;
+    if let Err(e) = db.save_misc(LAST_LOGINED_KEY.to_string(), name).await {
+        tracing::error!("save last login error: {:?}", e);
+    }
+    all_users.write().retain(|u| u.name != user.name);
+    all_users.push(user);
+    Ok(())
+}
+
+#[component]
+pub fn SignerLogin() -> Element {
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let all_users = use_context::<Signal<Vec<User>>>();
+
+    let mut name = use_signal(String::new);
+    let mut bunker_uri = use_signal(String::new);
+    let mut relay = use_signal(|| DEFAULT_CONNECT_RELAY.to_string());
+    let mut qrcode = use_signal(String::new);
+    let mut status = use_signal(String::new);
+    let mut secret = use_signal(String::new);
+    let mut passphrase = use_signal(String::new);
+    let mut exported = use_signal(String::new);
+
+    let handle_bunker = move |_| {
+        if name().is_empty() {
+            spawn(async move {
+                alert("Account name cannot be empty".to_string()).await;
+            });
+            return;
+        }
+        if let Err(e) = check_passphrase(&passphrase()) {
+            status.set(e.to_string());
+            return;
+        }
+        spawn(async move {
+            status.set("Waiting for the bunker to approve...".to_string());
+            match connect_bunker(&bunker_uri(), &passphrase()).await {
+                Ok((session, _)) => {
+                    match save_account(
+                        cb_database_db,
+                        all_users,
+                        name(),
+                        AccountType::RemoteSigner(session),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            bunker_uri.set(String::new());
+                            passphrase.set(String::new());
+                            status.set("Connected".to_string());
+                        }
+                        Err(e) => status.set(e),
+                    }
+                }
+                Err(e) => status.set(format!("Connection failed: {}", e)),
+            }
+        });
+    };
+
+    let handle_nostrconnect = move |_| {
+        if name().is_empty() {
+            spawn(async move {
+                alert("Account name cannot be empty".to_string()).await;
+            });
+            return;
+        }
+        if let Err(e) = check_passphrase(&passphrase()) {
+            status.set(e.to_string());
+            return;
+        }
+        let url = match Url::parse(&relay()) {
+            Ok(url) => url,
+            Err(e) => {
+                spawn(async move {
+                    alert(format!("Invalid relay: {}", e)).await;
+                });
+                return;
+            }
+        };
+        let app_keys = Keys::generate();
+        let uri = nostrconnect_uri(&app_keys, url, APP_NAME);
+        match QrCode::new(uri.to_string()) {
+            Ok(code) => qrcode.set(code.render::<svg::Color>().min_dimensions(200, 200).build()),
+            Err(e) => tracing::error!("qrcode error: {:?}", e),
+        }
+        spawn(async move {
+            status.set(format!("Scan with your signer app or paste: {}", uri));
+            match connect(uri, app_keys, &passphrase()).await {
+                Ok((session, _)) => {
+                    match save_account(
+                        cb_database_db,
+                        all_users,
+                        name(),
+                        AccountType::RemoteSigner(session),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            passphrase.set(String::new());
+                            status.set("Connected".to_string());
+                        }
+                        Err(e) => status.set(e),
+                    }
+                }
+                Err(e) => status.set(format!("Connection failed: {}", e)),
+            }
+            qrcode.set(String::new());
+        });
+    };
+
+    let handle_import = move |_| {
+        if name().is_empty() || passphrase().is_empty() {
+            spawn(async move {
+                alert("Account name and passphrase cannot be empty".to_string()).await;
+            });
+            return;
+        }
+        // an imported ncryptsec is kept as is, it is no stronger than its passphrase
+        if let Err(e) = check_passphrase(&passphrase()) {
+            status.set(format!("Import failed: {}", e));
+            return;
+        }
+        spawn(async move {
+            let key = secret().trim().to_string();
+            // an ncryptsec is stored as is once the passphrase is proven right
+            let ncryptsec = if key.starts_with("ncryptsec1") {
+                decrypt_ncryptsec(&key, &passphrase()).map(|_| key)
+            } else {
+                match SecretKey::from_str(&key) {
+                    Ok(sk) => encrypt_ncryptsec(&sk, &passphrase()),
+                    Err(e) => {
+                        status.set(format!("Invalid key: {}", e));
+                        return;
+                    }
+                }
+            };
+            match ncryptsec {
+                Ok(ncryptsec) => {
+                    match save_account(
+                        cb_database_db,
+                        all_users,
+                        name(),
+                        AccountType::Ncryptsec(NcryptsecPrivkey::new(ncryptsec)),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            secret.set(String::new());
+                            passphrase.set(String::new());
+                            status.set("Imported".to_string());
+                        }
+                        Err(e) => status.set(format!("Import failed: {}", e)),
+                    }
+                }
+                Err(e) => status.set(format!("Import failed: {}", e)),
+            }
+        });
+    };
+
+    let handle_export = move |_| {
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            let current = match db.get_misc(LAST_LOGINED_KEY.to_string()).await {
+                Ok(Some(current)) => current,
+                _ => return,
+            };
+            let Some(user) = all_users.read().iter().find(|u| u.name == current).cloned() else {
+                return;
+            };
+            match &user.inner {
+                // only exported under a passphrase that both opens it and is strong enough
+                AccountType::Ncryptsec(key) => {
+                    match check_passphrase(&passphrase()).and_then(|_| unlock(&user.inner, &passphrase())) {
+                        Ok(_) => exported.set(key.ncryptsec.clone()),
+                        Err(e) => status.set(format!("Export failed: {}", e)),
+                    }
+                }
+                AccountType::SecretKey(_) => {
+                    status.set("Unlock this account once to give it a passphrase, then export".to_string())
+                }
+                _ => status.set("This account has no local secret key".to_string()),
+            }
+        });
+    };
+
+    let handle_extension = move |_| {
+        if name().is_empty() {
+            spawn(async move {
+                alert("Account name cannot be empty".to_string()).await;
+            });
+            return;
+        }
+        spawn(async move {
+            match login_extension().await {
+                Ok(account) => {
+                    match save_account(
+                        cb_database_db,
+                        all_users,
+                        name(),
+                        AccountType::Extension(account),
+                    )
+                    .await
+                    {
+                        Ok(_) => status.set("Connected".to_string()),
+                        Err(e) => status.set(e),
+                    }
+                }
+                Err(e) => status.set(format!("Extension login failed: {}", e)),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: "settings-box-style",
+            h1 { class: "font-size-16 mb-15", "Signer login" }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "text",
+                    placeholder: "Account name",
+                    value: "{name}",
+                    oninput: move |event| name.set(event.value()),
+                }
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "password",
+                    placeholder: "Passphrase",
+                    value: "{passphrase}",
+                    oninput: move |event| passphrase.set(event.value()),
+                }
+            }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "text",
+                    placeholder: "bunker://",
+                    value: "{bunker_uri}",
+                    oninput: move |event| bunker_uri.set(event.value()),
+                }
+                button {
+                    class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                    onclick: handle_bunker,
+                    "Connect"
+                }
+            }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "text",
+                    value: "{relay}",
+                    oninput: move |event| relay.set(event.value()),
+                }
+                button {
+                    class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                    onclick: handle_nostrconnect,
+                    "Show QR code"
+                }
+            }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "password",
+                    placeholder: "nsec or ncryptsec",
+                    value: "{secret}",
+                    oninput: move |event| secret.set(event.value()),
+                }
+                button {
+                    class: "btn-circle-true built-li radius-26 text-center font-size-14 mr-8",
+                    onclick: handle_import,
+                    "Import"
+                }
+                button {
+                    class: "btn-circle-false built-li radius-26 text-center font-size-14",
+                    onclick: handle_export,
+                    "Export"
+                }
+            }
+            if !exported().is_empty() {
+                div { class: "font-size-14 text-overflow", "{exported}" }
+            }
+            if is_available() {
+                div {
+                    class: "relay-url-item mb-10 flex items-center",
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                        onclick: handle_extension,
+                        "Use browser extension"
+                    }
+                }
+            }
+            if !qrcode().is_empty() {
+                div {
+                    class: "qrcode-modal-qrcode",
+                    dangerous_inner_html: "{qrcode}",
+                }
+            }
+            if !status().is_empty() {
+                div { class: "font-size-14 text-overflow", "{status}" }
+            }
+        }
+    }
+}
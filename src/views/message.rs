@@ -1,8 +1,327 @@
 use dioxus::prelude::*;
+use nostr_sdk::{FromBech32, NostrSigner, PublicKey, ToBech32};
+
+use crate::account::session::{Error as SessionError, Session};
+use crate::init::{switch_account, LAST_LOGINED_KEY};
+use crate::nostr::{send_private_msg, ConversationKey, Inbox, MultiClient};
+use crate::store::{CBWebDatabase, User, DEFAULT_RELAY_SET_KEY};
+use crate::utils::format::{format_create_at, format_public_key};
+use crate::utils::js::alert;
+
+const PAGE_SIZE: usize = 50;
+
+fn conversation_title(key: &ConversationKey) -> String {
+    key.iter()
+        .map(|pk| format_public_key(&pk.to_bech32().unwrap_or_default(), None))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Moves the read marker of `key` to its last message and persists all markers
+fn mark_read(
+    mut inbox: Signal<Option<Inbox>>,
+    cb_database_db: Signal<CBWebDatabase>,
+    key: ConversationKey,
+) {
+    let (me, markers) = {
+        let mut guard = inbox.write();
+        let Some(current) = guard.as_mut() else {
+            return;
+        };
+        if !current.mark_read(&key) {
+            return;
+        }
+        (current.me(), current.read_markers().clone())
+    };
+    spawn(async move {
+        let db = cb_database_db.read().clone();
+        if let Err(e) = db.save_read_markers(&me.to_hex(), &markers).await {
+            tracing::error!("save read markers error: {:?}", e);
+        }
+    });
+}
 
 #[component]
 pub fn Message() -> Element {
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let all_users = use_context::<Signal<Vec<User>>>();
+    let session = use_context::<Signal<Session>>();
+
+    let mut account = use_signal(|| None::<PublicKey>);
+    let mut signer = use_signal(|| None::<NostrSigner>);
+    let mut inbox = use_signal(|| None::<Inbox>);
+    let mut selected = use_signal(|| None::<ConversationKey>);
+    let mut passphrase = use_signal(String::new);
+    let mut new_receiver = use_signal(String::new);
+    let mut draft = use_signal(String::new);
+    let mut is_loading = use_signal(|| false);
+
+    // the inbox follows the active account
+    use_effect(move || {
+        let current = session.read();
+        let public_key = current.public_key();
+        let current_signer = current.signer().ok().cloned();
+        if *account.peek() == public_key && signer.peek().is_some() == current_signer.is_some() {
+            return;
+        }
+        account.set(public_key);
+        inbox.set(None);
+        selected.set(None);
+        signer.set(current_signer);
+    });
+
+    // unlocks the last used local key, other accounts are switched from the menu
+    let handle_unlock = move |_| {
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            let name = db
+                .get_misc(LAST_LOGINED_KEY.to_string())
+                .await
+                .ok()
+                .flatten();
+            let user = all_users
+                .read()
+                .iter()
+                .find(|u| Some(&u.name) == name.as_ref())
+                .cloned();
+            let Some(user) = user else {
+                alert(SessionError::NoSigner.to_string()).await;
+                return;
+            };
+            let secret = passphrase();
+            passphrase.set(String::new());
+            if let Err(e) =
+                switch_account(session, multiclient, cb_database_db, user, Some(secret)).await
+            {
+                alert(e.to_string()).await;
+            }
+        });
+    };
+
+    // load the inbox once the signer is unlocked
+    use_effect(move || {
+        let Some(signer) = signer() else {
+            return;
+        };
+        spawn(async move {
+            is_loading.set(true);
+            let clients = multiclient();
+            match clients.get_or_create(DEFAULT_RELAY_SET_KEY).await {
+                Ok(hc) => {
+                    let db = cb_database_db.read().clone();
+                    let markers = match signer.public_key().await {
+                        Ok(me) => db.get_read_markers(&me.to_hex()).await.unwrap_or_default(),
+                        Err(_) => Default::default(),
+                    };
+                    match Inbox::new(hc, signer, markers, None).await {
+                        Ok(mut new_inbox) => match new_inbox.fetch_latest(PAGE_SIZE).await {
+                            Ok(page) => {
+                                new_inbox.extend_page(page);
+                                inbox.set(Some(new_inbox));
+                            }
+                            Err(e) => tracing::error!("inbox fetch error: {:?}", e),
+                        },
+                        Err(e) => tracing::error!("inbox error: {:?}", e),
+                    }
+                }
+                Err(e) => tracing::error!("message client Error: {:?}", e),
+            }
+            is_loading.set(false);
+        });
+    });
+
+    let handle_new = move |_| match PublicKey::from_bech32(new_receiver()) {
+        Ok(pk) => {
+            new_receiver.set(String::new());
+            selected.set(Some(ConversationKey::from([pk])));
+        }
+        Err(_) => {
+            spawn(async move {
+                alert("Invalid npub".to_string()).await;
+            });
+        }
+    };
+
+    let handle_older = move |_| {
+        let (Some(current), Some(key)) = (inbox(), selected()) else {
+            return;
+        };
+        spawn(async move {
+            is_loading.set(true);
+            match current.fetch_older(&key, PAGE_SIZE).await {
+                Ok(page) => {
+                    if let Some(list) = inbox.write().as_mut() {
+                        list.extend_page(page);
+                    }
+                }
+                Err(e) => tracing::error!("load older messages error: {:?}", e),
+            }
+            is_loading.set(false);
+        });
+    };
+
+    let handle_send = move |_| {
+        let (Some(signer), Some(current), Some(key)) = (signer(), inbox(), selected()) else {
+            return;
+        };
+        // NIP-17 group chats need one rumor for all members, only 1:1 is supported for now
+        let receiver = match key.iter().next() {
+            Some(pk) if key.len() == 1 => *pk,
+            _ => return,
+        };
+        let content = draft();
+        if content.is_empty() {
+            return;
+        }
+        spawn(async move {
+            let clients = multiclient();
+            match clients.get_or_create(DEFAULT_RELAY_SET_KEY).await {
+                Ok(hc) => {
+                    match send_private_msg(&hc, &signer, receiver, &content, None).await {
+                        Ok(_) => {
+                            draft.set(String::new());
+                            // our own copy comes back through the gift wrap addressed to us
+                            match current.fetch_newer(&key, PAGE_SIZE).await {
+                                Ok(page) => {
+                                    if let Some(list) = inbox.write().as_mut() {
+                                        list.extend_page(page);
+                                    }
+                                    mark_read(inbox, cb_database_db, key);
+                                }
+                                Err(e) => tracing::error!("load newer messages error: {:?}", e),
+                            }
+                        }
+                        Err(e) => {
+                            alert(format!("Send failed: {}", e)).await;
+                        }
+                    }
+                }
+                Err(e) => tracing::error!("message client Error: {:?}", e),
+            }
+        });
+    };
+
+    if signer.read().is_none() {
+        return rsx! {
+            div {
+                class: "settings-box-style",
+                h1 { class: "font-size-16 mb-15", "Messages" }
+                div { class: "font-size-14 mb-10", "{SessionError::NoSigner}" }
+                div {
+                    class: "relay-url-item mb-10 flex items-center",
+                    input {
+                        class: "relay-ipt mr-10",
+                        r#type: "password",
+                        placeholder: "Passphrase",
+                        value: "{passphrase}",
+                        oninput: move |event| passphrase.set(event.value()),
+                    }
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                        onclick: handle_unlock,
+                        "Unlock"
+                    }
+                }
+            }
+        };
+    }
+
+    let current_inbox = inbox();
+    let me = current_inbox.as_ref().map(|i| i.me());
+    let summaries = current_inbox
+        .as_ref()
+        .map(|i| i.summaries())
+        .unwrap_or_default();
+    let current = selected().map(|key| {
+        let msgs = current_inbox
+            .as_ref()
+            .map(|i| i.messages(&key))
+            .unwrap_or_default();
+        (key, msgs)
+    });
+
     rsx! {
-        h1 { "Message" }
+        div {
+            class: "message-content display-flex-box",
+            div {
+                class: "message-list",
+                div {
+                    class: "relay-url-item mb-10 flex items-center",
+                    input {
+                        class: "relay-ipt mr-10",
+                        r#type: "text",
+                        placeholder: "npub",
+                        value: "{new_receiver}",
+                        oninput: move |event| new_receiver.set(event.value()),
+                    }
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                        onclick: handle_new,
+                        "New"
+                    }
+                }
+                if is_loading() {
+                    div { class: "font-size-14", "Loading..." }
+                }
+                for summary in summaries {
+                    div {
+                        class: format!("built-li radius-26 mb-10 font-size-14 text-overflow {}",
+                            if Some(&summary.key) == selected.read().as_ref() { "built-li-checked" } else { "" }),
+                        onclick: {
+                            let key = summary.key.clone();
+                            move |_| {
+                                selected.set(Some(key.clone()));
+                                mark_read(inbox, cb_database_db, key.clone());
+                            }
+                        },
+                        div {
+                            "{conversation_title(&summary.key)}"
+                            if summary.unread > 0 {
+                                span { class: "note-action-count", " {summary.unread}" }
+                            }
+                        }
+                        div {
+                            class: "text-overflow",
+                            "{summary.last.content.clone().unwrap_or_default()}"
+                        }
+                    }
+                }
+            }
+            if let Some((key, msgs)) = current {
+                div {
+                    class: "message-thread",
+                    h1 { class: "font-size-16 mb-15", "{conversation_title(&key)}" }
+                    button {
+                        class: "btn-circle-false built-li radius-26 text-center font-size-14 mb-10",
+                        onclick: handle_older,
+                        "Load older"
+                    }
+                    for msg in msgs {
+                        div {
+                            class: if Some(msg.pubkey) == me { "message-item message-mine mb-10" } else { "message-item mb-10" },
+                            div { class: "font-size-14", "{msg.content.clone().unwrap_or_default()}" }
+                            div { class: "font-size-12", "{format_create_at(msg.created_at.as_u64())}" }
+                        }
+                    }
+                    if key.len() == 1 {
+                        div {
+                            class: "relay-url-item mb-10 flex items-center",
+                            input {
+                                class: "relay-ipt mr-10",
+                                r#type: "text",
+                                value: "{draft}",
+                                oninput: move |event| draft.set(event.value()),
+                            }
+                            button {
+                                class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                                onclick: handle_send,
+                                "Send"
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use dioxus::prelude::*;
+
+use super::login::save_account;
+use crate::account::{check_passphrase, encrypt_ncryptsec};
+use crate::account::mnemonic::{backup_challenge, derive_keys, generate_mnemonic, verify_backup};
+use crate::store::user::NcryptsecPrivkey;
+use crate::store::{AccountType, CBWebDatabase, User};
+use crate::utils::js::alert;
+
+const MNEMONIC_WORDS: usize = 12;
+const CHALLENGE_WORDS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Start,
+    Backup,
+    Confirm,
+    Recover,
+    Done,
+}
+
+#[component]
+pub fn MnemonicAccount() -> Element {
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let all_users = use_context::<Signal<Vec<User>>>();
+
+    let mut step = use_signal(|| Step::Start);
+    let mut name = use_signal(String::new);
+    let mut passphrase = use_signal(String::new);
+    let mut bip39_passphrase = use_signal(String::new);
+    let mut account_index = use_signal(|| "0".to_string());
+    let mut mnemonic = use_signal(String::new);
+    let mut challenge = use_signal(Vec::<usize>::new);
+    let mut answers = use_signal(HashMap::<usize, String>::new);
+    let mut status = use_signal(String::new);
+
+    // derives the key and stores it as ncryptsec under the app passphrase
+    let mut finish = move || {
+        if let Err(e) = check_passphrase(&passphrase()) {
+            status.set(e.to_string());
+            return;
+        }
+        let Ok(index) = account_index().trim().parse::<u32>() else {
+            status.set("Account index must be a number".to_string());
+            return;
+        };
+        let keys = match derive_keys(&mnemonic(), Some(&bip39_passphrase()), index) {
+            Ok(keys) => keys,
+            Err(e) => {
+                status.set(format!("Invalid mnemonic: {}", e));
+                return;
+            }
+        };
+        spawn(async move {
+            let ncryptsec = keys
+                .secret_key()
+                .map_err(|e| e.to_string())
+                .and_then(|sk| encrypt_ncryptsec(sk, &passphrase()).map_err(|e| e.to_string()));
+            match ncryptsec {
+                Ok(ncryptsec) => {
+                    match save_account(
+                        cb_database_db,
+                        all_users,
+                        name(),
+                        AccountType::Ncryptsec(NcryptsecPrivkey::new(ncryptsec)),
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            mnemonic.set(String::new());
+                            passphrase.set(String::new());
+                            bip39_passphrase.set(String::new());
+                            status.set(format!("Account {} is ready", name()));
+                            step.set(Step::Done);
+                        }
+                        // the words are kept so the user can try again
+                        Err(e) => status.set(e),
+                    }
+                }
+                Err(e) => status.set(format!("Encryption failed: {}", e)),
+            }
+        });
+    };
+
+    let mut check_inputs = move || -> bool {
+        if name().is_empty() || passphrase().is_empty() {
+            spawn(async move {
+                alert("Account name and passphrase cannot be empty".to_string()).await;
+            });
+            return false;
+        }
+        // fail before the user writes down words they can't use
+        if let Err(e) = check_passphrase(&passphrase()) {
+            status.set(e.to_string());
+            return false;
+        }
+        true
+    };
+
+    let handle_create = move |_| {
+        if !check_inputs() {
+            return;
+        }
+        match generate_mnemonic(MNEMONIC_WORDS) {
+            Ok(words) => {
+                mnemonic.set(words);
+                status.set(String::new());
+                step.set(Step::Backup);
+            }
+            Err(e) => status.set(format!("Mnemonic generation failed: {}", e)),
+        }
+    };
+
+    let handle_backed_up = move |_| {
+        challenge.set(backup_challenge(&mnemonic(), CHALLENGE_WORDS));
+        answers.set(HashMap::new());
+        step.set(Step::Confirm);
+    };
+
+    let handle_confirm = move |_| {
+        let given: Vec<(usize, String)> = challenge()
+            .iter()
+            .map(|pos| (*pos, answers().get(pos).cloned().unwrap_or_default()))
+            .collect();
+        if verify_backup(&mnemonic(), &given) {
+            finish();
+        } else {
+            status.set("The words do not match, check your backup".to_string());
+            step.set(Step::Backup);
+        }
+    };
+
+    let handle_recover = move |_| {
+        if check_inputs() {
+            finish();
+        }
+    };
+
+    rsx! {
+        div {
+            class: "settings-box-style",
+            h1 { class: "font-size-16 mb-15", "Mnemonic account" }
+            match step() {
+                Step::Start | Step::Recover => rsx! {
+                    div {
+                        class: "relay-url-item mb-10 flex items-center",
+                        input {
+                            class: "relay-ipt mr-10",
+                            r#type: "text",
+                            placeholder: "Account name",
+                            value: "{name}",
+                            oninput: move |event| name.set(event.value()),
+                        }
+                        input {
+                            class: "relay-ipt mr-10",
+                            r#type: "password",
+                            placeholder: "Passphrase to lock the key in this app",
+                            value: "{passphrase}",
+                            oninput: move |event| passphrase.set(event.value()),
+                        }
+                    }
+                    div {
+                        class: "relay-url-item mb-10 flex items-center",
+                        input {
+                            class: "relay-ipt mr-10",
+                            r#type: "password",
+                            placeholder: "BIP-39 passphrase (optional)",
+                            value: "{bip39_passphrase}",
+                            oninput: move |event| bip39_passphrase.set(event.value()),
+                        }
+                        input {
+                            class: "relay-ipt mr-10",
+                            r#type: "number",
+                            min: "0",
+                            placeholder: "Account index",
+                            value: "{account_index}",
+                            oninput: move |event| account_index.set(event.value()),
+                        }
+                    }
+                    if step() == Step::Recover {
+                        div {
+                            class: "relay-url-item mb-10 flex items-center",
+                            textarea {
+                                class: "relay-ipt mr-10",
+                                placeholder: "Mnemonic words",
+                                value: "{mnemonic}",
+                                oninput: move |event| mnemonic.set(event.value()),
+                            }
+                            button {
+                                class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                                onclick: handle_recover,
+                                "Recover"
+                            }
+                        }
+                    } else {
+                        div {
+                            class: "relay-url-item mb-10 flex items-center",
+                            button {
+                                class: "btn-circle-true built-li radius-26 text-center font-size-14 mr-8",
+                                onclick: handle_create,
+                                "Create new"
+                            }
+                            button {
+                                class: "btn-circle-false built-li radius-26 text-center font-size-14",
+                                onclick: move |_| step.set(Step::Recover),
+                                "Recover from mnemonic"
+                            }
+                        }
+                    }
+                },
+                Step::Backup => rsx! {
+                    div { class: "font-size-14 mb-10", "Write these words down in order and keep them offline." }
+                    ol {
+                        class: "font-size-14 mb-10",
+                        for word in mnemonic().split_whitespace() {
+                            li { "{word}" }
+                        }
+                    }
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                        onclick: handle_backed_up,
+                        "I wrote them down"
+                    }
+                },
+                Step::Confirm => rsx! {
+                    for pos in challenge() {
+                        div {
+                            class: "relay-url-item mb-10 flex items-center",
+                            span { class: "mr-10 font-size-14", "Word #{pos + 1}" }
+                            input {
+                                class: "relay-ipt mr-10",
+                                r#type: "text",
+                                value: "{answers().get(&pos).cloned().unwrap_or_default()}",
+                                oninput: move |event| {
+                                    answers.write().insert(pos, event.value());
+                                },
+                            }
+                        }
+                    }
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                        onclick: handle_confirm,
+                        "Confirm"
+                    }
+                },
+                Step::Done => rsx! {},
+            }
+            if !status().is_empty() {
+                div { class: "font-size-14", "{status}" }
+            }
+        }
+    }
+}
@@ -2,7 +2,9 @@ mod bookmark;
 mod channel;
 mod group;
 mod home;
+mod login;
 mod message;
+mod mnemonic;
 mod new_subscription;
 mod note_detail;
 mod note_list;
@@ -12,12 +14,15 @@ mod search;
 mod settings;
 mod subscription;
 mod test;
+mod wallet;
 
 pub use bookmark::Bookmark;
 pub use channel::Channel;
 pub use group::Group;
 pub use home::Home;
+pub use login::SignerLogin;
 pub use message::Message;
+pub use mnemonic::MnemonicAccount;
 pub use new_subscription::NewSubscription;
 pub use note_detail::NoteDetail;
 pub use note_list::NoteList;
@@ -27,3 +32,4 @@ pub use search::Search;
 pub use settings::Settings;
 pub use subscription::Subscription;
 pub use test::Test;
+pub use wallet::WalletSettings;
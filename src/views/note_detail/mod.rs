@@ -63,7 +63,6 @@ pub fn NoteDetail(sub: String, root_id: String, note_id: String) -> Element {
                         let _sub = _subs_map.get(&new_sub_name).unwrap();
                         sub_current.set(_sub.clone());
                         if let Some(client) = clients.get_client(&_sub.relay_set).await {
-                            let client = client.client();
                             match get_event_by_id(&client, &root_event_id, None).await {
                                 Ok(Some(event)) => {
                                     replytree_manager
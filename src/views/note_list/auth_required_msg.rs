@@ -0,0 +1,39 @@
+use dioxus::prelude::*;
+use nostr_sdk::Url;
+
+use crate::init::MODAL_MANAGER;
+use crate::nostr::MultiClient;
+use crate::utils::js::alert;
+
+pub const AUTH_REQUIRED_MSG_ID: &str = "sub-auth-required";
+
+/// Shown when a relay closed a feed subscription until we authenticate
+#[component]
+pub fn AuthRequiredMsg(relay_url: Url, reason: String) -> Element {
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let handle_auth = move |_| {
+        let relay_url = relay_url.clone();
+        spawn(async move {
+            let result = match multiclient().pool().await {
+                Ok(pool) => pool
+                    .authenticator()
+                    .authenticate(&pool, &relay_url)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match result {
+                Ok(_) => MODAL_MANAGER.write().close_modal(AUTH_REQUIRED_MSG_ID),
+                Err(e) => alert(e).await,
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: "new-note-msg",
+            onclick: handle_auth,
+            {format!("{} requires authentication ({}), click to sign in", relay_url, reason)}
+        }
+    }
+}
@@ -1,11 +1,21 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use std::usize;
 
 use dioxus::prelude::*;
+use gloo_timers::future::TimeoutFuture;
+use nostr_sdk::{Filter, Url};
 use regex::Regex;
 
 use crate::components::icons::{ADD, BOTTOMRIGHT, FALSE, TRUE, UPPERRIGHT};
-use crate::store::subscription::RelaySet;
+use crate::account::session::Session;
+use crate::components::RelayInfo;
+use crate::nostr::{
+    apply_recommendation, apply_relay_set_edit, discover, relay_string, AuthState,
+    ConnectionState, EventCache, MultiClient, Recommendation, Register, RelayHealth, SkipPolicy,
+    DEFAULT_MAX_RELAYS,
+};
+use crate::store::subscription::{AuthPolicy, CustomSub, RelaySet};
 use crate::store::{CBWebDatabase, DEFAULT_RELAY_SET_KEY};
 use crate::utils::contants::WSS_REG;
 use crate::utils::js::alert;
@@ -21,6 +31,176 @@ pub struct RelaysInputProps {
     #[props(default = true)]
     is_popup: bool,
 }
+// relays answering slower than this are skipped when the user asks for it
+const SLOW_RELAY: Duration = Duration::from_secs(2);
+const HEALTH_REFRESH_MS: u32 = 2_000;
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[component]
+fn RelayHealthLine(health: Option<RelayHealth>) -> Element {
+    let health = health.unwrap_or_default();
+    let ms = |d: Option<Duration>| match d {
+        Some(d) => format!("{}ms", d.as_millis()),
+        None => "-".to_string(),
+    };
+    let retry = match (health.state, health.next_retry) {
+        (ConnectionState::Disconnected, Some(at)) => {
+            let secs = ((at - js_sys::Date::now()) / 1000.0).max(0.0) as u64;
+            format!(" · retry in {}s", secs)
+        }
+        _ => String::new(),
+    };
+    let rtt = ms(health.latency);
+    let eose = ms(health.eose);
+    let uptime = format_duration(health.uptime());
+    rsx! {
+        div {
+            class: "relay-health relay-health--{health.state}",
+            span { class: "relay-health-dot" }
+            "{health.state}{retry} · rtt {rtt} · eose {eose} · up {uptime} · {health.errors} errors · {health.notices} notices"
+        }
+    }
+}
+
+#[component]
+fn RelayAuthLine(
+    policy: AuthPolicy,
+    state: Option<AuthState>,
+    on_change: EventHandler<AuthPolicy>,
+) -> Element {
+    let state = state.map(|state| format!(" · {}", state)).unwrap_or_default();
+    rsx! {
+        div {
+            class: "relay-health",
+            "NIP-42 auth"
+            select {
+                value: "{policy}",
+                oninput: move |event| {
+                    if let Ok(policy) = event.value().parse::<AuthPolicy>() {
+                        on_change.call(policy);
+                    }
+                },
+                for p in AuthPolicy::ALL {
+                    option {
+                        value: "{p}",
+                        selected: p == policy,
+                        "{p}"
+                    }
+                }
+            }
+            "{state}"
+        }
+    }
+}
+
+/// Relays suggested from where the follows write, applied to `name` at once
+#[component]
+fn RelaySuggest(name: String, on_applied: EventHandler<RelaySet>) -> Element {
+    let session = use_context::<Signal<Session>>();
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let mut recommendation = use_signal(|| None::<Recommendation>);
+    let mut loading = use_signal(|| false);
+
+    let handle_discover = move |_| {
+        let Some(public_key) = session.read().public_key() else {
+            spawn(async move {
+                alert("Log in to suggest relays from your follows".to_string()).await;
+            });
+            return;
+        };
+        loading.set(true);
+        spawn(async move {
+            let result = match multiclient().get_or_create(DEFAULT_RELAY_SET_KEY).await {
+                Ok(hc) => discover(&multiclient(), &hc, &public_key, DEFAULT_MAX_RELAYS)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match result {
+                Ok(r) => recommendation.set(Some(r)),
+                Err(e) => {
+                    tracing::error!("relay discovery error: {}", e);
+                    alert(format!("Relay discovery failed: {}", e)).await;
+                }
+            }
+            loading.set(false);
+        });
+    };
+    let set_name = name.clone();
+    let handle_apply = move |_| {
+        let Some(r) = recommendation() else {
+            return;
+        };
+        let name = set_name.clone();
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            match apply_recommendation(&multiclient(), &db, &name, &r.cover).await {
+                Ok(relay_set) => {
+                    recommendation.set(None);
+                    on_applied.call(relay_set);
+                }
+                Err(e) => {
+                    tracing::error!("apply relay suggestion error: {:?}", e);
+                    alert(format!("Failed to apply the suggestion: {}", e)).await;
+                }
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: "relay-suggest mt-10",
+            button {
+                class: "capy-btn",
+                disabled: loading(),
+                onclick: handle_discover,
+                if loading() { "Looking at your follows..." } else { "Suggest from follows" }
+            }
+            if let Some(r) = recommendation() {
+                div {
+                    class: "relay-health",
+                    "{r.cover.len()} relays cover {r.covered()} of {r.follows} follows"
+                }
+                for url in r.cover.iter() {
+                    {
+                        let coverage = r
+                            .ranked
+                            .iter()
+                            .find(|s| &s.url == url)
+                            .map(|s| s.coverage)
+                            .unwrap_or_default();
+                        let url = relay_string(url);
+                        rsx! {
+                            div {
+                                class: "relay-suggest-item text-overflow",
+                                "{url} · {coverage} follows"
+                            }
+                        }
+                    }
+                }
+                if !r.cover.is_empty() {
+                    button {
+                        class: "capy-btn mt-10",
+                        onclick: handle_apply,
+                        "Apply to {name}"
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct ModifiedRelaySet {
     old_name: String,
@@ -36,11 +216,66 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
     let mut old_relay_sets: Signal<Vec<RelaySet>> = use_signal(Vec::new);
     let allow_edit = use_context::<Signal<bool>>();
     let mut cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let multiclient = use_context::<Signal<MultiClient>>();
+    let mut subs_map = use_context::<Signal<HashMap<String, CustomSub>>>();
+    let register = use_context::<Signal<Register>>();
+    let event_cache = use_context::<Signal<EventCache>>();
+    let mut health: Signal<HashMap<String, RelayHealth>> = use_signal(HashMap::new);
+    let mut auth_states: Signal<HashMap<String, AuthState>> = use_signal(HashMap::new);
+    let mut skip_slow = use_signal(|| false);
     // let relay_sets.read() = relay_sets.read();
     let current_relay_set: RelaySet = match relay_sets.read().get(relay_curent_index()) {
         Some(relay) => relay.clone(),
         None => RelaySet::new(&relay_sets.read().len()),
     };
+    // checked against what each relay says it supports
+    let relay_subs: Vec<(String, Vec<Filter>)> = subs_map
+        .read()
+        .values()
+        .filter(|sub| sub.relay_set == current_relay_set.name)
+        .map(|sub| (sub.name.clone(), sub.get_filters()))
+        .collect();
+    // the monitor has no change feed, poll it while the editor is mounted
+    use_future(move || async move {
+        loop {
+            if let Ok(pool) = multiclient().pool().await {
+                let monitor = pool.monitor();
+                let authenticator = pool.authenticator();
+                skip_slow.set(monitor.policy().slow_after.is_some());
+                let relays = relay_sets
+                    .read()
+                    .get(relay_curent_index())
+                    .map(|rs| rs.relays.clone())
+                    .unwrap_or_default();
+                let mut map = HashMap::new();
+                let mut auth_map = HashMap::new();
+                for relay in relays {
+                    if let Ok(url) = Url::parse(&relay) {
+                        if let Some(state) = authenticator.state(&url) {
+                            auth_map.insert(relay.clone(), state);
+                        }
+                        if let Some(h) = monitor.get(&url) {
+                            map.insert(relay, h);
+                        }
+                    }
+                }
+                health.set(map);
+                auth_states.set(auth_map);
+            }
+            TimeoutFuture::new(HEALTH_REFRESH_MS).await;
+        }
+    });
+    let handle_skip_slow = move |checked: bool| {
+        skip_slow.set(checked);
+        spawn(async move {
+            if let Ok(monitor) = multiclient().monitor().await {
+                monitor.set_policy(SkipPolicy {
+                    skip_dead: true,
+                    slow_after: if checked { Some(SLOW_RELAY) } else { None },
+                });
+            }
+        });
+    };
     let wss_regx = Regex::new(WSS_REG).unwrap();
     // tracing::info!("index: {:?}", );
     use_effect(use_reactive(&props.relay_name, move |_relay_name| {
@@ -176,6 +411,25 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                         .relay_set_change(relay.old_name.clone(), relay.new_relay.clone())
                         .await
                         .unwrap();
+                    // running feeds move to the new relays without a reload
+                    if let Err(e) = apply_relay_set_edit(
+                        &multiclient(),
+                        &register(),
+                        &event_cache(),
+                        &relay.old_name,
+                        &relay.new_relay,
+                    )
+                    .await
+                    {
+                        tracing::error!("apply relay set edit error: {:?}", e);
+                    }
+                    if relay.old_name != relay.new_relay.name {
+                        for sub in subs_map.write().values_mut() {
+                            if sub.relay_set == relay.old_name {
+                                sub.relay_set.clone_from(&relay.new_relay.name);
+                            }
+                        }
+                    }
                 }
 
                 for relay in deleted.iter() {
@@ -183,11 +437,20 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                         .remove_relay_set(relay.name.clone())
                         .await
                         .unwrap();
+                    if let Err(e) = multiclient().remove(&relay.name).await {
+                        tracing::error!("remove relay set client error: {:?}", e);
+                    }
                 }
                 // Refresh relay sets signal after database operations
                 let updated_relay_sets: Vec<RelaySet> =
                     cb_database_db_write.get_all_relay_sets().await.unwrap();
                 relay_sets.set(updated_relay_sets.clone());
+                // the connections are shared, policies apply right away
+                if let Ok(authenticator) = multiclient().authenticator().await {
+                    for relay_set in updated_relay_sets.iter() {
+                        authenticator.set_policies(relay_set);
+                    }
+                }
                 old_relay_sets.set(updated_relay_sets);
             }
 
@@ -197,6 +460,19 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
             edit.set(false);
         });
     };
+    // the suggestion is saved already, nothing left for Save to diff
+    let handle_suggestion = move |relay_set: RelaySet| {
+        let mut sets = relay_sets.write();
+        match sets.iter().position(|rs| rs.name == relay_set.name) {
+            Some(i) => sets[i] = relay_set.clone(),
+            None => sets.push(relay_set.clone()),
+        }
+        let mut old_sets = old_relay_sets.write();
+        match old_sets.iter().position(|rs| rs.name == relay_set.name) {
+            Some(i) => old_sets[i] = relay_set,
+            None => old_sets.push(relay_set),
+        }
+    };
     let handle_delete = move |name: String| {
         spawn(async move {
             let cb_database_db_write = cb_database_db.write();
@@ -204,6 +480,9 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                 .remove_relay_set(name.clone())
                 .await
                 .unwrap();
+            if let Err(e) = multiclient().remove(&name).await {
+                tracing::error!("remove relay set client error: {:?}", e);
+            }
         });
     };
 
@@ -328,6 +607,23 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                                         }
                                     }
                                 }
+                                RelayHealthLine {
+                                    health: health.read().get(relay_url).cloned()
+                                }
+                                RelayAuthLine {
+                                    policy: current_relay_set.auth_policy(relay_url),
+                                    state: auth_states.read().get(relay_url).cloned(),
+                                    on_change: {
+                                        let relay_url = relay_url.clone();
+                                        move |policy| {
+                                            relay_sets.write()[relay_curent_index()].auth.insert(relay_url.clone(), policy);
+                                        }
+                                    }
+                                }
+                                RelayInfo {
+                                    url: relay_url.clone(),
+                                    subs: relay_subs.clone()
+                                }
                             }
                             div {
                                 class:"relay-url-item flex items-center",
@@ -364,6 +660,19 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                                     }
                                 }
                             }
+                            label {
+                                class: "relay-health mt-10 cursor-pointer",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: skip_slow(),
+                                    oninput: move |event| handle_skip_slow(event.value() == "true"),
+                                }
+                                "Skip relays slower than {SLOW_RELAY.as_secs()}s in queries"
+                            }
+                            RelaySuggest {
+                                name: current_relay_set.name.clone(),
+                                on_applied: handle_suggestion
+                            }
                         }
                     }
                 }
@@ -469,6 +778,23 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                                 }
                             }
                         }
+                        RelayHealthLine {
+                            health: health.read().get(relay_url).cloned()
+                        }
+                        RelayAuthLine {
+                            policy: current_relay_set.auth_policy(relay_url),
+                            state: auth_states.read().get(relay_url).cloned(),
+                            on_change: {
+                                let relay_url = relay_url.clone();
+                                move |policy| {
+                                    relay_sets.write()[relay_curent_index()].auth.insert(relay_url.clone(), policy);
+                                }
+                            }
+                        }
+                        RelayInfo {
+                            url: relay_url.clone(),
+                            subs: relay_subs.clone()
+                        }
                     }
                     div {
                         class:"relay-url-item flex items-center",
@@ -505,6 +831,19 @@ pub fn RelaysInput(props: RelaysInputProps) -> Element {
                             }
                         }
                     }
+                    label {
+                        class: "relay-health mt-10 cursor-pointer",
+                        input {
+                            r#type: "checkbox",
+                            checked: skip_slow(),
+                            oninput: move |event| handle_skip_slow(event.value() == "true"),
+                        }
+                        "Skip relays slower than {SLOW_RELAY.as_secs()}s in queries"
+                    }
+                    RelaySuggest {
+                        name: current_relay_set.name.clone(),
+                        on_applied: handle_suggestion
+                    }
                 }
             }
 
@@ -1,3 +1,4 @@
+mod auth_required_msg;
 pub(crate) mod custom_sub;
 pub mod detail_modal;
 pub mod new_note_msg;
@@ -5,20 +6,23 @@ pub mod note;
 pub mod reply;
 
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
 
 use crate::components::icons::LOADING;
 use crate::init::MODAL_MANAGER;
 use crate::init::SUB_COUNTERS;
 use crate::nostr::EventPaginator;
 use crate::nostr::MultiClient;
-use crate::nostr::{NotificationHandler, Register};
-use crate::store::subscription::CustomSub;
+use crate::nostr::{
+    is_auth_required, BatchOptions, Register, SubEvent, SubStream, AUTH_REQUIRED,
+};
+use crate::store::subscription::{CustomSub, FilterTemp};
 use crate::utils::js::{get_scroll_info, throttle};
+use auth_required_msg::{AuthRequiredMsg, AUTH_REQUIRED_MSG_ID};
 use dioxus::prelude::*;
+use futures::StreamExt;
 use new_note_msg::NewNoteMsg;
 use nostr_indexeddb::database::Order;
-use nostr_sdk::{Event, RelayMessage, RelayPoolNotification, SubscriptionId, Timestamp};
+use nostr_sdk::{Event, SubscriptionId, Timestamp};
 use note::Note;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::{JsCast, JsValue};
@@ -31,45 +35,56 @@ pub struct NoteListProps {
     pub is_cache: bool,
 }
 
-pub fn handle_sub_list(sub_id: Arc<RwLock<SubscriptionId>>) -> NotificationHandler {
-    Arc::new(move |notification| {
-        let sub_id = sub_id.read().unwrap().clone();
-        Box::pin(async move {
-            let sub_id = sub_id.clone();
-            match notification {
-                RelayPoolNotification::Message {
-                    message: RelayMessage::Event { event, .. },
-                    ..
-                } => {
-                    tracing::info!(
-                        "eventid: {:?}, author: {:?}, eventkind: {:?}, eventcontent: {:?}",
-                        event.id.to_string(),
-                        event.author().to_string(),
-                        event.kind,
-                        event.content
-                    );
-                    {
-                        SUB_COUNTERS.write().inc(&sub_id, *event.clone());
-                        let id = "sub-new-msg".to_string();
-                        MODAL_MANAGER.write().add_message(
-                            rsx! {
-                                NewNoteMsg {
-                                    sub_id: sub_id.clone()
-                                }
-                            },
-                            id.clone(),
-                        );
-                        MODAL_MANAGER.write().open_modal(&id);
-                    }
-                    Ok(false)
-                }
-                _ => {
-                    tracing::info!("notification: {:?}", notification);
-                    Ok(false)
+/// Shows the "new notes" message of `sub_id`
+fn show_new_notes(sub_id: &SubscriptionId) {
+    let id = "sub-new-msg".to_string();
+    MODAL_MANAGER.write().add_message(
+        rsx! {
+            NewNoteMsg {
+                sub_id: sub_id.clone()
+            }
+        },
+        id.clone(),
+    );
+    MODAL_MANAGER.write().open_modal(&id);
+}
+
+/// Follows the live subscription of a note list: new events feed the
+/// "new notes" counter, a relay asking for AUTH first tells the user
+pub async fn handle_sub_list(sub_id: SubscriptionId, mut stream: SubStream) {
+    while let Some(sub_event) = stream.next().await {
+        match sub_event {
+            SubEvent::Event { event, .. } => {
+                SUB_COUNTERS.write().inc(&sub_id, *event);
+                show_new_notes(&sub_id);
+            }
+            SubEvent::Events { events, dropped } => {
+                tracing::info!("{:?}: {} new events", sub_id, events.len());
+                if dropped > 0 {
+                    tracing::warn!("{:?}: {} events dropped, too many at once", sub_id, dropped);
                 }
+                SUB_COUNTERS.write().inc_batch(&sub_id, events);
+                show_new_notes(&sub_id);
             }
-        })
-    })
+            SubEvent::Closed { relay_url, reason } if is_auth_required(&reason) => {
+                tracing::warn!("{:?} closed by {}: {}", sub_id, relay_url, reason);
+                let reason = reason.trim_start_matches(AUTH_REQUIRED).trim().to_string();
+                MODAL_MANAGER.write().add_message(
+                    rsx! {
+                        AuthRequiredMsg {
+                            relay_url,
+                            reason
+                        }
+                    },
+                    AUTH_REQUIRED_MSG_ID.to_string(),
+                );
+                MODAL_MANAGER.write().open_modal(AUTH_REQUIRED_MSG_ID);
+            }
+            _ => {
+                tracing::info!("{:?}: {:?}", sub_id, sub_event);
+            }
+        }
+    }
 }
 #[component]
 pub fn NoteList(props: NoteListProps) -> Element {
@@ -113,17 +128,42 @@ pub fn NoteList(props: NoteListProps) -> Element {
             }
         });
     };
+    // newer notes, or the ones a previous call left between, in feed order
+    let handle_newer = move |_| {
+        spawn(async move {
+            if is_loading() {
+                return;
+            }
+            is_loading.set(true);
+            let mut paginator_write = paginator.write();
+            if let Some(paginator) = paginator_write.as_mut() {
+                if let Some(events) = paginator.newer_page().await {
+                    let mut list = notes.write();
+                    for event in events {
+                        if !list.iter().any(|note| note.id == event.id) {
+                            list.push(event);
+                        }
+                    }
+                    list.sort_by(|a, b| b.created_at().cmp(&a.created_at()));
+                }
+            }
+            is_loading.set(false);
+        });
+    };
     let handle_init = move || {
-        let handler = handle_sub_list.clone();
         spawn(async move {
             let sub_current = sub_current.read().clone();
             let filters = sub_current.get_filters();
             let clients = multiclient();
             let client_result = clients.get_or_create(&sub_current.relay_set).await;
+            let outbox = clients.outbox();
+            let routed = sub_current
+                .filters
+                .iter()
+                .any(|f| matches!(f, FilterTemp::Accounts(_)));
 
             match client_result {
                 Ok(hc) => {
-                    let client = hc.client();
                     {
                         is_loading.set(false);
                         tracing::info!("hello handle init");
@@ -131,22 +171,42 @@ pub fn NoteList(props: NoteListProps) -> Element {
 
                         if sub_current.live {
                             tracing::info!("sub_id: {:?}", sub_id.clone());
-                            sub_register
-                                .write()
-                                .add_subscription(
-                                    &client,
-                                    sub_id.clone(),
-                                    filters.clone(),
-                                    handler(Arc::new(RwLock::new(sub_id.clone()))),
-                                    None,
-                                )
-                                .await
-                                .unwrap();
+                            // account feeds follow each author to their write relays
+                            let mut fallback_filters = filters.clone();
+                            if routed {
+                                match outbox.subscribe(&hc, sub_id.clone(), filters.clone()).await {
+                                    Ok(rest) => fallback_filters = rest,
+                                    Err(e) => tracing::error!("outbox subscribe error: {:?}", e),
+                                }
+                            }
+                            let stream = if fallback_filters.is_empty() {
+                                sub_register.read().attach(&hc, sub_id.clone())
+                            } else {
+                                sub_register
+                                    .write()
+                                    .add_relay_set_subscription(
+                                        &hc,
+                                        sub_id.clone(),
+                                        fallback_filters,
+                                        None,
+                                    )
+                                    .await
+                                    .unwrap()
+                            };
+                            // one counter update per batch, not per event
+                            if let Err(e) = sub_register
+                                .read()
+                                .set_batch_options(&sub_id, BatchOptions::default())
+                            {
+                                tracing::error!("batching {:?} failed: {:?}", sub_id, e);
+                            }
+                            spawn(handle_sub_list(sub_id.clone(), stream));
+                            // returns at once when the pool is already followed
                             spawn({
-                                let client = client.clone();
+                                let hc = hc.clone();
                                 async move {
-                                    if !sub_register().get_sub_flag(&sub_id).await {
-                                        sub_register().handle_notifications(&client).await.unwrap();
+                                    if let Err(e) = sub_register().handle_notifications(&hc).await {
+                                        tracing::error!("notifications error: {:?}", e);
                                     }
                                 }
                             });
@@ -155,13 +215,16 @@ pub fn NoteList(props: NoteListProps) -> Element {
                         }
                     }
                     {
-                        let paginator_result = EventPaginator::new(
-                            client.clone(),
+                        let mut paginator_result = EventPaginator::new(
+                            hc.clone(),
                             filters.clone(),
                             None,
                             40,
                             sub_current.live,
                         );
+                        if routed {
+                            paginator_result = paginator_result.with_outbox(outbox.clone());
+                        }
                         paginator.set(Some(paginator_result));
                     }
                     {
@@ -172,7 +235,7 @@ pub fn NoteList(props: NoteListProps) -> Element {
 
                         if !sub_current.live {
                             let stored_events =
-                                client.database().query(filters.clone(), Order::Desc).await;
+                                hc.database().query(filters.clone(), Order::Desc).await;
                             match stored_events {
                                 Ok(events) => {
                                     notes.set(events);
@@ -267,6 +330,14 @@ pub fn NoteList(props: NoteListProps) -> Element {
                 },
                 div {
                     class: "note-more-mod-box",
+                    // live lists get new notes from their subscription
+                    if !sub_current().live {
+                        button {
+                            class: "btn-circle-false built-li radius-26 text-center font-size-14 mb-10",
+                            onclick: handle_newer,
+                            "Load newer"
+                        }
+                    }
                     div {
                         class: "note-more-mod-box",
                         for (i, note) in notes().clone().iter().enumerate() {
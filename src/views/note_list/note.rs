@@ -3,10 +3,11 @@ use dioxus::prelude::*;
 use nostr_sdk::{Event, JsonUtil, Kind};
 use crate::components::{
     icons::*,
-    Avatar
+    Avatar,
+    ZapModal,
 };
 use crate::init::MODAL_MANAGER;
-use crate::nostr::get_reactions;
+use crate::nostr::{get_reactions, get_zaps, ZapTarget};
 use crate::nostr::MultiClient;
 use crate::nostr::{ReplyTreeManager, TextNote};
 use crate::utils::format::format_note_content;
@@ -141,8 +142,7 @@ pub fn Note(props: NoteProps) -> Element {
                 let client_result = clients.get_or_create(&sub.relay_set).await;
                 match client_result {
                     Ok(hc) => {
-                        let client: std::sync::Arc<nostr_sdk::Client> = hc.client();
-                        match get_reactions(&client, &eid, None, is_tree).await {
+                        match get_reactions(&hc, &eid, None, is_tree).await {
                             Ok(reactions) => {
                                 if reactions.len() > 0 {
                                     reactions_maps.set(reactions);
@@ -161,6 +161,38 @@ pub fn Note(props: NoteProps) -> Element {
         },
     ));
 
+    //loading zaps
+    let mut zap_sats = use_signal(|| 0u64);
+    use_effect(use_reactive(
+        (&props.is_tree, &props.sub_name, &props.event.id),
+        move |(is_tree, sub_name, eid)| {
+            spawn(async move {
+                let _subs_map: HashMap<String, CustomSub> = subs_map();
+                if !_subs_map.contains_key(&sub_name) {
+                    return;
+                }
+                let sub = _subs_map.get(&sub_name).unwrap();
+                let clients = multiclient();
+                let client_result = clients.get_or_create(&sub.relay_set).await;
+                match client_result {
+                    Ok(hc) => {
+                        match get_zaps(&hc, ZapTarget::Event(eid), None, is_tree).await {
+                            Ok(summary) => {
+                                zap_sats.set(summary.total_sats());
+                            }
+                            Err(e) => {
+                                tracing::error!("get zaps error: {:?}", e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("zaps client Error: {:?}", e);
+                    }
+                }
+            });
+        },
+    ));
+
     let nav = navigator();
     let handle_nav = move |route: Route| {
         nav.push(route);
@@ -273,10 +305,28 @@ pub fn Note(props: NoteProps) -> Element {
                     }
                     div {
                         class: "note-action-item cursor-pointer flex items-center",
+                        onclick: move |_| {
+                            let modal_id = format!("zap-{}", event().id().to_hex());
+                            MODAL_MANAGER.write().add_modal(rsx! {
+                                ZapModal {
+                                    id: modal_id.clone(),
+                                    recipient: event().pubkey,
+                                    event_id: Some(event().id()),
+                                    relay_name: relay_name(),
+                                }
+                            }, modal_id.clone());
+                            MODAL_MANAGER.write().open_modal(&modal_id);
+                        },
                         span {
                             class: "note-action-icon",
                             dangerous_inner_html: "{ZAP}"
                         }
+                        if zap_sats() > 0 {
+                            span {
+                                class: "note-action-count",
+                                {zap_sats.to_string()}
+                            }
+                        }
                     }
 
                     //split div
@@ -41,7 +41,7 @@ pub fn Reply(props: ReplyProps) -> Element {
         spawn(async move {
             let clients = multiclient();
             if let Some(client) = clients.get_client(&relay_name).await {
-                match get_event_by_id(&client.client(), &eventid, None).await {
+                match get_event_by_id(&client, &eventid, None).await {
                     Ok(root) => {
                         if let Some(root_event) = root {
                             root_rsx.set(rsx! {
@@ -6,6 +6,7 @@ use dioxus::prelude::*;
 use nostr_sdk::prelude::*;
 
 use crate::utils::format::{format_create_at, format_public_key, splite_by_replys};
+use crate::Route;
 // {
 //     "id": "eb8142a456387a0f593273b808290b29765a1958700f94bcc6c1ff0cef7fa4b0",
 //     "pubkey": "fcab5a7bee61b9d16f36ef9c5801227cdc500c746b9ab501e808685e0eddb9f7",
@@ -63,6 +64,21 @@ pub fn Settings() -> Element {
                 get_events()
             },
             class:"settings-box-style",
+            Link {
+                class: "nav-item",
+                to: Route::WalletSettings {},
+                "Wallet connections"
+            }
+            Link {
+                class: "nav-item",
+                to: Route::SignerLogin {},
+                "Signer login"
+            }
+            Link {
+                class: "nav-item",
+                to: Route::MnemonicAccount {},
+                "Create or recover account"
+            }
             for event in data.iter() {
                 EventItem { event: event.clone() }
             }
@@ -0,0 +1,166 @@
+use dioxus::prelude::*;
+
+use crate::account::MIN_PASSPHRASE_LEN;
+use crate::components::icons::LOADING;
+use crate::init::{LAST_LOGINED_KEY, NOT_LOGGED_IN_USER_NAME};
+use crate::nostr::Wallet;
+use crate::store::wallet::WalletConnection;
+use crate::store::CBWebDatabase;
+use crate::utils::js::alert;
+
+#[component]
+pub fn WalletSettings() -> Element {
+    let cb_database_db = use_context::<Signal<CBWebDatabase>>();
+    let mut user_name = use_signal(|| NOT_LOGGED_IN_USER_NAME.to_string());
+    let mut wallets = use_signal(Vec::<WalletConnection>::new);
+    let mut name = use_signal(String::new);
+    let mut uri = use_signal(String::new);
+    let mut passphrase = use_signal(String::new);
+    let mut status = use_signal(String::new);
+    let mut is_loading = use_signal(|| false);
+
+    let mut reload = move || {
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            if let Ok(Some(name)) = db.get_misc(LAST_LOGINED_KEY.to_string()).await {
+                user_name.set(name);
+            }
+            match db.get_wallets(user_name()).await {
+                Ok(list) => wallets.set(list),
+                Err(e) => tracing::error!("get wallets error: {:?}", e),
+            }
+        });
+    };
+
+    use_effect(move || {
+        reload();
+    });
+
+    let handle_add = move |_| {
+        if name().is_empty() {
+            spawn(async move {
+                alert("Wallet name cannot be empty".to_string()).await;
+            });
+            return;
+        }
+        let connection = match Wallet::seal(&user_name(), &name(), &uri(), &passphrase()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                spawn(async move {
+                    alert(format!("Invalid wallet connection: {}", e)).await;
+                });
+                return;
+            }
+        };
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            match db.add_wallet(connection).await {
+                Ok(_) => {
+                    name.set(String::new());
+                    uri.set(String::new());
+                    reload();
+                }
+                Err(e) => alert(format!("Save wallet failed: {}", e)).await,
+            }
+        });
+    };
+
+    let handle_test = move |connection: WalletConnection| {
+        spawn(async move {
+            is_loading.set(true);
+            let result = match Wallet::unlock(&connection, &passphrase()) {
+                Ok(wallet) => wallet.get_balance().await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(balance) => status.set(format!(
+                    "{}: connected, balance {} sats",
+                    connection.name,
+                    balance / 1000
+                )),
+                Err(e) => status.set(format!("{}: {}", connection.name, e)),
+            }
+            is_loading.set(false);
+        });
+    };
+
+    let handle_remove = move |id: String| {
+        spawn(async move {
+            let db = cb_database_db.read().clone();
+            match db.remove_wallet(id).await {
+                Ok(_) => reload(),
+                Err(e) => tracing::error!("remove wallet error: {:?}", e),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            class: "settings-box-style",
+            h1 { class: "font-size-16 mb-15", "Wallet connections" }
+            for wallet in wallets() {
+                div {
+                    class: "relay-url-item mb-10 flex items-center",
+                    span { class: "mr-10 text-overflow", "{wallet.name}" }
+                    button {
+                        class: "btn-circle-true built-li radius-26 text-center font-size-14 mr-8",
+                        onclick: {
+                            let wallet = wallet.clone();
+                            move |_| handle_test(wallet.clone())
+                        },
+                        "Test"
+                    }
+                    button {
+                        class: "btn-circle-false built-li radius-26 text-center font-size-14",
+                        onclick: {
+                            let id = wallet.id.clone();
+                            move |_| handle_remove(id.clone())
+                        },
+                        "Remove"
+                    }
+                }
+            }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "text",
+                    placeholder: "Name",
+                    value: "{name}",
+                    oninput: move |event| name.set(event.value()),
+                }
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "text",
+                    placeholder: "nostr+walletconnect://",
+                    value: "{uri}",
+                    oninput: move |event| uri.set(event.value()),
+                }
+            }
+            div {
+                class: "relay-url-item mb-10 flex items-center",
+                input {
+                    class: "relay-ipt mr-10",
+                    r#type: "password",
+                    placeholder: "Passphrase, {MIN_PASSPHRASE_LEN}+ characters",
+                    value: "{passphrase}",
+                    oninput: move |event| passphrase.set(event.value()),
+                }
+                button {
+                    class: "btn-circle-true built-li radius-26 text-center font-size-14",
+                    onclick: handle_add,
+                    "Add"
+                }
+            }
+            if is_loading() {
+                div {
+                    class: "laoding-box",
+                    dangerous_inner_html: "{LOADING}"
+                }
+            }
+            if !status().is_empty() {
+                div { class: "font-size-14", "{status}" }
+            }
+        }
+    }
+}